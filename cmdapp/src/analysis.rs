@@ -0,0 +1,246 @@
+use visioncortex::ColorImage;
+
+/// Pixels with an alpha below this are treated as transparent and left out of the statistics
+const OPAQUE_ALPHA: u8 = 128;
+/// Luma difference between neighbouring pixels that counts as an edge
+const EDGE_THRESHOLD: i32 = 32;
+/// Largest difference between the channels of a pixel that still counts as gray
+const GRAY_TOLERANCE: u8 = 8;
+/// Number of most frequent colors whose coverage measures how peaked the histogram is
+const TOP_COLORS: usize = 16;
+/// Fewest blocks along each side for an image to count as upscaled pixel art
+const MIN_PIXEL_BLOCKS: usize = 4;
+
+/// Statistics of an image's content, from which [`Config::auto`](crate::Config::auto)
+/// chooses the conversion settings
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImageAnalysis {
+    /// Number of distinct colors among the opaque pixels
+    pub distinct_colors: usize,
+    /// Fraction of the opaque pixels covered by the 16 most frequent colors.
+    /// Close to 1 for flat graphics, low for photos.
+    pub top_colors_coverage: f64,
+    /// Fraction of neighbouring opaque pixel pairs whose luma differs noticeably
+    pub edge_density: f64,
+    /// Whether any pixel is not fully opaque
+    pub has_alpha: bool,
+    /// Whether every opaque pixel is a shade of gray
+    pub is_grayscale: bool,
+    /// Fraction of the opaque pixels that are close to black or white
+    pub black_and_white_coverage: f64,
+    /// The side of the square blocks of equal color the image is made of;
+    /// more than 1 for pixel art upscaled by an integer factor
+    pub pixel_scale: usize,
+}
+
+impl ImageAnalysis {
+    pub fn new(img: &ColorImage) -> Self {
+        let pixel = |x: usize, y: usize| {
+            let i = 4 * (y * img.width + x);
+            &img.pixels[i..i + 4]
+        };
+        let luma = |p: &[u8]| (299 * p[0] as i32 + 587 * p[1] as i32 + 114 * p[2] as i32) / 1000;
+
+        let mut colors: Vec<u32> = Vec::with_capacity(img.width * img.height);
+        let mut opaque = 0;
+        let mut has_alpha = false;
+        let mut is_grayscale = true;
+        let mut black_or_white = 0;
+        for p in img.pixels.chunks_exact(4) {
+            has_alpha |= p[3] < 255;
+            if p[3] < OPAQUE_ALPHA {
+                continue;
+            }
+            opaque += 1;
+            colors.push(u32::from_be_bytes([0, p[0], p[1], p[2]]));
+            let (min, max) = (p[0].min(p[1]).min(p[2]), p[0].max(p[1]).max(p[2]));
+            is_grayscale &= max - min <= GRAY_TOLERANCE;
+            let luma = luma(p);
+            if !(48..=208).contains(&luma) {
+                black_or_white += 1;
+            }
+        }
+
+        // sorting groups equal colors together, which is faster than hashing every pixel
+        colors.sort_unstable();
+        let mut counts: Vec<usize> = colors
+            .chunk_by(|a, b| a == b)
+            .map(|run| run.len())
+            .collect();
+        counts.sort_unstable_by(|a, b| b.cmp(a));
+        let top: usize = counts.iter().take(TOP_COLORS).sum();
+
+        let mut pairs = 0;
+        let mut edges = 0;
+        for y in 0..img.height {
+            for x in 0..img.width {
+                let p = pixel(x, y);
+                if p[3] < OPAQUE_ALPHA {
+                    continue;
+                }
+                let neighbours = [(x + 1, y), (x, y + 1)];
+                for (nx, ny) in neighbours {
+                    if nx >= img.width || ny >= img.height {
+                        continue;
+                    }
+                    let q = pixel(nx, ny);
+                    if q[3] < OPAQUE_ALPHA {
+                        continue;
+                    }
+                    pairs += 1;
+                    if (luma(p) - luma(q)).abs() > EDGE_THRESHOLD {
+                        edges += 1;
+                    }
+                }
+            }
+        }
+
+        let fraction = |count: usize, total: usize| {
+            if total == 0 {
+                0.0
+            } else {
+                count as f64 / total as f64
+            }
+        };
+        Self {
+            distinct_colors: counts.len(),
+            top_colors_coverage: fraction(top, opaque),
+            edge_density: fraction(edges, pairs),
+            has_alpha,
+            is_grayscale,
+            black_and_white_coverage: fraction(black_or_white, opaque),
+            pixel_scale: pixel_scale(img),
+        }
+    }
+}
+
+/// The greatest common divisor of the lengths of all runs of equal pixels along rows and
+/// columns, which is the upscaling factor of pixel art and 1 for anything else.
+/// Runs spanning a whole row or column say nothing about the block size and are ignored,
+/// and a scale leaving fewer than `MIN_PIXEL_BLOCKS` blocks along a side is not trusted.
+pub(crate) fn pixel_scale(img: &ColorImage) -> usize {
+    let same = |a: usize, b: usize| {
+        let (p, q) = (&img.pixels[4 * a..4 * a + 4], &img.pixels[4 * b..4 * b + 4]);
+        // the color of fully transparent pixels is invisible, and often arbitrary
+        p == q || (p[3] == 0 && q[3] == 0)
+    };
+    let mut scale = 0;
+    let mut add_run = |run: usize, length: usize| {
+        if run < length {
+            scale = gcd(scale, run);
+        }
+        scale != 1
+    };
+
+    for y in 0..img.height {
+        let mut run = 1;
+        for x in 1..img.width {
+            let i = y * img.width + x;
+            if same(i, i - 1) {
+                run += 1;
+            } else {
+                if !add_run(run, img.width) {
+                    return 1;
+                }
+                run = 1;
+            }
+        }
+        if !add_run(run, img.width) {
+            return 1;
+        }
+    }
+    for x in 0..img.width {
+        let mut run = 1;
+        for y in 1..img.height {
+            let i = y * img.width + x;
+            if same(i, i - img.width) {
+                run += 1;
+            } else {
+                if !add_run(run, img.height) {
+                    return 1;
+                }
+                run = 1;
+            }
+        }
+        if !add_run(run, img.height) {
+            return 1;
+        }
+    }
+    if scale == 0 || img.width / scale < MIN_PIXEL_BLOCKS || img.height / scale < MIN_PIXEL_BLOCKS {
+        return 1;
+    }
+    scale
+}
+
+fn gcd(a: usize, b: usize) -> usize {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use visioncortex::Color;
+
+    use super::*;
+
+    /// A `width` by `height` image of `scale` sized blocks, colored by `block(x, y)`
+    fn blocks(
+        width: usize,
+        height: usize,
+        scale: usize,
+        block: impl Fn(usize, usize) -> Color,
+    ) -> ColorImage {
+        let mut image = ColorImage::new_w_h(width, height);
+        for y in 0..height {
+            for x in 0..width {
+                image.set_pixel(x, y, &block(x / scale, y / scale));
+            }
+        }
+        image
+    }
+
+    fn checker(x: usize, y: usize) -> Color {
+        if (x + y).is_multiple_of(2) {
+            Color::new(0, 0, 0)
+        } else {
+            Color::new(255, 255, 255)
+        }
+    }
+
+    #[test]
+    fn solid_image_is_not_pixel_art() {
+        let image = blocks(64, 64, 1, |_, _| Color::new(40, 80, 120));
+        assert_eq!(pixel_scale(&image), 1);
+    }
+
+    #[test]
+    fn few_large_blocks_are_not_pixel_art() {
+        let image = blocks(64, 64, 32, checker);
+        assert_eq!(pixel_scale(&image), 1);
+    }
+
+    #[test]
+    fn upscaled_pixel_art() {
+        assert_eq!(pixel_scale(&blocks(64, 64, 4, checker)), 4);
+        // a background band across the whole image does not hide the scale
+        let image = blocks(48, 63, 3, |x, y| {
+            if y < 4 {
+                Color::new(0, 0, 255)
+            } else {
+                checker(x, y)
+            }
+        });
+        assert_eq!(pixel_scale(&image), 3);
+    }
+
+    #[test]
+    fn photo_like_image_is_not_pixel_art() {
+        let image = blocks(32, 32, 1, |x, y| {
+            Color::new((x * 7) as u8, (y * 5) as u8, 0)
+        });
+        assert_eq!(pixel_scale(&image), 1);
+    }
+}
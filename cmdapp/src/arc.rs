@@ -0,0 +1,394 @@
+use std::f64::consts::{FRAC_PI_2, PI};
+use std::fmt::Write;
+
+use visioncortex::{CompoundPathElement, NumberFormat, PointF64, Spline};
+
+use super::centerline::distance_to_segment;
+use super::rasterizer::distance;
+use super::svg::SvgFile;
+
+/// Points sampled along each cubic curve to measure a fit against
+const SAMPLES_PER_CURVE: usize = 8;
+/// An arc must turn by less than this, so that its end stays apart from its start
+const MAX_ARC_ANGLE: f64 = 1.9 * PI;
+
+/// A piece of an [`ArcPath`], ending at `to`
+#[derive(Debug, Clone, PartialEq)]
+pub enum Segment {
+    Line {
+        to: PointF64,
+    },
+    Cubic {
+        c1: PointF64,
+        c2: PointF64,
+        to: PointF64,
+    },
+    /// A circular arc about `center`, turning by `angle` radians (clockwise on screen if positive)
+    Arc {
+        center: PointF64,
+        angle: f64,
+        to: PointF64,
+    },
+}
+
+/// A spline with the runs of curves that a straight line or a circular arc fits replaced by it
+#[derive(Debug, Clone, PartialEq)]
+pub struct ArcPath {
+    pub start: PointF64,
+    pub segments: Vec<Segment>,
+}
+
+impl Segment {
+    fn to(&self) -> PointF64 {
+        match *self {
+            Self::Line { to } | Self::Cubic { to, .. } | Self::Arc { to, .. } => to,
+        }
+    }
+}
+
+impl ArcPath {
+    /// Enlarge the path by `factor` about the origin
+    pub(crate) fn scale(&mut self, factor: f64) {
+        let scale = |p: &mut PointF64| {
+            p.x *= factor;
+            p.y *= factor;
+        };
+        scale(&mut self.start);
+        for segment in self.segments.iter_mut() {
+            match segment {
+                Segment::Line { to } => scale(to),
+                Segment::Cubic { c1, c2, to } => {
+                    scale(c1);
+                    scale(c2);
+                    scale(to);
+                }
+                Segment::Arc { center, to, .. } => {
+                    scale(center);
+                    scale(to);
+                }
+            }
+        }
+    }
+
+    /// Move the path by `offset`
+    pub(crate) fn translate(&mut self, offset: PointF64) {
+        let translate = |p: &mut PointF64| *p = PointF64::new(p.x + offset.x, p.y + offset.y);
+        translate(&mut self.start);
+        for segment in self.segments.iter_mut() {
+            match segment {
+                Segment::Line { to } => translate(to),
+                Segment::Cubic { c1, c2, to } => {
+                    translate(c1);
+                    translate(c2);
+                    translate(to);
+                }
+                Segment::Arc { center, to, .. } => {
+                    translate(center);
+                    translate(to);
+                }
+            }
+        }
+    }
+
+    /// The path as a spline, with lines as straight curves and arcs as curves of at most a
+    /// quarter turn each
+    pub(crate) fn to_spline(&self) -> Spline {
+        let mut spline = Spline::new(self.start);
+        let mut from = self.start;
+        for segment in self.segments.iter() {
+            match *segment {
+                Segment::Line { to } => spline.add(from, to, to),
+                Segment::Cubic { c1, c2, to } => spline.add(c1, c2, to),
+                Segment::Arc { center, angle, to } => {
+                    let radius = distance(center, from);
+                    let start = (from.y - center.y).atan2(from.x - center.x);
+                    let pieces = (angle.abs() / FRAC_PI_2).ceil().max(1.0) as usize;
+                    let step = angle / pieces as f64;
+                    // the length of the control arms of a cubic curve approximating an arc
+                    let arm = 4.0 / 3.0 * (step / 4.0).tan() * radius;
+                    for piece in 0..pieces {
+                        let (a, b) = (
+                            start + step * piece as f64,
+                            start + step * (piece + 1) as f64,
+                        );
+                        let end = if piece + 1 == pieces {
+                            to
+                        } else {
+                            PointF64::new(center.x + radius * b.cos(), center.y + radius * b.sin())
+                        };
+                        let begin =
+                            PointF64::new(center.x + radius * a.cos(), center.y + radius * a.sin());
+                        spline.add(
+                            PointF64::new(begin.x - arm * a.sin(), begin.y + arm * a.cos()),
+                            PointF64::new(end.x + arm * b.sin(), end.y - arm * b.cos()),
+                            end,
+                        );
+                    }
+                }
+            }
+            from = segment.to();
+        }
+        spline
+    }
+
+    /// Path data relative to `origin`, with `Z` at the end if `close`
+    pub(crate) fn to_svg_string(
+        &self,
+        close: bool,
+        origin: PointF64,
+        precision: Option<u32>,
+    ) -> String {
+        let num = |x: f64| f64::number_format(x, precision);
+        let point = |p: PointF64| format!("{} {}", num(p.x - origin.x), num(p.y - origin.y));
+        let mut string = format!("M{} ", point(self.start));
+        let mut from = self.start;
+        for segment in self.segments.iter() {
+            match *segment {
+                Segment::Line { to } => write!(string, "L{} ", point(to)),
+                Segment::Cubic { c1, c2, to } => {
+                    write!(string, "C{} {} {} ", point(c1), point(c2), point(to))
+                }
+                Segment::Arc { center, angle, to } => {
+                    let radius = num(distance(center, from));
+                    write!(
+                        string,
+                        "A{} {} 0 {} {} {} ",
+                        radius,
+                        radius,
+                        (angle.abs() > PI) as u8,
+                        (angle > 0.0) as u8,
+                        point(to)
+                    )
+                }
+            }
+            .unwrap();
+            from = segment.to();
+        }
+        if close {
+            string.push_str("Z ");
+        }
+        string
+    }
+}
+
+/// Fit straight lines and circular arcs within `tolerance` pixels to the splines of `svg`, for
+/// the paths traced as splines only
+pub(crate) fn fit_arcs(svg: &mut SvgFile, tolerance: f64) {
+    for path in svg.paths.iter_mut() {
+        if path.primitive.is_some() {
+            continue;
+        }
+        let arcs: Option<Vec<ArcPath>> = path
+            .path
+            .iter()
+            .map(|element| match element {
+                CompoundPathElement::Spline(spline) if !spline.is_empty() => {
+                    Some(fit_spline(&spline.points, tolerance))
+                }
+                _ => None,
+            })
+            .collect();
+        if let Some(arcs) = arcs.filter(|arcs| !arcs.is_empty()) {
+            path.arcs = Some(arcs);
+        }
+    }
+}
+
+/// Replace the longest runs of curves from the start on that a line or an arc fits
+fn fit_spline(points: &[PointF64], tolerance: f64) -> ArcPath {
+    let curves: Vec<[PointF64; 4]> = points
+        .windows(4)
+        .step_by(3)
+        .map(|curve| [curve[0], curve[1], curve[2], curve[3]])
+        .collect();
+    let mut segments = vec![];
+    let mut first = 0;
+    while first < curves.len() {
+        let mut samples = vec![curves[first][0]];
+        let mut best = None;
+        for (last, curve) in curves.iter().enumerate().skip(first) {
+            samples.extend(sample_curve(curve));
+            match fit_segment(&samples, tolerance) {
+                Some(segment) => best = Some((last, segment)),
+                None => break,
+            }
+        }
+        match best {
+            Some((last, segment)) => {
+                segments.push(segment);
+                first = last + 1;
+            }
+            None => {
+                let [_, c1, c2, to] = curves[first];
+                segments.push(Segment::Cubic { c1, c2, to });
+                first += 1;
+            }
+        }
+    }
+    ArcPath {
+        start: points[0],
+        segments,
+    }
+}
+
+/// Points along a cubic curve after its start, ending on its end
+fn sample_curve(curve: &[PointF64; 4]) -> impl Iterator<Item = PointF64> + '_ {
+    (1..=SAMPLES_PER_CURVE).map(move |step| {
+        let t = step as f64 / SAMPLES_PER_CURVE as f64;
+        let u = 1.0 - t;
+        let (a, b, c, d) = (u * u * u, 3.0 * u * u * t, 3.0 * u * t * t, t * t * t);
+        PointF64::new(
+            a * curve[0].x + b * curve[1].x + c * curve[2].x + d * curve[3].x,
+            a * curve[0].y + b * curve[1].y + c * curve[2].y + d * curve[3].y,
+        )
+    })
+}
+
+/// A line, or failing that an arc, from the first to the last of `samples` that passes within
+/// `tolerance` of all of them
+fn fit_segment(samples: &[PointF64], tolerance: f64) -> Option<Segment> {
+    let (from, to) = (samples[0], samples[samples.len() - 1]);
+    let chord = distance(from, to);
+    if chord <= tolerance {
+        return None;
+    }
+    if samples
+        .iter()
+        .all(|&p| distance_to_segment(p, from, to) <= tolerance)
+    {
+        return Some(Segment::Line { to });
+    }
+
+    // the center lies on the perpendicular bisector of the chord, at the offset that best solves
+    // |p - center|² = radius² for the samples in the least squares sense
+    let middle = PointF64::new((from.x + to.x) / 2.0, (from.y + to.y) / 2.0);
+    let normal = PointF64::new(-(to.y - from.y) / chord, (to.x - from.x) / chord);
+    let half_chord = chord / 2.0;
+    let (mut numerator, mut denominator) = (0.0, 0.0);
+    for p in samples.iter() {
+        let (dx, dy) = (p.x - middle.x, p.y - middle.y);
+        let w = 2.0 * (normal.x * dx + normal.y * dy);
+        let b = dx * dx + dy * dy - half_chord * half_chord;
+        numerator += w * b;
+        denominator += w * w;
+    }
+    if denominator == 0.0 {
+        return None;
+    }
+    let offset = numerator / denominator;
+    let center = PointF64::new(middle.x + offset * normal.x, middle.y + offset * normal.y);
+    let radius = distance(center, from);
+
+    // the samples must stay on the circle and go around it one way only
+    let mut angle = 0.0;
+    let mut previous = (from.y - center.y).atan2(from.x - center.x);
+    for &p in samples.iter() {
+        if (distance(center, p) - radius).abs() > tolerance {
+            return None;
+        }
+        let current = (p.y - center.y).atan2(p.x - center.x);
+        let mut step = current - previous;
+        if step > PI {
+            step -= 2.0 * PI;
+        } else if step < -PI {
+            step += 2.0 * PI;
+        }
+        if step * angle < 0.0 {
+            return None;
+        }
+        angle += step;
+        previous = current;
+    }
+    if angle == 0.0 || angle.abs() > MAX_ARC_ANGLE {
+        return None;
+    }
+    Some(Segment::Arc { center, angle, to })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A spline of two cubic curves along the circle of radius 10 about (20, 20), from the angle
+    /// `from` turning by `sweep` radians
+    fn quarter_circle(from: f64, sweep: f64) -> Vec<PointF64> {
+        let (center, radius) = (PointF64::new(20.0, 20.0), 10.0);
+        let at = |angle: f64| {
+            PointF64::new(
+                center.x + radius * angle.cos(),
+                center.y + radius * angle.sin(),
+            )
+        };
+        let tangent =
+            |angle: f64, length: f64| PointF64::new(-angle.sin() * length, angle.cos() * length);
+        let step = sweep / 2.0;
+        // the control distance of a cubic approximating an arc of `step` radians
+        let k = 4.0 / 3.0 * (step / 4.0).tan() * radius;
+        let mut points = vec![at(from)];
+        for i in 0..2 {
+            let (a, b) = (from + step * i as f64, from + step * (i + 1) as f64);
+            let (p, q) = (at(a), at(b));
+            let (ta, tb) = (tangent(a, k), tangent(b, k));
+            points.push(PointF64::new(p.x + ta.x, p.y + ta.y));
+            points.push(PointF64::new(q.x - tb.x, q.y - tb.y));
+            points.push(q);
+        }
+        points
+    }
+
+    #[test]
+    fn quarter_circle_is_one_arc() {
+        let path = fit_spline(&quarter_circle(0.0, FRAC_PI_2), 0.1);
+        match path.segments[..] {
+            [Segment::Arc { center, angle, to }] => {
+                assert!(
+                    distance(center, PointF64::new(20.0, 20.0)) < 0.05,
+                    "{:?}",
+                    center
+                );
+                assert!((angle - FRAC_PI_2).abs() < 0.01, "{}", angle);
+                assert!(distance(to, PointF64::new(20.0, 30.0)) < 1e-9, "{:?}", to);
+            }
+            ref other => panic!("expected an arc, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn counterclockwise_arc_turns_negatively() {
+        let path = fit_spline(&quarter_circle(PI, -FRAC_PI_2), 0.1);
+        match path.segments[..] {
+            [Segment::Arc { angle, .. }] => {
+                assert!((angle + FRAC_PI_2).abs() < 0.01, "{}", angle)
+            }
+            ref other => panic!("expected an arc, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn straight_curve_is_a_line() {
+        let points = [
+            PointF64::new(0.0, 0.0),
+            PointF64::new(3.0, 1.0),
+            PointF64::new(6.0, 2.0),
+            PointF64::new(9.0, 3.0),
+        ];
+        assert_eq!(
+            fit_spline(&points, 0.1).segments,
+            [Segment::Line { to: points[3] }]
+        );
+    }
+
+    #[test]
+    fn wavy_curve_is_kept() {
+        let points = [
+            PointF64::new(0.0, 0.0),
+            PointF64::new(3.0, 6.0),
+            PointF64::new(6.0, -6.0),
+            PointF64::new(9.0, 0.0),
+        ];
+        assert!(matches!(
+            fit_spline(&points, 0.1).segments[..],
+            [Segment::Cubic { .. }]
+        ));
+    }
+}
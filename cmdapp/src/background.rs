@@ -0,0 +1,241 @@
+use std::fmt;
+use std::str::FromStr;
+
+use visioncortex::{Color, ColorImage};
+
+use super::palette::parse_hex;
+
+/// The background keyed out of the image by [`Config::background`](crate::Config::background)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Background {
+    /// The color most corners of the image agree on, removed where it reaches in from them.
+    /// Nothing is removed unless at least two corners agree.
+    Auto,
+    /// This color, removed wherever it is
+    Color(Color),
+}
+
+impl FromStr for Background {
+    type Err = String;
+
+    /// Parse `auto` or a hex color like `#00ff00`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(Self::Auto),
+            hex => parse_hex(hex)
+                .map(Self::Color)
+                .map_err(|_| format!("unknown Background {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for Background {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Auto => write!(f, "auto"),
+            Self::Color(color) => write!(f, "{}", color.to_hex_string()),
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for Background {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Background {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let background = String::deserialize(deserializer)?;
+        Self::from_str(&background).map_err(serde::de::Error::custom)
+    }
+}
+
+/// Make the `background` pixels of `img` fully transparent, those differing from its color by at
+/// most `tolerance` in every channel. Returns whether any were.
+pub(crate) fn remove_background(
+    img: &mut ColorImage,
+    background: Background,
+    tolerance: u8,
+) -> bool {
+    if img.width == 0 || img.height == 0 {
+        return false;
+    }
+    let matches = |a: &Color, b: &Color| {
+        a.r.abs_diff(b.r) <= tolerance
+            && a.g.abs_diff(b.g) <= tolerance
+            && a.b.abs_diff(b.b) <= tolerance
+    };
+    let mut removed = false;
+    match background {
+        Background::Color(color) => {
+            for pixel in img.pixels.chunks_exact_mut(4) {
+                if pixel[3] > 0 && matches(&Color::new(pixel[0], pixel[1], pixel[2]), &color) {
+                    pixel[3] = 0;
+                    removed = true;
+                }
+            }
+        }
+        Background::Auto => {
+            let (width, height) = (img.width, img.height);
+            let corners: Vec<(usize, usize)> = [
+                (0, 0),
+                (width - 1, 0),
+                (0, height - 1),
+                (width - 1, height - 1),
+            ]
+            .into_iter()
+            .filter(|&(x, y)| img.get_pixel(x, y).a > 0)
+            .collect();
+            // the corner color the most other corners match, the first of those tied, unless no
+            // two corners agree on a backdrop
+            let color = match corners
+                .iter()
+                .map(|&(x, y)| {
+                    let color = img.get_pixel(x, y);
+                    let agreeing = corners
+                        .iter()
+                        .filter(|&&(x, y)| matches(&img.get_pixel(x, y), &color))
+                        .count();
+                    (color, agreeing)
+                })
+                .rev()
+                .max_by_key(|&(_, agreeing)| agreeing)
+            {
+                Some((color, agreeing)) if agreeing >= 2 => color,
+                _ => return false,
+            };
+
+            // flood fill from the matching corners, through background already transparent
+            let mut seen = vec![false; width * height];
+            let mut stack: Vec<(usize, usize)> = corners
+                .into_iter()
+                .filter(|&(x, y)| matches(&img.get_pixel(x, y), &color))
+                .collect();
+            for &(x, y) in stack.iter() {
+                seen[y * width + x] = true;
+            }
+            while let Some((x, y)) = stack.pop() {
+                let pixel = img.get_pixel(x, y);
+                if pixel.a > 0 {
+                    img.set_pixel(x, y, &Color::new_rgba(pixel.r, pixel.g, pixel.b, 0));
+                    removed = true;
+                }
+                let neighbours = [
+                    (x > 0).then(|| (x - 1, y)),
+                    (x + 1 < width).then(|| (x + 1, y)),
+                    (y > 0).then(|| (x, y - 1)),
+                    (y + 1 < height).then(|| (x, y + 1)),
+                ];
+                for (x, y) in neighbours.into_iter().flatten() {
+                    let neighbour = img.get_pixel(x, y);
+                    if !seen[y * width + x] && (neighbour.a == 0 || matches(&neighbour, &color)) {
+                        seen[y * width + x] = true;
+                        stack.push((x, y));
+                    }
+                }
+            }
+        }
+    }
+    removed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WHITE: Color = Color {
+        r: 255,
+        g: 255,
+        b: 255,
+        a: 255,
+    };
+
+    /// A white 12 by 12 image with a red ring of 3 to 8 and a white middle, and `corners` colored
+    fn ring(corners: [Color; 4]) -> ColorImage {
+        let mut image = ColorImage::new_w_h(12, 12);
+        for y in 0..12 {
+            for x in 0..12 {
+                let color = if (3..=8).contains(&x)
+                    && (3..=8).contains(&y)
+                    && !((5..=6).contains(&x) && (5..=6).contains(&y))
+                {
+                    Color::new(200, 0, 0)
+                } else {
+                    WHITE
+                };
+                image.set_pixel(x, y, &color);
+            }
+        }
+        for (&(x, y), color) in [(0, 0), (11, 0), (0, 11), (11, 11)].iter().zip(corners) {
+            image.set_pixel(x, y, &color);
+        }
+        image
+    }
+
+    fn transparent(image: &ColorImage) -> Vec<(usize, usize)> {
+        (0..image.height)
+            .flat_map(|y| (0..image.width).map(move |x| (x, y)))
+            .filter(|&(x, y)| image.get_pixel(x, y).a == 0)
+            .collect()
+    }
+
+    #[test]
+    fn auto_removes_the_backdrop_around_the_subject() {
+        let mut image = ring([WHITE; 4]);
+        assert!(remove_background(&mut image, Background::Auto, 0));
+        let removed = transparent(&image);
+        // everything outside the ring, none of it or the white enclosed by it
+        assert_eq!(removed.len(), 12 * 12 - 6 * 6);
+        assert!(removed
+            .iter()
+            .all(|&(x, y)| !(3..=8).contains(&x) || !(3..=8).contains(&y)));
+        assert_eq!(image.get_pixel(5, 5).a, 255);
+        assert_eq!(image.get_pixel(3, 3).a, 255);
+    }
+
+    #[test]
+    fn auto_tolerates_a_noisy_backdrop() {
+        let mut image = ring([WHITE; 4]);
+        image.set_pixel(1, 1, &Color::new(250, 245, 255));
+        assert!(remove_background(&mut image, Background::Auto, 10));
+        assert_eq!(image.get_pixel(1, 1).a, 0);
+        assert_eq!(transparent(&image).len(), 12 * 12 - 6 * 6);
+    }
+
+    #[test]
+    fn color_is_removed_everywhere() {
+        let mut image = ring([WHITE; 4]);
+        image.set_pixel(2, 2, &Color::new(190, 10, 5));
+        let background = Background::from_str("#c80000").unwrap();
+        assert!(remove_background(&mut image, background, 12));
+        // the ring and the matching stray pixel, but no white
+        let removed = transparent(&image);
+        assert_eq!(removed.len(), 6 * 6 - 2 * 2 + 1);
+        assert!(removed.contains(&(2, 2)));
+        assert_eq!(image.get_pixel(5, 5).a, 255);
+
+        // without tolerance, only the exact color
+        let mut image = ring([WHITE; 4]);
+        image.set_pixel(2, 2, &Color::new(190, 10, 5));
+        assert!(remove_background(&mut image, background, 0));
+        assert_eq!(transparent(&image).len(), 6 * 6 - 2 * 2);
+        assert_eq!(image.get_pixel(2, 2).a, 255);
+    }
+
+    #[test]
+    fn disagreeing_corners_leave_the_image_alone() {
+        let corners = [
+            Color::new(255, 0, 0),
+            Color::new(0, 255, 0),
+            Color::new(0, 0, 255),
+            Color::new(0, 0, 0),
+        ];
+        let mut image = ring(corners);
+        let original = image.pixels.clone();
+        assert!(!remove_background(&mut image, Background::Auto, 24));
+        assert_eq!(image.pixels, original);
+    }
+}
@@ -0,0 +1,219 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+use crate::error::CliError;
+use crate::report::Reporter;
+use crate::ConfigLayers;
+use image::ImageFormat;
+use vtracer::{convert_with_stats, read_image, write_svg, ConversionStats};
+
+pub struct BatchOptions {
+    /// Maximum number of conversions running at the same time
+    pub jobs: usize,
+    /// Convert even if the output is newer than the input
+    pub force: bool,
+    /// Only print failures
+    pub quiet: bool,
+}
+
+enum Outcome {
+    Converted(ConversionStats),
+    Skipped,
+    Failed(String),
+}
+
+struct Job {
+    input_path: PathBuf,
+    output_path: PathBuf,
+}
+
+/// Whether `input` names a set of images (a directory or a glob pattern) rather than a single file
+pub fn is_batch_input(input: &str) -> bool {
+    let path = Path::new(input);
+    path.is_dir() || (!path.exists() && input.contains(['*', '?', '[']))
+}
+
+/// Convert every image matched by `input` into `output_dir`, mirroring the directory structure.
+/// Prints a line per file and fails if any conversion failed.
+pub fn convert_batch(
+    input: &str,
+    output_dir: &Path,
+    config: &ConfigLayers,
+    options: &BatchOptions,
+    reporter: Option<&Reporter>,
+) -> Result<(), CliError> {
+    let (root, input_paths) = collect_inputs(input).map_err(CliError::UnreadableInput)?;
+    if input_paths.is_empty() {
+        return Err(CliError::UnreadableInput(format!(
+            "No image files found at {}",
+            input
+        )));
+    }
+
+    let jobs: Vec<Job> = input_paths
+        .into_iter()
+        .map(|input_path| {
+            let relative = input_path.strip_prefix(&root).unwrap_or(&input_path);
+            let output_path = output_dir.join(relative).with_extension("svg");
+            Job {
+                input_path,
+                output_path,
+            }
+        })
+        .collect();
+
+    // `a.png` and `a.jpg` would both be written to `a.svg`
+    let mut outputs: HashMap<&Path, &Path> = HashMap::new();
+    for job in jobs.iter() {
+        if let Some(other) = outputs.insert(&job.output_path, &job.input_path) {
+            return Err(CliError::InvalidArguments(format!(
+                "{} and {} would both be written to {}",
+                other.display(),
+                job.input_path.display(),
+                job.output_path.display()
+            )));
+        }
+    }
+
+    let next = AtomicUsize::new(0);
+    let counts = Mutex::new((0, 0, 0));
+    let num_threads = options.jobs.clamp(1, jobs.len());
+
+    thread::scope(|scope| {
+        for _ in 0..num_threads {
+            scope.spawn(|| loop {
+                let index = next.fetch_add(1, Ordering::Relaxed);
+                let Some(job) = jobs.get(index) else {
+                    break;
+                };
+                let mut outcome = convert_job(job, config, options.force);
+                if let (Outcome::Converted(stats), Some(reporter)) = (&outcome, reporter) {
+                    if let Err(err) = reporter.write(&job.input_path, &job.output_path, stats) {
+                        outcome = Outcome::Failed(err.to_string());
+                    }
+                }
+                let mut counts = counts.lock().unwrap();
+                match outcome {
+                    Outcome::Converted(_) => {
+                        counts.0 += 1;
+                        if !options.quiet {
+                            println!(
+                                "[converted] {} -> {}",
+                                job.input_path.display(),
+                                job.output_path.display()
+                            );
+                        }
+                    }
+                    Outcome::Skipped => {
+                        counts.1 += 1;
+                        if !options.quiet {
+                            println!("[skipped]   {} (up to date)", job.input_path.display());
+                        }
+                    }
+                    Outcome::Failed(msg) => {
+                        counts.2 += 1;
+                        eprintln!("[failed]    {}: {}", job.input_path.display(), msg);
+                    }
+                }
+            });
+        }
+    });
+
+    let (converted, skipped, failed) = counts.into_inner().unwrap();
+    if !options.quiet {
+        println!(
+            "{} converted, {} skipped, {} failed.",
+            converted, skipped, failed
+        );
+    }
+
+    if failed > 0 {
+        return Err(CliError::ConversionFailed(format!(
+            "{} of {} images failed",
+            failed,
+            jobs.len()
+        )));
+    }
+    Ok(())
+}
+
+fn convert_job(job: &Job, config: &ConfigLayers, force: bool) -> Outcome {
+    if !force && is_up_to_date(&job.input_path, &job.output_path) {
+        return Outcome::Skipped;
+    }
+    if let Some(parent) = job.output_path.parent() {
+        if let Err(err) = fs::create_dir_all(parent) {
+            return Outcome::Failed(format!("Cannot create output directory: {}", err));
+        }
+    }
+    let result = read_image(&job.input_path)
+        .and_then(|img| {
+            let config = config.for_image(&img).map_err(|err| err.to_string())?;
+            convert_with_stats(img, config)
+        })
+        .and_then(|(svg, stats)| write_svg(svg, &job.output_path).map(|()| stats));
+    match result {
+        Ok(stats) => Outcome::Converted(stats),
+        Err(msg) => Outcome::Failed(msg),
+    }
+}
+
+fn is_up_to_date(input_path: &Path, output_path: &Path) -> bool {
+    let modified = |path: &Path| fs::metadata(path).and_then(|meta| meta.modified()).ok();
+    match (modified(input_path), modified(output_path)) {
+        (Some(input), Some(output)) => output >= input,
+        _ => false,
+    }
+}
+
+/// Returns the directory that output paths are made relative to, and the sorted list of images
+fn collect_inputs(input: &str) -> Result<(PathBuf, Vec<PathBuf>), String> {
+    let path = Path::new(input);
+    if path.is_dir() {
+        let mut paths = vec![];
+        walk_dir(path, &mut paths)?;
+        paths.sort();
+        return Ok((path.to_path_buf(), paths));
+    }
+
+    let entries = glob::glob(input).map_err(|err| format!("Invalid glob pattern: {}", err))?;
+    let mut paths: Vec<PathBuf> = entries
+        .filter_map(Result::ok)
+        .filter(|path| path.is_file() && is_image_file(path))
+        .collect();
+    paths.sort();
+    Ok((glob_root(input), paths))
+}
+
+fn walk_dir(dir: &Path, paths: &mut Vec<PathBuf>) -> Result<(), String> {
+    let entries = fs::read_dir(dir)
+        .map_err(|err| format!("Cannot read directory {}: {}", dir.display(), err))?;
+    for entry in entries {
+        let path = entry.map_err(|err| err.to_string())?.path();
+        if path.is_dir() {
+            walk_dir(&path, paths)?;
+        } else if is_image_file(&path) {
+            paths.push(path);
+        }
+    }
+    Ok(())
+}
+
+fn is_image_file(path: &Path) -> bool {
+    ImageFormat::from_path(path).is_ok()
+}
+
+/// The leading components of a glob pattern that contain no wildcards
+fn glob_root(pattern: &str) -> PathBuf {
+    Path::new(pattern)
+        .components()
+        .take_while(|component| match component {
+            Component::Normal(part) => !part.to_string_lossy().contains(['*', '?', '[']),
+            _ => true,
+        })
+        .collect()
+}
@@ -0,0 +1,488 @@
+use visioncortex::{CompoundPath, PathF64, PathSimplifyMode, PointF64, Spline};
+
+/// Largest distance in pixels between a skeleton and the polyline simplifying it
+const SIMPLIFY_TOLERANCE: f64 = 1.0;
+
+/// A line along the middle of a shape, to be drawn with a pen of the given width
+pub(crate) struct Stroke {
+    pub path: CompoundPath,
+    pub width: f64,
+}
+
+/// A binary image with a one pixel border of background, so that every pixel of the image has
+/// eight neighbours
+struct Grid {
+    pixels: Vec<bool>,
+    width: usize,
+}
+
+impl Grid {
+    fn new(mask: &[bool], width: usize, height: usize) -> Self {
+        let padded = width + 2;
+        let mut pixels = vec![false; padded * (height + 2)];
+        for y in 0..height {
+            pixels[(y + 1) * padded + 1..(y + 1) * padded + 1 + width]
+                .copy_from_slice(&mask[y * width..(y + 1) * width]);
+        }
+        Self {
+            pixels,
+            width: padded,
+        }
+    }
+
+    /// The indices of the neighbours of `i`: north, east, south, west, then the diagonals
+    /// north-east, south-east, south-west and north-west
+    fn neighbours(&self, i: usize) -> [usize; 8] {
+        let w = self.width;
+        [
+            i - w,
+            i + 1,
+            i + w,
+            i - 1,
+            i - w + 1,
+            i + w + 1,
+            i + w - 1,
+            i - w - 1,
+        ]
+    }
+
+    /// The neighbours of `i` in clockwise order from north, as Zhang-Suen numbers them P2 to P9
+    fn ring(&self, i: usize) -> [bool; 8] {
+        let [n, e, s, w, ne, se, sw, nw] = self.neighbours(i).map(|j| self.pixels[j]);
+        [n, ne, e, se, s, sw, w, nw]
+    }
+
+    fn point(&self, i: usize) -> PointF64 {
+        // the centre of the pixel, undoing the padding
+        PointF64::new((i % self.width) as f64 - 0.5, (i / self.width) as f64 - 0.5)
+    }
+}
+
+/// Trace the centerlines of the foreground of `mask`, a `width` by `height` image in row-major
+/// order. Each stroke runs between line ends and junctions of the skeleton and is as wide as the
+/// shape around it on average, rounded to half a pixel. The strokes come ordered by width, so
+/// that strokes of equal width can share a path.
+pub(crate) fn trace_centerlines(
+    mask: &[bool],
+    width: usize,
+    height: usize,
+    mode: PathSimplifyMode,
+    corner_threshold: f64,
+) -> Vec<Stroke> {
+    let shape = Grid::new(mask, width, height);
+    let distance = distance_transform(&shape);
+    let mut skeleton = Grid::new(mask, width, height);
+    thin(&mut skeleton);
+    remove_staircases(&mut skeleton);
+
+    let widths: Vec<f64> = (0..skeleton.pixels.len())
+        .map(|i| {
+            if !skeleton.pixels[i] {
+                return 0.0;
+            }
+            // a ridge two pixels wide means the shape is even in width, and the skeleton
+            // runs half a pixel off its middle
+            let plateau = skeleton.neighbours(i)[..4]
+                .iter()
+                .any(|&j| shape.pixels[j] && !skeleton.pixels[j] && distance[j] >= distance[i]);
+            if plateau {
+                2.0 * distance[i]
+            } else {
+                2.0 * distance[i] - 1.0
+            }
+        })
+        .collect();
+
+    let mut strokes: Vec<Stroke> = trace_lines(&skeleton, &distance)
+        .into_iter()
+        .map(|line| {
+            // junctions are thicker than the lines meeting there, so leave them out of the average
+            let inner = if line.len() > 2 {
+                &line[1..line.len() - 1]
+            } else {
+                &line[..]
+            };
+            let width = inner.iter().map(|&i| widths[i]).sum::<f64>() / inner.len() as f64;
+            let points: Vec<PointF64> = line.iter().map(|&i| skeleton.point(i)).collect();
+            Stroke {
+                path: fit(&points, mode, corner_threshold),
+                width: (2.0 * width).round().max(2.0) / 2.0,
+            }
+        })
+        .collect();
+    strokes.sort_by(|a, b| a.width.total_cmp(&b.width));
+    strokes
+}
+
+/// The Euclidean distance from the centre of every pixel to the centre of the nearest
+/// background pixel, by the separable algorithm of Felzenszwalb and Huttenlocher
+fn distance_transform(grid: &Grid) -> Vec<f64> {
+    let (width, height) = (grid.width, grid.pixels.len() / grid.width);
+    let mut squared: Vec<f64> = grid
+        .pixels
+        .iter()
+        .map(|&fg| if fg { f64::INFINITY } else { 0.0 })
+        .collect();
+
+    let mut line = vec![];
+    for x in 0..width {
+        line.clear();
+        line.extend((0..height).map(|y| squared[y * width + x]));
+        for (y, d) in lower_envelope(&line).into_iter().enumerate() {
+            squared[y * width + x] = d;
+        }
+    }
+    for y in 0..height {
+        let row = &mut squared[y * width..(y + 1) * width];
+        let distances = lower_envelope(row);
+        row.copy_from_slice(&distances);
+    }
+    squared.into_iter().map(f64::sqrt).collect()
+}
+
+/// The squared distance transform of one row, `min over q of (p - q)^2 + f(q)`
+fn lower_envelope(f: &[f64]) -> Vec<f64> {
+    let n = f.len();
+    let mut result = vec![f64::INFINITY; n];
+    // the parabolas making up the envelope, and where each starts to be the lowest
+    let mut vertices: Vec<usize> = Vec::with_capacity(n);
+    let mut starts: Vec<f64> = Vec::with_capacity(n + 1);
+    for q in (0..n).filter(|&q| f[q].is_finite()) {
+        let intersect = |p: usize| {
+            ((f[q] + (q * q) as f64) - (f[p] + (p * p) as f64)) / (2.0 * (q as f64 - p as f64))
+        };
+        while let Some(&p) = vertices.last() {
+            if intersect(p) <= *starts.last().unwrap() {
+                vertices.pop();
+                starts.pop();
+            } else {
+                break;
+            }
+        }
+        starts.push(match vertices.last() {
+            Some(&p) => intersect(p),
+            None => f64::NEG_INFINITY,
+        });
+        vertices.push(q);
+    }
+    if vertices.is_empty() {
+        return result;
+    }
+
+    let mut k = 0;
+    for (p, d) in result.iter_mut().enumerate() {
+        while k + 1 < vertices.len() && starts[k + 1] < p as f64 {
+            k += 1;
+        }
+        let q = vertices[k];
+        *d = (p as f64 - q as f64).powi(2) + f[q];
+    }
+    result
+}
+
+/// Thin the foreground down to lines one pixel wide by the algorithm of Zhang and Suen
+fn thin(grid: &mut Grid) {
+    let mut foreground: Vec<usize> = (0..grid.pixels.len()).filter(|&i| grid.pixels[i]).collect();
+    let mut removed = vec![];
+    loop {
+        let mut changed = false;
+        for step in 0..2 {
+            removed.clear();
+            for &i in foreground.iter() {
+                let p = grid.ring(i);
+                let count = p.iter().filter(|&&v| v).count();
+                if !(2..=6).contains(&count) {
+                    continue;
+                }
+                let transitions = (0..8).filter(|&k| !p[k] && p[(k + 1) % 8]).count();
+                if transitions != 1 {
+                    continue;
+                }
+                let [n, _, e, _, s, _, w, _] = p;
+                let deletable = if step == 0 {
+                    !(e && s && (n || w))
+                } else {
+                    !(n && w && (e || s))
+                };
+                if deletable {
+                    removed.push(i);
+                }
+            }
+            for &i in removed.iter() {
+                grid.pixels[i] = false;
+            }
+            changed |= !removed.is_empty();
+            foreground.retain(|&i| grid.pixels[i]);
+        }
+        if !changed {
+            break;
+        }
+    }
+}
+
+/// Remove the corner pixels of staircases left by thinning, whose two neighbours along the
+/// stairs also touch diagonally, so that every pixel inside a line has exactly two neighbours
+fn remove_staircases(grid: &mut Grid) {
+    for i in 0..grid.pixels.len() {
+        if !grid.pixels[i] {
+            continue;
+        }
+        let [n, e, s, w, ne, se, sw, nw] = grid.neighbours(i).map(|j| grid.pixels[j]);
+        let redundant = (n && e && !s && !w && !sw)
+            || (e && s && !n && !w && !nw)
+            || (s && w && !n && !e && !ne)
+            || (w && n && !s && !e && !se);
+        if redundant {
+            grid.pixels[i] = false;
+        }
+    }
+}
+
+/// Split the skeleton into lines of pixel indices, each running between two nodes (line ends
+/// and junctions), plus the closed loops that have no node. Short spurs from a junction to a
+/// line end are noise of the thinning and left out, and so are the links between adjacent
+/// junction pixels, which the round ends of the lines meeting there cover.
+fn trace_lines(grid: &Grid, distance: &[f64]) -> Vec<Vec<usize>> {
+    let on = |i: usize| grid.pixels[i];
+    let degree = |i: usize| grid.neighbours(i).iter().filter(|&&j| on(j)).count();
+    let is_node = |i: usize| degree(i) != 2;
+
+    let mut visited = vec![false; grid.pixels.len()];
+    let mut lines = vec![];
+
+    for node in (0..grid.pixels.len()).filter(|&i| on(i) && is_node(i)) {
+        if degree(node) == 0 {
+            lines.push(vec![node]);
+            continue;
+        }
+        for next in grid.neighbours(node) {
+            if !on(next) {
+                continue;
+            }
+            let mut line = vec![node, next];
+            if is_node(next) {
+                let is_link = degree(node) > 2 && degree(next) > 2;
+                if next < node || is_link {
+                    continue;
+                }
+            } else {
+                if visited[next] {
+                    continue;
+                }
+                visited[next] = true;
+                let (mut previous, mut current) = (node, next);
+                while let Some(following) = grid
+                    .neighbours(current)
+                    .into_iter()
+                    .find(|&j| on(j) && j != previous && (is_node(j) || !visited[j]))
+                {
+                    line.push(following);
+                    if is_node(following) {
+                        break;
+                    }
+                    visited[following] = true;
+                    (previous, current) = (current, following);
+                }
+            }
+
+            let (first, last) = (line[0], *line.last().unwrap());
+            let spur = |end: usize, junction: usize| {
+                degree(end) == 1 && degree(junction) > 2 && (line.len() as f64) < distance[junction]
+            };
+            if !(spur(first, last) || spur(last, first)) {
+                lines.push(line);
+            }
+        }
+    }
+
+    for start in 0..grid.pixels.len() {
+        if !on(start) || visited[start] || is_node(start) {
+            continue;
+        }
+        visited[start] = true;
+        let mut line = vec![start];
+        let mut previous = None;
+        let mut current = start;
+        while let Some(following) = grid
+            .neighbours(current)
+            .into_iter()
+            .find(|&j| on(j) && Some(j) != previous && !visited[j])
+        {
+            visited[following] = true;
+            line.push(following);
+            (previous, current) = (Some(current), following);
+        }
+        line.push(start);
+        lines.push(line);
+    }
+    lines
+}
+
+/// Fit the pixel centres of a line as `mode` does outlines: `none` keeps every pixel,
+/// `polygon` simplifies the line, and `spline` passes smooth curves through the simplified
+/// line's vertices, keeping the corners sharper than `corner_threshold` (radians)
+fn fit(points: &[PointF64], mode: PathSimplifyMode, corner_threshold: f64) -> CompoundPath {
+    let mut path = CompoundPath::new();
+    let points = match mode {
+        PathSimplifyMode::None => points.to_vec(),
+        PathSimplifyMode::Polygon | PathSimplifyMode::Spline => simplify(points),
+    };
+    if matches!(mode, PathSimplifyMode::Spline) && points.len() > 2 {
+        path.add_spline(catmull_rom(&points, corner_threshold));
+    } else {
+        let mut points = points;
+        // a single point is drawn as a dot by the round line caps
+        if points.len() == 1 {
+            points.push(points[0]);
+        }
+        path.add_path_f64(PathF64::from_points(points));
+    }
+    path
+}
+
+/// Ramer-Douglas-Peucker simplification
+pub(crate) fn simplify(points: &[PointF64]) -> Vec<PointF64> {
+    if points.len() < 3 {
+        return points.to_vec();
+    }
+    let mut keep = vec![false; points.len()];
+    keep[0] = true;
+    keep[points.len() - 1] = true;
+    let mut sections = vec![(0, points.len() - 1)];
+    while let Some((first, last)) = sections.pop() {
+        let (a, b) = (points[first], points[last]);
+        let farthest = (first + 1..last)
+            .map(|i| (i, distance_to_segment(points[i], a, b)))
+            .max_by(|x, y| x.1.total_cmp(&y.1));
+        if let Some((i, distance)) = farthest {
+            if distance > SIMPLIFY_TOLERANCE {
+                keep[i] = true;
+                sections.push((first, i));
+                sections.push((i, last));
+            }
+        }
+    }
+    points
+        .iter()
+        .zip(keep)
+        .filter(|(_, keep)| *keep)
+        .map(|(&point, _)| point)
+        .collect()
+}
+
+pub(crate) fn distance_to_segment(p: PointF64, a: PointF64, b: PointF64) -> f64 {
+    let (dx, dy) = (b.x - a.x, b.y - a.y);
+    let length_squared = dx * dx + dy * dy;
+    let t = if length_squared == 0.0 {
+        0.0
+    } else {
+        (((p.x - a.x) * dx + (p.y - a.y) * dy) / length_squared).clamp(0.0, 1.0)
+    };
+    let (x, y) = (a.x + t * dx - p.x, a.y + t * dy - p.y);
+    (x * x + y * y).sqrt()
+}
+
+/// A spline through `points` with Catmull-Rom tangents, except at the ends and at corners
+/// turning by more than `corner_threshold`, where the curves meet at an angle
+fn catmull_rom(points: &[PointF64], corner_threshold: f64) -> Spline {
+    let last = points.len() - 1;
+    let corner: Vec<bool> = (0..points.len())
+        .map(|i| {
+            if i == 0 || i == last {
+                return true;
+            }
+            let (a, b, c) = (points[i - 1], points[i], points[i + 1]);
+            let turn = ((c.y - b.y).atan2(c.x - b.x) - (b.y - a.y).atan2(b.x - a.x)).abs();
+            let turn = if turn > std::f64::consts::PI {
+                2.0 * std::f64::consts::PI - turn
+            } else {
+                turn
+            };
+            turn > corner_threshold
+        })
+        .collect();
+
+    let mut spline = Spline::new(points[0]);
+    for i in 0..last {
+        let (p1, p2) = (points[i], points[i + 1]);
+        let p0 = if corner[i] { p1 } else { points[i - 1] };
+        let p3 = if corner[i + 1] { p2 } else { points[i + 2] };
+        spline.add(
+            PointF64::new(p1.x + (p2.x - p0.x) / 6.0, p1.y + (p2.y - p0.y) / 6.0),
+            PointF64::new(p2.x - (p3.x - p1.x) / 6.0, p2.y - (p3.y - p1.y) / 6.0),
+            p2,
+        );
+    }
+    spline
+}
+
+#[cfg(test)]
+mod tests {
+    use visioncortex::CompoundPathElement;
+
+    use super::*;
+    use crate::rasterizer::distance;
+
+    /// A `width` by `height` mask with the pixels `inside` says are foreground
+    fn mask(width: usize, height: usize, inside: impl Fn(usize, usize) -> bool) -> Vec<bool> {
+        (0..width * height)
+            .map(|i| inside(i % width, i / width))
+            .collect()
+    }
+
+    fn polyline(stroke: &Stroke) -> Vec<PointF64> {
+        match stroke.path.iter().collect::<Vec<_>>()[..] {
+            [CompoundPathElement::PathF64(path)] => path.iter().copied().collect(),
+            _ => panic!("expected a single polyline"),
+        }
+    }
+
+    #[test]
+    fn thin_line_is_a_single_polyline() {
+        let mask = mask(24, 5, |x, y| y == 2 && (2..20).contains(&x));
+        let strokes = trace_centerlines(&mask, 24, 5, PathSimplifyMode::Polygon, 60.0);
+        assert_eq!(strokes.len(), 1);
+        assert_eq!(strokes[0].width, 1.0);
+        let points = polyline(&strokes[0]);
+        assert_eq!(points.len(), 2);
+        let (first, last) = (points[0], points[points.len() - 1]);
+        let ends = if first.x < last.x {
+            (first, last)
+        } else {
+            (last, first)
+        };
+        // at the centres of the end pixels
+        assert_eq!(ends, (PointF64::new(2.5, 2.5), PointF64::new(19.5, 2.5)));
+    }
+
+    #[test]
+    fn bent_line_keeps_its_corner() {
+        let mask = mask(20, 20, |x, y| {
+            (y == 3 && (3..16).contains(&x)) || (x == 15 && (3..16).contains(&y))
+        });
+        let strokes = trace_centerlines(&mask, 20, 20, PathSimplifyMode::Polygon, 60.0);
+        assert_eq!(strokes.len(), 1);
+        let points = polyline(&strokes[0]);
+        assert_eq!(points.len(), 3);
+        // thinning may round the corner off by a pixel
+        assert!(
+            distance(points[1], PointF64::new(15.5, 3.5)) <= 1.5,
+            "{:?}",
+            points[1]
+        );
+    }
+
+    #[test]
+    fn thick_bar_is_as_wide_as_the_shape() {
+        let mask = mask(40, 11, |x, y| (3..8).contains(&y) && (3..37).contains(&x));
+        let strokes = trace_centerlines(&mask, 40, 11, PathSimplifyMode::Polygon, 60.0);
+        assert_eq!(strokes.len(), 1);
+        assert_eq!(strokes[0].width, 5.0);
+        let points = polyline(&strokes[0]);
+        assert!(
+            points.iter().all(|p| (p.y - 5.5).abs() <= 0.5),
+            "{:?}",
+            points
+        );
+    }
+}
@@ -0,0 +1,270 @@
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+use std::sync::OnceLock;
+use visioncortex::Color;
+
+/// D65 reference white
+const WHITE: [f64; 3] = [0.95047, 1.0, 1.08883];
+const EPSILON: f64 = 216.0 / 24389.0;
+const KAPPA: f64 = 24389.0 / 27.0;
+
+/// OKLab coordinates are scaled by this, so that distances are on the same scale as CIELAB's
+const OKLAB_SCALE: f64 = 100.0;
+
+/// The space in which colors are compared and averaged
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(rename_all = "lowercase")
+)]
+pub enum ColorSpace {
+    /// Plain sRGB channel values
+    Rgb,
+    /// CIELAB, where distance approximates perceived difference
+    Lab,
+    /// OKLab, which is more uniform than CIELAB in hue and for saturated blues.
+    /// Its coordinates are scaled by 100 to put distances on the scale of CIELAB's.
+    Oklab,
+}
+
+impl FromStr for ColorSpace {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "rgb" => Ok(Self::Rgb),
+            "lab" => Ok(Self::Lab),
+            "oklab" => Ok(Self::Oklab),
+            _ => Err(format!("unknown ColorSpace {}", s)),
+        }
+    }
+}
+
+impl ColorSpace {
+    /// The coordinates of `color` in this space
+    pub(crate) fn to_point(self, color: &Color) -> [f64; 3] {
+        match self {
+            Self::Rgb => [color.r as f64, color.g as f64, color.b as f64],
+            Self::Lab => {
+                let lab = srgb_to_lab(color);
+                [lab.l, lab.a, lab.b]
+            }
+            Self::Oklab => srgb_to_oklab(color).map(|c| c * OKLAB_SCALE),
+        }
+    }
+
+    /// The sRGB color at `point` in this space
+    pub(crate) fn to_color(self, point: [f64; 3]) -> Color {
+        match self {
+            Self::Rgb => {
+                let [r, g, b] = point.map(|c| c.round().clamp(0.0, 255.0) as u8);
+                Color::new(r, g, b)
+            }
+            Self::Lab => lab_to_srgb(&Lab {
+                l: point[0],
+                a: point[1],
+                b: point[2],
+            }),
+            Self::Oklab => oklab_to_srgb(point.map(|c| c / OKLAB_SCALE)),
+        }
+    }
+
+    /// The distance between two colors, which in `lab` is the CIE76 ΔE*ab
+    pub(crate) fn delta_e(self, a: &Color, b: &Color) -> f64 {
+        distance_squared(&self.to_point(a), &self.to_point(b)).sqrt()
+    }
+}
+
+pub(crate) fn distance_squared(a: &[f64; 3], b: &[f64; 3]) -> f64 {
+    a.iter().zip(b).map(|(a, b)| (a - b) * (a - b)).sum()
+}
+
+/// A color in CIELAB, where euclidean distance approximates perceived difference
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Lab {
+    pub l: f64,
+    pub a: f64,
+    pub b: f64,
+}
+
+impl Lab {
+    /// The CIE76 color difference ΔE*ab, squared
+    pub fn distance_squared(&self, other: &Lab) -> f64 {
+        let (dl, da, db) = (self.l - other.l, self.a - other.a, self.b - other.b);
+        dl * dl + da * da + db * db
+    }
+}
+
+/// Convert an sRGB color to CIELAB under D65, ignoring alpha
+pub(crate) fn srgb_to_lab(color: &Color) -> Lab {
+    let [r, g, b] = [color.r, color.g, color.b].map(linear_channel);
+    let xyz = [
+        (0.4124564 * r + 0.3575761 * g + 0.1804375 * b) / WHITE[0],
+        (0.2126729 * r + 0.7151522 * g + 0.0721750 * b) / WHITE[1],
+        (0.0193339 * r + 0.1191920 * g + 0.9503041 * b) / WHITE[2],
+    ];
+    let [fx, fy, fz] = xyz.map(|t| {
+        if t > EPSILON {
+            t.cbrt()
+        } else {
+            (KAPPA * t + 16.0) / 116.0
+        }
+    });
+    Lab {
+        l: 116.0 * fy - 16.0,
+        a: 500.0 * (fx - fy),
+        b: 200.0 * (fy - fz),
+    }
+}
+
+/// Convert a CIELAB color under D65 to sRGB, clamping colors outside the gamut
+pub(crate) fn lab_to_srgb(lab: &Lab) -> Color {
+    let fy = (lab.l + 16.0) / 116.0;
+    let fx = fy + lab.a / 500.0;
+    let fz = fy - lab.b / 200.0;
+    let inverse = |f: f64| {
+        if f * f * f > EPSILON {
+            f * f * f
+        } else {
+            (116.0 * f - 16.0) / KAPPA
+        }
+    };
+    let [x, y, z] = [
+        inverse(fx) * WHITE[0],
+        inverse(fy) * WHITE[1],
+        inverse(fz) * WHITE[2],
+    ];
+    let [r, g, b] = [
+        3.2404542 * x - 1.5371385 * y - 0.4985314 * z,
+        -0.9692660 * x + 1.8760108 * y + 0.0415560 * z,
+        0.0556434 * x - 0.2040259 * y + 1.0572252 * z,
+    ]
+    .map(|c| (from_linear(c).clamp(0.0, 1.0) * 255.0).round() as u8);
+    Color::new(r, g, b)
+}
+
+/// Convert an sRGB color to OKLab, ignoring alpha
+fn srgb_to_oklab(color: &Color) -> [f64; 3] {
+    let [r, g, b] = [color.r, color.g, color.b].map(linear_channel);
+    let [l, m, s] = [
+        0.4122214708 * r + 0.5363325363 * g + 0.0514459929 * b,
+        0.2119034982 * r + 0.6806995451 * g + 0.1073969566 * b,
+        0.0883024619 * r + 0.2817188376 * g + 0.6299787005 * b,
+    ]
+    .map(f64::cbrt);
+    [
+        0.2104542553 * l + 0.7936177850 * m - 0.0040720468 * s,
+        1.9779984951 * l - 2.4285922050 * m + 0.4505937099 * s,
+        0.0259040371 * l + 0.7827717662 * m - 0.8086757660 * s,
+    ]
+}
+
+/// Convert an OKLab color to sRGB, clamping colors outside the gamut
+fn oklab_to_srgb([l, a, b]: [f64; 3]) -> Color {
+    let [l, m, s] = [
+        l + 0.3963377774 * a + 0.2158037573 * b,
+        l - 0.1055613458 * a - 0.0638541728 * b,
+        l - 0.0894841775 * a - 1.2914855480 * b,
+    ]
+    .map(|c| c * c * c);
+    let [r, g, b] = [
+        4.0767416621 * l - 3.3077115913 * m + 0.2309699292 * s,
+        -1.2684380046 * l + 2.6097574011 * m - 0.3413193965 * s,
+        -0.0041960863 * l - 0.7034186147 * m + 1.7076147010 * s,
+    ]
+    .map(|c| (from_linear(c).clamp(0.0, 1.0) * 255.0).round() as u8);
+    Color::new(r, g, b)
+}
+
+/// The linear intensity of an sRGB channel value, from a table since clustering converts
+/// colors for every pair of pixels it compares
+fn linear_channel(c: u8) -> f64 {
+    static TABLE: OnceLock<[f64; 256]> = OnceLock::new();
+    TABLE.get_or_init(|| std::array::from_fn(|c| to_linear(c as f64 / 255.0)))[c as usize]
+}
+
+fn to_linear(c: f64) -> f64 {
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn from_linear(c: f64) -> f64 {
+    if c <= 0.0031308 {
+        12.92 * c
+    } else {
+        1.055 * c.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: [f64; 3], expected: [f64; 3], tolerance: f64) {
+        for (actual, expected) in actual.iter().zip(expected) {
+            assert!(
+                (actual - expected).abs() <= tolerance,
+                "{:?} is not {:?}",
+                actual,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn lab_reference_values() {
+        let cases = [
+            (Color::new(255, 255, 255), [100.0, 0.0, 0.0]),
+            (Color::new(0, 0, 0), [0.0, 0.0, 0.0]),
+            (Color::new(255, 0, 0), [53.2408, 80.0925, 67.2032]),
+            (Color::new(0, 255, 0), [87.7347, -86.1827, 83.1793]),
+            (Color::new(0, 0, 255), [32.2970, 79.1875, -107.8602]),
+            (Color::new(128, 128, 128), [53.5850, 0.0, 0.0]),
+        ];
+        for (color, lab) in cases {
+            assert_close(ColorSpace::Lab.to_point(&color), lab, 0.01);
+        }
+    }
+
+    #[test]
+    fn oklab_reference_values() {
+        let cases = [
+            (Color::new(255, 255, 255), [1.0, 0.0, 0.0]),
+            (Color::new(255, 0, 0), [0.62796, 0.22486, 0.12585]),
+            (Color::new(0, 255, 0), [0.86644, -0.23389, 0.17950]),
+            (Color::new(0, 0, 255), [0.45201, -0.03246, -0.31153]),
+        ];
+        for (color, oklab) in cases {
+            assert_close(
+                ColorSpace::Oklab.to_point(&color),
+                oklab.map(|c| c * OKLAB_SCALE),
+                0.01,
+            );
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        for space in [ColorSpace::Rgb, ColorSpace::Lab, ColorSpace::Oklab] {
+            for r in (0..=255).step_by(15) {
+                for g in (0..=255).step_by(15) {
+                    for b in (0..=255).step_by(15) {
+                        let color = Color::new(r, g, b);
+                        assert_eq!(space.to_color(space.to_point(&color)), color, "{:?}", space);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn out_of_gamut_is_clamped() {
+        let color = ColorSpace::Lab.to_color([50.0, 200.0, 0.0]);
+        assert_eq!((color.r, color.g), (255, 0));
+    }
+}
@@ -0,0 +1,147 @@
+use std::path::{Path, PathBuf};
+
+use crate::error::CliError;
+use crate::ConfigLayers;
+use clap::{App, Arg, ArgMatches, SubCommand};
+use serde::Serialize;
+use vtracer::Fidelity;
+
+pub struct CompareArgs {
+    pub input_path: PathBuf,
+    pub output_path: Option<PathBuf>,
+    pub heatmap_path: Option<PathBuf>,
+    pub json: bool,
+    pub config: ConfigLayers,
+}
+
+#[derive(Serialize)]
+struct Report<'a> {
+    input: &'a Path,
+    #[serde(flatten)]
+    fidelity: &'a Fidelity,
+}
+
+pub fn subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("compare")
+        .about("Trace an image and measure how closely the result reproduces it")
+        .arg(
+            Arg::with_name("input")
+                .long("input")
+                .short("i")
+                .takes_value(true)
+                .help("Path to input raster image")
+                .required(true),
+        )
+        .arg(
+            Arg::with_name("output")
+                .long("output")
+                .short("o")
+                .takes_value(true)
+                .help("Path to also write the traced vector graphics to"),
+        )
+        .arg(
+            Arg::with_name("heatmap")
+                .long("heatmap")
+                .takes_value(true)
+                .help("Path to write a PNG of the per-pixel error to, brighter is worse"),
+        )
+        .arg(
+            Arg::with_name("json")
+                .long("json")
+                .help("Print the metrics as a JSON object"),
+        )
+        .args(&crate::config_args())
+}
+
+pub fn args_from_matches(matches: &ArgMatches) -> Result<CompareArgs, CliError> {
+    Ok(CompareArgs {
+        // Required, so clap guarantees it is present
+        input_path: PathBuf::from(matches.value_of("input").unwrap()),
+        output_path: matches.value_of("output").map(PathBuf::from),
+        heatmap_path: matches.value_of("heatmap").map(PathBuf::from),
+        json: matches.is_present("json"),
+        config: ConfigLayers::from_matches(matches)?,
+    })
+}
+
+/// Trace the input, then print its PSNR and SSIM against the rendered result
+pub fn run(args: CompareArgs) -> Result<(), CliError> {
+    let img = vtracer::read_image(&args.input_path).map_err(CliError::UnreadableInput)?;
+    let config = args.config.for_image(&img)?;
+    let (svg, _, fidelity) =
+        vtracer::convert_and_compare(&img, config).map_err(CliError::ConversionFailed)?;
+
+    if let Some(output_path) = &args.output_path {
+        vtracer::write_svg(svg, output_path).map_err(CliError::UnwritableOutput)?;
+    }
+    if let Some(heatmap_path) = &args.heatmap_path {
+        vtracer::write_png(&fidelity.heatmap, heatmap_path).map_err(CliError::UnwritableOutput)?;
+    }
+
+    if args.json {
+        let report = Report {
+            input: &args.input_path,
+            fidelity: &fidelity,
+        };
+        let json = serde_json::to_string(&report)
+            .map_err(|err| CliError::UnwritableOutput(err.to_string()))?;
+        println!("{}", json);
+    } else {
+        println!("PSNR: {:.2} dB", fidelity.psnr);
+        println!("SSIM: {:.4}", fidelity.ssim);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use visioncortex::Color;
+    use vtracer::{write_png, ColorImage};
+
+    /// A white 48 by 48 image with a dark square in the middle, and the directory it is written to
+    fn square() -> (PathBuf, PathBuf) {
+        let dir = std::env::temp_dir().join(format!("vtracer-compare-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut image = ColorImage::new_w_h(48, 48);
+        for y in 0..48 {
+            for x in 0..48 {
+                let color = if (12..36).contains(&x) && (12..36).contains(&y) {
+                    Color::new(20, 40, 90)
+                } else {
+                    Color::new(255, 255, 255)
+                };
+                image.set_pixel(x, y, &color);
+            }
+        }
+        let input = dir.join("square.png");
+        write_png(&image, &input).unwrap();
+        (dir, input)
+    }
+
+    #[test]
+    fn compares_trimmed_output() {
+        let (dir, input) = square();
+        let output = dir.join("square.svg");
+        let args = [
+            "compare",
+            "--input",
+            input.to_str().unwrap(),
+            "--output",
+            output.to_str().unwrap(),
+            "--background",
+            "auto",
+            "--mode",
+            "polygon",
+            "--trim",
+            "--trim_padding",
+            "2",
+        ];
+        let matches = subcommand().get_matches_from_safe(args).unwrap();
+        run(args_from_matches(&matches).unwrap()).unwrap();
+
+        // measured against the whole image, then written trimmed to the square
+        let svg = std::fs::read_to_string(&output).unwrap();
+        assert!(svg.contains(r#"width="28" height="28""#), "{}", svg);
+    }
+}
@@ -1,7 +1,22 @@
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::fmt;
 use std::str::FromStr;
-use visioncortex::PathSimplifyMode;
+use visioncortex::{ColorImage, PathSimplifyMode};
+
+use super::analysis::ImageAnalysis;
+use super::background::Background;
+use super::color_space::ColorSpace;
+use super::hybrid::RasterFormat;
+use super::palette::Palette;
+use super::quantize::QuantizeMethod;
 
 #[derive(Debug, Clone)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(rename_all = "lowercase")
+)]
 pub enum Preset {
     Bw,
     Poster,
@@ -9,12 +24,23 @@ pub enum Preset {
 }
 
 #[derive(Debug, Clone)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(rename_all = "lowercase")
+)]
 pub enum ColorMode {
     Color,
+    #[cfg_attr(feature = "serde", serde(alias = "bw"))]
     Binary,
 }
 
 #[derive(Debug, Clone)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(rename_all = "lowercase")
+)]
 pub enum Hierarchical {
     Stacked,
     Cutout,
@@ -22,18 +48,82 @@ pub enum Hierarchical {
 
 /// Converter config
 #[derive(Debug, Clone)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(default, deny_unknown_fields)
+)]
 pub struct Config {
     pub color_mode: ColorMode,
     pub hierarchical: Hierarchical,
     pub filter_speckle: usize,
     pub color_precision: i32,
     pub layer_difference: i32,
+    #[cfg_attr(feature = "serde", serde(with = "path_simplify_mode"))]
     pub mode: PathSimplifyMode,
     pub corner_threshold: i32,
     pub length_threshold: f64,
     pub max_iterations: usize,
     pub splice_threshold: i32,
     pub path_precision: Option<u32>,
+    /// Trace upscaled pixel art on its native pixel grid
+    pub pixel_art: bool,
+    /// The colors the output is restricted to; in a config file, a list of hex colors
+    pub palette: Option<Palette>,
+    /// The number of colors the image is reduced to before clustering, in color mode
+    pub colors: Option<usize>,
+    /// How the `colors` are chosen
+    pub quantize_method: QuantizeMethod,
+    /// The space in which the `colors` are chosen and pixels are matched to them
+    pub quantize_space: ColorSpace,
+    /// The space in which clustering compares colors, in color mode
+    pub color_space: ColorSpace,
+    /// Trace the middle of every shape as a stroke, in binary mode
+    pub centerline: bool,
+    /// Write outlines fitting a circle, ellipse, rectangle or line as that element
+    pub primitives: bool,
+    /// The largest distance in pixels of a traced outline from a shape that replaces it
+    pub primitive_tolerance: f64,
+    /// Snap edges near the axes to them and turned shapes to right angles, in polygon mode
+    pub orthogonal: bool,
+    /// The largest angle in degrees by which an edge is turned to snap it with `orthogonal`
+    pub snap_angle: i32,
+    /// In polygon mode, round the vertices to multiples of this many pixels
+    pub grid: Option<f64>,
+    /// Write runs of curves fitting a line or circular arc as `L` and `A` commands, in spline mode
+    pub arcs: bool,
+    /// The largest distance in pixels of a traced curve from a line or arc that replaces it
+    pub arc_tolerance: f64,
+    /// Fill regions with a linear or radial gradient fitted to their pixels, in color mode
+    pub gradients: bool,
+    /// The largest root mean square difference per color channel of a gradient from its pixels
+    pub gradient_tolerance: f64,
+    /// Embed the parts of the image too detailed to trace as clipped pictures, in color mode
+    pub hybrid: bool,
+    /// The number of paths showing in a 32 by 32 pixel tile above which `hybrid` embeds it
+    pub hybrid_threshold: usize,
+    /// The encoding of the pictures embedded by `hybrid`
+    pub raster_format: RasterFormat,
+    /// Keep partial transparency as `fill-opacity`, in color mode. This always cuts layers out,
+    /// whatever `hierarchical` says, so that translucent paths do not compound.
+    pub alpha: bool,
+    /// The alpha from 1 to 255 below which pixels are background with `alpha`
+    pub alpha_threshold: u8,
+    /// The background color to remove, or `auto` for the corners' color, in color mode
+    pub background: Option<Background>,
+    /// The largest difference per color channel from the `background` color of a pixel removed
+    pub background_tolerance: u8,
+    /// Crop the canvas to the drawn content
+    pub trim: bool,
+    /// The margin in pixels left around the paths by `trim`
+    pub trim_padding: usize,
+}
+
+/// A field of [`Config`] holding an invalid value
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigError {
+    pub field: &'static str,
+    pub message: String,
 }
 
 #[derive(Debug, Clone)]
@@ -49,6 +139,31 @@ pub(crate) struct ConverterConfig {
     pub max_iterations: usize,
     pub splice_threshold: f64,
     pub path_precision: Option<u32>,
+    pub pixel_art: bool,
+    pub palette: Option<Palette>,
+    pub colors: Option<usize>,
+    pub quantize_method: QuantizeMethod,
+    pub quantize_space: ColorSpace,
+    pub color_space: ColorSpace,
+    pub centerline: bool,
+    pub primitives: bool,
+    pub primitive_tolerance: f64,
+    pub orthogonal: bool,
+    pub snap_angle: f64,
+    pub grid: Option<f64>,
+    pub arcs: bool,
+    pub arc_tolerance: f64,
+    pub gradients: bool,
+    pub gradient_tolerance: f64,
+    pub hybrid: bool,
+    pub hybrid_threshold: usize,
+    pub raster_format: RasterFormat,
+    pub alpha: bool,
+    pub alpha_threshold: u8,
+    pub background: Option<Background>,
+    pub background_tolerance: u8,
+    pub trim: bool,
+    pub trim_padding: usize,
 }
 
 impl Default for Config {
@@ -65,6 +180,31 @@ impl Default for Config {
             splice_threshold: 45,
             max_iterations: 10,
             path_precision: Some(2),
+            pixel_art: false,
+            palette: None,
+            colors: None,
+            quantize_method: QuantizeMethod::MedianCut,
+            quantize_space: ColorSpace::Rgb,
+            color_space: ColorSpace::Rgb,
+            centerline: false,
+            primitives: false,
+            primitive_tolerance: 1.0,
+            orthogonal: false,
+            snap_angle: 3,
+            grid: None,
+            arcs: false,
+            arc_tolerance: 0.5,
+            gradients: false,
+            gradient_tolerance: 8.0,
+            hybrid: false,
+            hybrid_threshold: 16,
+            raster_format: RasterFormat::Jpeg,
+            alpha: false,
+            alpha_threshold: 16,
+            background: None,
+            background_tolerance: 24,
+            trim: false,
+            trim_padding: 0,
         }
     }
 }
@@ -106,68 +246,341 @@ impl FromStr for Preset {
     }
 }
 
+/// Parse a curve fitting mode `none`, `polygon` or `spline`.
+/// `pixel` is accepted as an alias of `none`.
+pub fn path_simplify_mode_from_str(s: &str) -> Result<PathSimplifyMode, String> {
+    match s {
+        "none" | "pixel" => Ok(PathSimplifyMode::None),
+        "polygon" => Ok(PathSimplifyMode::Polygon),
+        "spline" => Ok(PathSimplifyMode::Spline),
+        _ => Err(format!("unknown PathSimplifyMode {}", s)),
+    }
+}
+
+pub fn path_simplify_mode_to_str(mode: &PathSimplifyMode) -> &'static str {
+    match mode {
+        PathSimplifyMode::None => "none",
+        PathSimplifyMode::Polygon => "polygon",
+        PathSimplifyMode::Spline => "spline",
+    }
+}
+
+#[cfg(feature = "serde")]
+mod path_simplify_mode {
+    use super::{path_simplify_mode_from_str, path_simplify_mode_to_str};
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+    use visioncortex::PathSimplifyMode;
+
+    pub fn serialize<S: Serializer>(
+        mode: &PathSimplifyMode,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(path_simplify_mode_to_str(mode))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<PathSimplifyMode, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        path_simplify_mode_from_str(&s).map_err(D::Error::custom)
+    }
+}
+
 impl Config {
     pub fn from_preset(preset: Preset) -> Self {
         match preset {
             Preset::Bw => Self {
                 color_mode: ColorMode::Binary,
-                hierarchical: Hierarchical::Stacked,
-                filter_speckle: 4,
-                color_precision: 6,
-                layer_difference: 16,
-                mode: PathSimplifyMode::Spline,
-                corner_threshold: 60,
-                length_threshold: 4.0,
-                max_iterations: 10,
-                splice_threshold: 45,
-                path_precision: Some(2),
+                ..Self::default()
             },
             Preset::Poster => Self {
-                color_mode: ColorMode::Color,
-                hierarchical: Hierarchical::Stacked,
-                filter_speckle: 4,
                 color_precision: 8,
-                layer_difference: 16,
-                mode: PathSimplifyMode::Spline,
-                corner_threshold: 60,
-                length_threshold: 4.0,
-                max_iterations: 10,
-                splice_threshold: 45,
-                path_precision: Some(2),
+                ..Self::default()
             },
             Preset::Photo => Self {
-                color_mode: ColorMode::Color,
-                hierarchical: Hierarchical::Stacked,
                 filter_speckle: 10,
                 color_precision: 8,
                 layer_difference: 48,
-                mode: PathSimplifyMode::Spline,
                 corner_threshold: 180,
-                length_threshold: 4.0,
-                max_iterations: 10,
-                splice_threshold: 45,
-                path_precision: Some(2),
+                ..Self::default()
             },
         }
     }
 
+    /// Choose the color mode and thresholds from the content of `img`, see [`ImageAnalysis`].
+    /// Gray images that are mostly black and white are traced in binary mode, upscaled pixel art
+    /// is traced on its native grid, flat graphics get the poster settings and anything else the photo ones,
+    /// filtering more speckles the busier the image is.
+    pub fn auto(img: &ColorImage) -> Self {
+        Self::from_analysis(&ImageAnalysis::new(img))
+    }
+
+    /// The config [`Config::auto`] chooses for an image with the given statistics
+    pub fn from_analysis(analysis: &ImageAnalysis) -> Self {
+        // binary mode ignores transparency, so it is only suitable for opaque images
+        if !analysis.has_alpha && analysis.is_grayscale && analysis.black_and_white_coverage >= 0.9
+        {
+            return Self::from_preset(Preset::Bw);
+        }
+
+        if analysis.pixel_scale > 1 && analysis.distinct_colors <= 256 {
+            return Self {
+                pixel_art: true,
+                // every single pixel is a detail of the art
+                filter_speckle: 0,
+                layer_difference: 8,
+                ..Self::from_preset(Preset::Poster)
+            };
+        }
+
+        if analysis.distinct_colors <= 64 || analysis.top_colors_coverage >= 0.6 {
+            return Self::from_preset(Preset::Poster);
+        }
+
+        Self {
+            filter_speckle: (10.0 + 120.0 * analysis.edge_density).round().min(16.0) as usize,
+            ..Self::from_preset(Preset::Photo)
+        }
+    }
+
+    /// Check that every field is within its accepted range.
+    /// Returns all offending fields at once rather than stopping at the first.
+    pub fn validate(&self) -> Result<(), Vec<ConfigError>> {
+        let mut errors = vec![];
+        let mut check =
+            |field: &'static str, valid: bool, range: &str, value: &dyn fmt::Display| {
+                if !valid {
+                    errors.push(ConfigError {
+                        field,
+                        message: format!("{} is out of range, it must be within {}", value, range),
+                    });
+                }
+            };
+
+        check(
+            "filter_speckle",
+            self.filter_speckle <= 16,
+            "[0,16]",
+            &self.filter_speckle,
+        );
+        check(
+            "color_precision",
+            (1..=8).contains(&self.color_precision),
+            "[1,8]",
+            &self.color_precision,
+        );
+        check(
+            "layer_difference",
+            (0..=255).contains(&self.layer_difference),
+            "[0,255]",
+            &self.layer_difference,
+        );
+        check(
+            "corner_threshold",
+            (0..=180).contains(&self.corner_threshold),
+            "[0,180]",
+            &self.corner_threshold,
+        );
+        check(
+            "length_threshold",
+            (3.5..=10.0).contains(&self.length_threshold),
+            "[3.5,10]",
+            &self.length_threshold,
+        );
+        check(
+            "splice_threshold",
+            (0..=180).contains(&self.splice_threshold),
+            "[0,180]",
+            &self.splice_threshold,
+        );
+        check(
+            "primitive_tolerance",
+            (0.1..=10.0).contains(&self.primitive_tolerance),
+            "[0.1,10]",
+            &self.primitive_tolerance,
+        );
+        check(
+            "snap_angle",
+            (0..=20).contains(&self.snap_angle),
+            "[0,20]",
+            &self.snap_angle,
+        );
+        check(
+            "arc_tolerance",
+            (0.1..=10.0).contains(&self.arc_tolerance),
+            "[0.1,10]",
+            &self.arc_tolerance,
+        );
+        check(
+            "gradient_tolerance",
+            (1.0..=64.0).contains(&self.gradient_tolerance),
+            "[1,64]",
+            &self.gradient_tolerance,
+        );
+        check(
+            "hybrid_threshold",
+            (1..=1024).contains(&self.hybrid_threshold),
+            "[1,1024]",
+            &self.hybrid_threshold,
+        );
+        check(
+            "alpha_threshold",
+            self.alpha_threshold >= 1,
+            "[1,255]",
+            &self.alpha_threshold,
+        );
+
+        if let Some(grid) = self.grid {
+            check("grid", grid > 0.0, "(0,∞)", &grid);
+        }
+
+        if let Some(colors) = self.colors {
+            check("colors", (1..=256).contains(&colors), "[1,256]", &colors);
+        }
+
+        if self.palette.as_ref().is_some_and(Palette::is_empty) {
+            errors.push(ConfigError {
+                field: "palette",
+                message: String::from("must contain at least one color"),
+            });
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
     pub(crate) fn into_converter_config(self) -> ConverterConfig {
         ConverterConfig {
             color_mode: self.color_mode,
-            hierarchical: self.hierarchical,
+            hierarchical: if self.alpha {
+                Hierarchical::Cutout
+            } else {
+                self.hierarchical
+            },
             filter_speckle_area: self.filter_speckle * self.filter_speckle,
             color_precision_loss: 8 - self.color_precision,
             layer_difference: self.layer_difference,
-            mode: self.mode,
+            mode: if self.pixel_art {
+                PathSimplifyMode::None
+            } else {
+                self.mode
+            },
             corner_threshold: deg2rad(self.corner_threshold),
             length_threshold: self.length_threshold,
             max_iterations: self.max_iterations,
             splice_threshold: deg2rad(self.splice_threshold),
             path_precision: self.path_precision,
+            pixel_art: self.pixel_art,
+            palette: self.palette,
+            colors: self.colors,
+            quantize_method: self.quantize_method,
+            quantize_space: self.quantize_space,
+            color_space: self.color_space,
+            centerline: self.centerline,
+            primitives: self.primitives,
+            primitive_tolerance: self.primitive_tolerance,
+            orthogonal: self.orthogonal,
+            snap_angle: deg2rad(self.snap_angle),
+            grid: self.grid,
+            arcs: self.arcs,
+            arc_tolerance: self.arc_tolerance,
+            gradients: self.gradients,
+            gradient_tolerance: self.gradient_tolerance,
+            hybrid: self.hybrid,
+            hybrid_threshold: self.hybrid_threshold,
+            raster_format: self.raster_format,
+            alpha: self.alpha,
+            alpha_threshold: self.alpha_threshold,
+            background: self.background,
+            background_tolerance: self.background_tolerance,
+            trim: self.trim,
+            trim_padding: self.trim_padding,
         }
     }
 }
 
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.field, self.message)
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
 fn deg2rad(deg: i32) -> f64 {
     deg as f64 / 180.0 * std::f64::consts::PI
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn invalid_fields(config: &Config) -> Vec<&'static str> {
+        match config.validate() {
+            Ok(()) => vec![],
+            Err(errors) => errors.into_iter().map(|error| error.field).collect(),
+        }
+    }
+
+    #[test]
+    fn defaults_and_presets_are_valid() {
+        for config in [
+            Config::default(),
+            Config::from_preset(Preset::Bw),
+            Config::from_preset(Preset::Poster),
+            Config::from_preset(Preset::Photo),
+        ] {
+            assert_eq!(invalid_fields(&config), Vec::<&str>::new());
+        }
+    }
+
+    #[test]
+    fn each_field_reports_its_own_error() {
+        type Invalidate = fn(&mut Config);
+        let cases: [(&str, Invalidate); 15] = [
+            ("filter_speckle", |c| c.filter_speckle = 17),
+            ("color_precision", |c| c.color_precision = 0),
+            ("layer_difference", |c| c.layer_difference = 256),
+            ("corner_threshold", |c| c.corner_threshold = -1),
+            ("length_threshold", |c| c.length_threshold = 3.0),
+            ("splice_threshold", |c| c.splice_threshold = 181),
+            ("primitive_tolerance", |c| c.primitive_tolerance = 0.0),
+            ("snap_angle", |c| c.snap_angle = 21),
+            ("arc_tolerance", |c| c.arc_tolerance = 11.0),
+            ("gradient_tolerance", |c| c.gradient_tolerance = 0.5),
+            ("hybrid_threshold", |c| c.hybrid_threshold = 0),
+            ("alpha_threshold", |c| c.alpha_threshold = 0),
+            ("grid", |c| c.grid = Some(0.0)),
+            ("colors", |c| c.colors = Some(257)),
+            ("palette", |c| c.palette = Some(Palette::new(vec![]))),
+        ];
+        for (field, invalidate) in cases {
+            let mut config = Config::default();
+            invalidate(&mut config);
+            assert_eq!(invalid_fields(&config), [field]);
+        }
+    }
+
+    #[test]
+    fn several_errors_at_once() {
+        let config = Config {
+            filter_speckle: 100,
+            color_precision: 9,
+            snap_angle: -1,
+            colors: Some(0),
+            ..Config::default()
+        };
+        assert_eq!(
+            invalid_fields(&config),
+            ["filter_speckle", "color_precision", "snap_angle", "colors"]
+        );
+        let errors = config.validate().unwrap_err();
+        assert_eq!(
+            errors[1].message,
+            "9 is out of range, it must be within [1,8]"
+        );
+    }
+}
@@ -1,24 +1,67 @@
+use std::io::Cursor;
 use std::path::Path;
 use std::{fs::File, io::Write};
 
+use super::analysis::pixel_scale;
+use super::arc::fit_arcs;
+use super::background::remove_background;
+use super::centerline::trace_centerlines;
+use super::color_space::ColorSpace;
 use super::config::{ColorMode, Config, ConverterConfig, Hierarchical};
+use super::gradient::fit_gradients;
+use super::hybrid::embed_crowded_regions;
+use super::orthogonal::orthogonalize;
+use super::palette::Palette;
+use super::primitive::detect_primitives;
+use super::quantize::{quantize, remap};
+use super::stats::{ConversionStats, Stopwatch};
 use super::svg::SvgFile;
-use fastrand::Rng;
-use visioncortex::color_clusters::{KeyingAction, Runner, RunnerConfig, HIERARCHICAL_MAX};
-use visioncortex::{Color, ColorImage, ColorName};
+use image::{io::Reader, DynamicImage};
+use visioncortex::color_clusters::{
+    color_diff, color_same, Clusters, KeyingAction, Runner, RunnerConfig, HIERARCHICAL_MAX,
+};
+use visioncortex::{Color, ColorImage, ColorName, PathSimplifyMode};
 
-const NUM_UNUSED_COLOR_ITERATIONS: usize = 6;
 /// The fraction of pixels in the top/bottom rows of the image that need to be transparent before
 /// the entire image will be keyed.
 const KEYING_THRESHOLD: f32 = 0.2;
 
 /// Convert an in-memory image into an in-memory SVG
 pub fn convert(img: ColorImage, config: Config) -> Result<SvgFile, String> {
+    convert_with_stats(img, config).map(|(svg, _)| svg)
+}
+
+/// Convert an in-memory image into an in-memory SVG, measuring the conversion along the way
+pub fn convert_with_stats(
+    img: ColorImage,
+    config: Config,
+) -> Result<(SvgFile, ConversionStats), String> {
     let config = config.into_converter_config();
-    match config.color_mode {
-        ColorMode::Color => color_image_to_svg(img, config),
-        ColorMode::Binary => binary_image_to_svg(img, config),
+    let trim = config.trim.then_some(config.trim_padding);
+    let mut stats = ConversionStats::default();
+    let scale = if config.pixel_art {
+        pixel_scale(&img)
+    } else {
+        1
+    };
+    let img = if scale > 1 {
+        downsample(&img, scale)
+    } else {
+        img
+    };
+    let mut svg = match config.color_mode {
+        ColorMode::Color => color_image_to_svg(img, config, &mut stats),
+        ColorMode::Binary if config.centerline => centerline_image_to_svg(img, config, &mut stats),
+        ColorMode::Binary => binary_image_to_svg(img, config, &mut stats),
+    }?;
+    if scale > 1 {
+        svg.scale(scale);
     }
+    if let Some(padding) = trim {
+        svg.trim(padding);
+    }
+    stats.record_output(&svg);
+    Ok((svg, stats))
 }
 
 /// Convert an image file into svg file
@@ -32,41 +75,53 @@ pub fn convert_image_to_svg(
     write_svg(svg, output_path)
 }
 
-fn color_exists_in_image(img: &ColorImage, color: Color) -> bool {
-    for y in 0..img.height {
-        for x in 0..img.width {
-            let pixel_color = img.get_pixel(x, y);
-            if pixel_color.r == color.r && pixel_color.g == color.g && pixel_color.b == color.b {
-                return true;
-            }
+/// Shrink an image made of `scale` by `scale` blocks of equal pixels to one pixel per block
+fn downsample(img: &ColorImage, scale: usize) -> ColorImage {
+    let mut small = ColorImage::new_w_h(img.width / scale, img.height / scale);
+    for y in 0..small.height {
+        for x in 0..small.width {
+            small.set_pixel(x, y, &img.get_pixel(x * scale, y * scale));
         }
     }
-    false
+    small
 }
 
-fn find_unused_color_in_image(img: &ColorImage) -> Result<Color, String> {
-    let special_colors = IntoIterator::into_iter([
+/// A color with no pixel of `img` in it, to key transparent pixels with. The same image always
+/// gets the same color: a few easily told apart ones are tried first, then the lowest unused one.
+pub fn find_unused_color_in_image(img: &ColorImage) -> Result<Color, String> {
+    // one bit per RGB color, set for those in the image
+    let mut used = vec![0u64; (1 << 24) / 64];
+    for pixel in img.pixels.chunks_exact(4) {
+        let rgb = u32::from_be_bytes([0, pixel[0], pixel[1], pixel[2]]) as usize;
+        used[rgb / 64] |= 1 << (rgb % 64);
+    }
+    let is_used = |color: &Color| {
+        let rgb = u32::from_be_bytes([0, color.r, color.g, color.b]) as usize;
+        used[rgb / 64] & (1 << (rgb % 64)) != 0
+    };
+
+    let special_colors = [
         Color::new(255, 0, 0),
         Color::new(0, 255, 0),
         Color::new(0, 0, 255),
         Color::new(255, 255, 0),
         Color::new(0, 255, 255),
         Color::new(255, 0, 255),
-    ]);
-    let mut rng = Rng::new();
-    let random_colors =
-        (0..NUM_UNUSED_COLOR_ITERATIONS).map(|_| Color::new(rng.u8(..), rng.u8(..), rng.u8(..)));
-    for color in special_colors.chain(random_colors) {
-        if !color_exists_in_image(img, color) {
-            return Ok(color);
-        }
+    ];
+    if let Some(color) = special_colors.into_iter().find(|color| !is_used(color)) {
+        return Ok(color);
     }
-    Err(String::from(
-        "unable to find unused color in image to use as key",
-    ))
+    used.iter()
+        .enumerate()
+        .find(|(_, &bits)| bits != !0)
+        .map(|(i, &bits)| i * 64 + (!bits).trailing_zeros() as usize)
+        .map(|rgb| Color::new((rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8))
+        .ok_or_else(|| String::from("unable to find unused color in image to use as key"))
 }
 
-fn should_key_image(img: &ColorImage) -> bool {
+/// Whether enough pixels are more transparent than `background` to key them out, where a
+/// `background` of 1 counts only the fully transparent ones
+pub fn should_key_image(img: &ColorImage, background: u8) -> bool {
     if img.width == 0 || img.height == 0 {
         return false;
     }
@@ -83,7 +138,7 @@ fn should_key_image(img: &ColorImage) -> bool {
     ];
     for y in y_positions {
         for x in 0..img.width {
-            if img.get_pixel(x, y).a == 0 {
+            if img.get_pixel(x, y).a < background {
                 num_transparent_pixels += 1;
             }
             if num_transparent_pixels >= threshold {
@@ -95,15 +150,66 @@ fn should_key_image(img: &ColorImage) -> bool {
     false
 }
 
-fn color_image_to_svg(mut img: ColorImage, config: ConverterConfig) -> Result<SvgFile, String> {
+fn color_image_to_svg(
+    mut img: ColorImage,
+    config: ConverterConfig,
+    stats: &mut ConversionStats,
+) -> Result<SvgFile, String> {
     let width = img.width;
     let height = img.height;
 
-    let key_color = if should_key_image(&img) {
+    // the background is made transparent to be keyed out with the rest, before taking up a color
+    let removed_background = config.background.is_some_and(|background| {
+        remove_background(&mut img, background, config.background_tolerance)
+    });
+
+    // the colors the output is restricted to
+    let inks = match config.colors {
+        Some(num_colors) => {
+            let mut colors = quantize(
+                &img,
+                num_colors,
+                config.quantize_method,
+                config.quantize_space,
+            );
+            if let Some(palette) = &config.palette {
+                let mut snapped: Vec<Color> = vec![];
+                for color in colors.iter().map(|color| palette.nearest(color)) {
+                    if !snapped.contains(&color) {
+                        snapped.push(color);
+                    }
+                }
+                colors = snapped;
+            }
+            remap(&mut img, &colors, config.quantize_space);
+            Some(Palette::new(colors))
+        }
+        None => {
+            if let Some(palette) = &config.palette {
+                palette.snap_image(&mut img);
+            }
+            config.palette.clone()
+        }
+    };
+
+    // gradients are fitted to the image as clustered, unless its colors are restricted, and
+    // pictures are embedded from it before transparent pixels are keyed
+    let fit_gradients_to_source = config.gradients && inks.is_none();
+    let source = (config.hybrid || fit_gradients_to_source).then(|| img.clone());
+
+    // pixels more transparent than this are background, only the fully transparent ones unless
+    // partial transparency is kept
+    let background = if config.alpha {
+        config.alpha_threshold
+    } else {
+        1
+    };
+    let stopwatch = Stopwatch::start();
+    let key_color = if removed_background || should_key_image(&img, background) {
         let key_color = find_unused_color_in_image(&img)?;
         for y in 0..height {
             for x in 0..width {
-                if img.get_pixel(x, y).a == 0 {
+                if img.get_pixel(x, y).a < background {
                     img.set_pixel(x, y, &key_color);
                 }
             }
@@ -113,7 +219,9 @@ fn color_image_to_svg(mut img: ColorImage, config: ConverterConfig) -> Result<Sv
         // The default color is all zeroes, which is treated by visioncortex as a special value meaning no keying will be applied.
         Color::default()
     };
+    stats.stage_times.keying = stopwatch.elapsed();
 
+    let stopwatch = Stopwatch::start();
     let runner = Runner::new(
         RunnerConfig {
             diagonal: config.layer_difference == 0,
@@ -135,8 +243,16 @@ fn color_image_to_svg(mut img: ColorImage, config: ConverterConfig) -> Result<Sv
         img,
     );
 
-    let mut clusters = runner.run();
+    let mut clusters = run_clustering(
+        runner,
+        config.color_space,
+        config.color_precision_loss,
+        config.alpha,
+    );
+    stats.clusters_found = clusters.view().clusters.len();
+    stats.stage_times.clustering = stopwatch.elapsed();
 
+    let stopwatch = Stopwatch::start();
     match config.hierarchical {
         Hierarchical::Stacked => {}
         Hierarchical::Cutout => {
@@ -148,7 +264,7 @@ fn color_image_to_svg(mut img: ColorImage, config: ConverterConfig) -> Result<Sv
                     hierarchical: 64,
                     batch_size: 25600,
                     good_min_area: 0,
-                    good_max_area: (image.width * image.height) as usize,
+                    good_max_area: image.width * image.height,
                     is_same_color_a: 0,
                     is_same_color_b: 1,
                     deepen_diff: 0,
@@ -158,10 +274,12 @@ fn color_image_to_svg(mut img: ColorImage, config: ConverterConfig) -> Result<Sv
                 },
                 image,
             );
-            clusters = runner.run();
+            clusters = run_clustering(runner, ColorSpace::Rgb, 0, config.alpha);
         }
     }
+    stats.stage_times.reclustering = stopwatch.elapsed();
 
+    let stopwatch = Stopwatch::start();
     let view = clusters.view();
 
     let mut svg = SvgFile::new(width, height, config.path_precision);
@@ -176,20 +294,93 @@ fn color_image_to_svg(mut img: ColorImage, config: ConverterConfig) -> Result<Sv
             config.max_iterations,
             config.splice_threshold,
         );
-        svg.add_path(paths, cluster.residue_color());
+        let mut color = cluster.residue_color();
+        if !config.alpha {
+            color.a = 255;
+        }
+        svg.add_path(paths, color);
     }
+    if let Some(source) = &source {
+        let mut regions: Vec<&[u32]> = view
+            .clusters_output
+            .iter()
+            .rev()
+            .map(|&cluster_index| view.get_cluster(cluster_index).indices.as_slice())
+            .collect();
+        if config.hybrid {
+            embed_crowded_regions(
+                &mut svg,
+                &mut regions,
+                source,
+                config.hybrid_threshold,
+                config.raster_format,
+            )?;
+        }
+        if fit_gradients_to_source {
+            fit_gradients(&mut svg, &regions, source, config.gradient_tolerance);
+        }
+    }
+    refine_shapes(&mut svg, &config);
+    if let Some(inks) = &inks {
+        inks.snap_svg(&mut svg);
+    }
+    stats.stage_times.vectorizing = stopwatch.elapsed();
 
     Ok(svg)
 }
 
-fn binary_image_to_svg(img: ColorImage, config: ConverterConfig) -> Result<SvgFile, String> {
+/// Cluster the image of `runner`, comparing colors in `space` with the same `precision_loss` as
+/// visioncortex does in RGB, and by alpha as well if `alpha`
+fn run_clustering(runner: Runner, space: ColorSpace, precision_loss: i32, alpha: bool) -> Clusters {
+    match (space, alpha) {
+        (ColorSpace::Rgb, false) => runner.run(),
+        (ColorSpace::Rgb, true) => runner
+            .builder()
+            .same(move |a, b| {
+                color_same(a, b, precision_loss, 1)
+                    && ((a.a >> precision_loss) as i32 - (b.a >> precision_loss) as i32).abs() <= 1
+            })
+            .diff(move |a, b| color_diff(a, b) + (a.a as i32 - b.a as i32).abs())
+            .run(),
+        (space, alpha) => {
+            // compare colors by perceived difference instead of per channel, with a difference
+            // in alpha from opaque to clear as large as from black to white
+            let delta_e = move |a: &Color, b: &Color| {
+                let opacity = if alpha {
+                    (a.a as f64 - b.a as f64).abs() * 100.0 / 255.0
+                } else {
+                    0.0
+                };
+                space.delta_e(a, b) + opacity
+            };
+            let tolerance = (1 << precision_loss) as f64;
+            runner
+                .builder()
+                .same(move |a, b| delta_e(&a, &b) <= tolerance)
+                .diff(move |a, b| delta_e(&a, &b).round() as i32)
+                .run()
+        }
+    }
+}
+
+fn binary_image_to_svg(
+    img: ColorImage,
+    config: ConverterConfig,
+    stats: &mut ConversionStats,
+) -> Result<SvgFile, String> {
+    let stopwatch = Stopwatch::start();
     let img = img.to_binary_image(|x| x.r < 128);
     let width = img.width;
     let height = img.height;
 
     let clusters = img.to_clusters(false);
+    stats.clusters_found = clusters.len();
+    stats.stage_times.clustering = stopwatch.elapsed();
+
+    let stopwatch = Stopwatch::start();
 
     let mut svg = SvgFile::new(width, height, config.path_precision);
+    let mut dropped = 0;
     for i in 0..clusters.len() {
         let cluster = clusters.get_cluster(i);
         if cluster.size() >= config.filter_speckle_area {
@@ -201,37 +392,244 @@ fn binary_image_to_svg(img: ColorImage, config: ConverterConfig) -> Result<SvgFi
                 config.splice_threshold,
             );
             svg.add_path(paths, Color::color(&ColorName::Black));
+        } else {
+            dropped += 1;
         }
     }
+    stats.paths_dropped = Some(dropped);
+    refine_shapes(&mut svg, &config);
+    if let Some(palette) = &config.palette {
+        palette.snap_svg(&mut svg);
+    }
+    stats.stage_times.vectorizing = stopwatch.elapsed();
 
     Ok(svg)
 }
 
-fn read_image(input_path: &Path) -> Result<ColorImage, String> {
+fn centerline_image_to_svg(
+    img: ColorImage,
+    config: ConverterConfig,
+    stats: &mut ConversionStats,
+) -> Result<SvgFile, String> {
+    let stopwatch = Stopwatch::start();
+    let img = img.to_binary_image(|x| x.r < 128);
+    let width = img.width;
+    let height = img.height;
+
+    let clusters = img.to_clusters(false);
+    stats.clusters_found = clusters.len();
+    let mut mask = vec![false; width * height];
+    let mut dropped = 0;
+    for i in 0..clusters.len() {
+        let cluster = clusters.get_cluster(i);
+        if cluster.size() >= config.filter_speckle_area {
+            for point in cluster.points.iter() {
+                mask[point.y as usize * width + point.x as usize] = true;
+            }
+        } else {
+            dropped += 1;
+        }
+    }
+    stats.paths_dropped = Some(dropped);
+    stats.stage_times.clustering = stopwatch.elapsed();
+
+    let stopwatch = Stopwatch::start();
+    let mut svg = SvgFile::new(width, height, config.path_precision);
+    for stroke in trace_centerlines(&mask, width, height, config.mode, config.corner_threshold) {
+        svg.add_stroke(stroke.path, Color::color(&ColorName::Black), stroke.width);
+    }
+    refine_shapes(&mut svg, &config);
+    match &config.palette {
+        Some(palette) => palette.snap_svg(&mut svg),
+        None => svg.merge_same_color_paths(),
+    }
+    stats.stage_times.vectorizing = stopwatch.elapsed();
+
+    Ok(svg)
+}
+
+/// Straighten polygons, replace outlines by primitive shapes and fit arcs to curves, as configured
+fn refine_shapes(svg: &mut SvgFile, config: &ConverterConfig) {
+    if matches!(config.mode, PathSimplifyMode::Polygon)
+        && (config.orthogonal || config.grid.is_some())
+    {
+        orthogonalize(
+            svg,
+            config.orthogonal.then_some(config.snap_angle),
+            config.grid,
+        );
+    }
+    if config.primitives {
+        detect_primitives(svg, config.primitive_tolerance);
+    }
+    if config.arcs && matches!(config.mode, PathSimplifyMode::Spline) {
+        fit_arcs(svg, config.arc_tolerance);
+    }
+}
+
+/// Read an image file into an in-memory image
+pub fn read_image(input_path: &Path) -> Result<ColorImage, String> {
     let img = image::open(input_path);
     let img = match img {
-        Ok(file) => file.to_rgba8(),
-        Err(_) => return Err(String::from("No image file found at specified input path")),
+        Ok(file) => file,
+        Err(image::ImageError::IoError(_)) => {
+            return Err(String::from("No image file found at specified input path"))
+        }
+        Err(err) => return Err(format!("Failed to decode image: {}", err)),
     };
 
+    Ok(to_color_image(img))
+}
+
+/// Decode an encoded image (png, jpg...) held in memory, detecting its format from the magic bytes
+pub fn read_image_from_memory(bytes: &[u8]) -> Result<ColorImage, String> {
+    let img = Reader::new(Cursor::new(bytes))
+        .with_guessed_format()
+        .map_err(|_| String::from("Unrecognized image format"))?
+        .decode()
+        .map_err(|_| String::from("Failed to decode image"))?;
+
+    Ok(to_color_image(img))
+}
+
+fn to_color_image(img: DynamicImage) -> ColorImage {
+    let img = img.to_rgba8();
     let (width, height) = (img.width() as usize, img.height() as usize);
-    let img = ColorImage {
+    ColorImage {
         pixels: img.as_raw().to_vec(),
         width,
         height,
-    };
-
-    Ok(img)
+    }
 }
 
-fn write_svg(svg: SvgFile, output_path: &Path) -> Result<(), String> {
+/// Write an in-memory SVG to a file
+pub fn write_svg(svg: SvgFile, output_path: &Path) -> Result<(), String> {
     let out_file = File::create(output_path);
     let mut out_file = match out_file {
         Ok(file) => file,
         Err(_) => return Err(String::from("Cannot create output file.")),
     };
 
-    write!(&mut out_file, "{}", svg).expect("failed to write file.");
+    write!(&mut out_file, "{}", svg).map_err(|_| String::from("Failed to write output file."))
+}
+
+/// Write an in-memory image to a PNG file
+pub fn write_png(img: &ColorImage, output_path: &Path) -> Result<(), String> {
+    image::save_buffer_with_format(
+        output_path,
+        &img.pixels,
+        img.width as u32,
+        img.height as u32,
+        image::ColorType::Rgba8,
+        image::ImageFormat::Png,
+    )
+    .map_err(|err| format!("Failed to write image: {}", err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    Ok(())
+    /// A 16 by 16 image of `colors` repeating along the rows, with a transparent right half
+    fn image_of(colors: &[Color]) -> ColorImage {
+        let mut image = ColorImage::new_w_h(16, 16);
+        for y in 0..16 {
+            for x in 0..8 {
+                image.set_pixel(x, y, &colors[(y * 8 + x) % colors.len()]);
+            }
+        }
+        image
+    }
+
+    #[test]
+    fn unused_color_is_deterministic() {
+        let image = image_of(&[Color::new(12, 34, 56), Color::new(255, 0, 0)]);
+        let key = find_unused_color_in_image(&image).unwrap();
+        assert_eq!(key, Color::new(0, 255, 0));
+        for _ in 0..3 {
+            assert_eq!(find_unused_color_in_image(&image).unwrap(), key);
+        }
+    }
+
+    #[test]
+    fn unused_color_avoids_the_special_colors_in_use() {
+        let special_colors = [
+            Color::new(255, 0, 0),
+            Color::new(0, 255, 0),
+            Color::new(0, 0, 255),
+            Color::new(255, 255, 0),
+            Color::new(0, 255, 255),
+            Color::new(255, 0, 255),
+        ];
+        // the transparent pixels are black, so the lowest unused color is the next one
+        let image = image_of(&special_colors);
+        let key = find_unused_color_in_image(&image).unwrap();
+        assert_eq!(key, Color::new(0, 0, 1));
+
+        let mut image = image_of(&[Color::new(0, 0, 0), Color::new(0, 0, 1)]);
+        for (i, color) in special_colors.iter().enumerate() {
+            image.set_pixel(8 + i, 0, color);
+        }
+        let key = find_unused_color_in_image(&image).unwrap();
+        assert_eq!(key, Color::new(0, 0, 2));
+    }
+
+    #[test]
+    fn lab_merges_colors_close_in_lightness() {
+        // light grays 15 apart per channel, but only about 5.6 apart in ΔE*ab
+        let mut image = ColorImage::new_w_h(32, 32);
+        for y in 0..32 {
+            for x in 0..32 {
+                let value = if x < 16 { 240 } else { 255 };
+                image.set_pixel(x, y, &Color::new(value, value, value));
+            }
+        }
+        let paths = |color_space| {
+            let config = Config {
+                color_space,
+                layer_difference: 16,
+                ..Config::default()
+            };
+            convert(image.clone(), config).unwrap().paths.len()
+        };
+        assert_eq!(paths(ColorSpace::Rgb), 2);
+        assert_eq!(paths(ColorSpace::Lab), 1);
+        assert_eq!(paths(ColorSpace::Oklab), 1);
+    }
+
+    #[test]
+    fn alpha_keeps_partial_transparency() {
+        // opaque, half transparent and nearly clear stripes of one color
+        let mut image = ColorImage::new_w_h(24, 24);
+        for y in 0..24 {
+            for x in 0..24 {
+                let alpha = [255, 128, 8][x / 8];
+                image.set_pixel(x, y, &Color::new_rgba(30, 60, 200, alpha));
+            }
+        }
+        let opacities = |alpha, alpha_threshold| {
+            let config = Config {
+                alpha,
+                alpha_threshold,
+                ..Config::default()
+            };
+            let svg = convert(image.clone(), config).unwrap();
+            let mut opacities: Vec<u8> = svg.paths.iter().map(|path| path.color.a).collect();
+            opacities.sort_unstable();
+            (opacities, svg.to_string())
+        };
+
+        let (kept, svg) = opacities(true, 16);
+        assert_eq!(kept, [128, 255]);
+        assert!(svg.contains("fill-opacity=\"0.502\""));
+
+        // a lower threshold keeps the nearly clear stripe too
+        let (kept, svg) = opacities(true, 4);
+        assert_eq!(kept, [8, 128, 255]);
+        assert!(svg.contains("fill-opacity=\"0.031\""));
+
+        let (kept, svg) = opacities(false, 16);
+        assert!(kept.iter().all(|&alpha| alpha == 255));
+        assert!(!svg.contains("fill-opacity"));
+    }
 }
@@ -0,0 +1,41 @@
+use std::fmt;
+
+pub const EXIT_CODES_HELP: &str = "EXIT CODES:
+    0    Success
+    1    Conversion failed (in batch mode: at least one image failed)
+    2    Invalid arguments or config file
+    3    Input cannot be read or decoded
+    4    Output cannot be written";
+
+/// A failure of the cmd app, each kind exiting with a distinct code
+#[derive(Debug)]
+pub enum CliError {
+    ConversionFailed(String),
+    InvalidArguments(String),
+    UnreadableInput(String),
+    UnwritableOutput(String),
+}
+
+impl CliError {
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Self::ConversionFailed(_) => 1,
+            Self::InvalidArguments(_) => 2,
+            Self::UnreadableInput(_) => 3,
+            Self::UnwritableOutput(_) => 4,
+        }
+    }
+}
+
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            // clap messages are already prefixed with `error:`
+            Self::InvalidArguments(msg) if msg.starts_with("error:") => write!(f, "{}", msg),
+            Self::InvalidArguments(msg) => write!(f, "error: {}", msg),
+            Self::ConversionFailed(msg) => write!(f, "error: Conversion failed: {}", msg),
+            Self::UnreadableInput(msg) => write!(f, "error: Cannot read input: {}", msg),
+            Self::UnwritableOutput(msg) => write!(f, "error: Cannot write output: {}", msg),
+        }
+    }
+}
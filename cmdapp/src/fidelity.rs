@@ -0,0 +1,254 @@
+#[cfg(feature = "serde")]
+use serde::Serialize;
+use visioncortex::{Color, ColorImage};
+
+use super::config::Config;
+use super::converter::convert_with_stats;
+use super::rasterizer::rasterize;
+use super::stats::ConversionStats;
+use super::svg::SvgFile;
+
+/// Side of the square window over which SSIM is computed
+const SSIM_WINDOW: usize = 7;
+const SSIM_C1: f64 = (0.01 * 255.0) * (0.01 * 255.0);
+const SSIM_C2: f64 = (0.03 * 255.0) * (0.03 * 255.0);
+/// PSNR reported for identical images, whose ratio is infinite
+pub const MAX_PSNR: f64 = 100.0;
+
+/// How closely a traced image reproduces its source, returned by [`compare`]
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct Fidelity {
+    /// Peak signal-to-noise ratio over the RGB channels in dB, at most [`MAX_PSNR`]
+    pub psnr: f64,
+    /// Mean structural similarity of the luma channel, up to 1 for identical images
+    pub ssim: f64,
+    /// Per-pixel color error, from black (exact) through red and yellow to white (opposite colors)
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub heatmap: ColorImage,
+}
+
+/// Render `svg` at its original size and measure it against the `source` image it was traced from
+pub fn compare(source: &ColorImage, svg: &SvgFile) -> Result<Fidelity, String> {
+    compare_images(source, &rasterize(svg, 1.0))
+}
+
+/// Trace `img` with `config` and measure the result against it. The fidelity is measured on the
+/// whole canvas, before [`Config::trim`] crops the SVG, so that it lines up with `img`.
+pub fn convert_and_compare(
+    img: &ColorImage,
+    config: Config,
+) -> Result<(SvgFile, ConversionStats, Fidelity), String> {
+    let trim = config.trim.then_some(config.trim_padding);
+    let config = Config {
+        trim: false,
+        ..config
+    };
+    let (mut svg, mut stats) = convert_with_stats(img.clone(), config)?;
+    let fidelity = compare(img, &svg)?;
+    if let Some(padding) = trim {
+        svg.trim(padding);
+        stats.record_output(&svg);
+    }
+    Ok((svg, stats, fidelity))
+}
+
+/// Measure the difference between two images of the same size.
+/// Both images are composited over white, so transparent areas compare equal whatever their color.
+pub fn compare_images(a: &ColorImage, b: &ColorImage) -> Result<Fidelity, String> {
+    if a.width != b.width || a.height != b.height {
+        return Err(format!(
+            "Image sizes differ: {}x{} and {}x{}",
+            a.width, a.height, b.width, b.height
+        ));
+    }
+    let (width, height) = (a.width, a.height);
+    let a = over_white(a);
+    let b = over_white(b);
+
+    let mut heatmap = ColorImage::new_w_h(width, height);
+    let mut squared_error = 0.0;
+    for (i, (pa, pb)) in a.iter().zip(b.iter()).enumerate() {
+        let error: f64 = pa
+            .iter()
+            .zip(pb.iter())
+            .map(|(x, y)| (x - y) * (x - y))
+            .sum();
+        squared_error += error;
+        // the largest possible error is 255 in all three channels
+        let normalized = error.sqrt() / (255.0 * 3f64.sqrt());
+        heatmap.set_pixel(i % width, i / width, &heat_color(normalized));
+    }
+
+    let samples = (a.len() * 3).max(1) as f64;
+    let mse = squared_error / samples;
+    let psnr = (10.0 * (255.0 * 255.0 / mse).log10()).min(MAX_PSNR);
+
+    let luma = |pixels: &[[f64; 3]]| -> Vec<f64> {
+        pixels
+            .iter()
+            .map(|p| 0.299 * p[0] + 0.587 * p[1] + 0.114 * p[2])
+            .collect()
+    };
+    let ssim = ssim(&luma(&a), &luma(&b), width, height);
+
+    Ok(Fidelity {
+        psnr,
+        ssim,
+        heatmap,
+    })
+}
+
+/// The RGB channels of every pixel after alpha blending onto a white background
+fn over_white(image: &ColorImage) -> Vec<[f64; 3]> {
+    image
+        .pixels
+        .chunks_exact(4)
+        .map(|p| {
+            let alpha = p[3] as f64 / 255.0;
+            let blend = |c: u8| c as f64 * alpha + 255.0 * (1.0 - alpha);
+            [blend(p[0]), blend(p[1]), blend(p[2])]
+        })
+        .collect()
+}
+
+/// Mean SSIM over every `SSIM_WINDOW` square window, or over the whole image if it is smaller
+fn ssim(a: &[f64], b: &[f64], width: usize, height: usize) -> f64 {
+    if width == 0 || height == 0 {
+        return 1.0;
+    }
+    let window_w = SSIM_WINDOW.min(width);
+    let window_h = SSIM_WINDOW.min(height);
+
+    let sums_a = SummedArea::new(a.iter().copied(), width, height);
+    let sums_b = SummedArea::new(b.iter().copied(), width, height);
+    let sums_aa = SummedArea::new(a.iter().map(|x| x * x), width, height);
+    let sums_bb = SummedArea::new(b.iter().map(|y| y * y), width, height);
+    let sums_ab = SummedArea::new(a.iter().zip(b).map(|(x, y)| x * y), width, height);
+
+    let n = (window_w * window_h) as f64;
+    let mut total = 0.0;
+    let mut count = 0;
+    for y in 0..=height - window_h {
+        for x in 0..=width - window_w {
+            let rect = (x, y, window_w, window_h);
+            let mean_a = sums_a.sum(rect) / n;
+            let mean_b = sums_b.sum(rect) / n;
+            let var_a = sums_aa.sum(rect) / n - mean_a * mean_a;
+            let var_b = sums_bb.sum(rect) / n - mean_b * mean_b;
+            let covar = sums_ab.sum(rect) / n - mean_a * mean_b;
+            total += ((2.0 * mean_a * mean_b + SSIM_C1) * (2.0 * covar + SSIM_C2))
+                / ((mean_a * mean_a + mean_b * mean_b + SSIM_C1) * (var_a + var_b + SSIM_C2));
+            count += 1;
+        }
+    }
+    total / count as f64
+}
+
+/// Table of the sums of all values above and to the left of each position,
+/// for summing any rectangle in constant time
+struct SummedArea {
+    sums: Vec<f64>,
+    stride: usize,
+}
+
+impl SummedArea {
+    fn new(values: impl Iterator<Item = f64>, width: usize, height: usize) -> Self {
+        let stride = width + 1;
+        let mut sums = vec![0.0; stride * (height + 1)];
+        for (i, value) in values.enumerate() {
+            let (x, y) = (i % width + 1, i / width + 1);
+            sums[y * stride + x] = value + sums[(y - 1) * stride + x] + sums[y * stride + x - 1]
+                - sums[(y - 1) * stride + x - 1];
+        }
+        Self { sums, stride }
+    }
+
+    /// Sum of the values in the rectangle `(x, y, width, height)`
+    fn sum(&self, (x, y, width, height): (usize, usize, usize, usize)) -> f64 {
+        let at = |x: usize, y: usize| self.sums[y * self.stride + x];
+        at(x + width, y + height) - at(x, y + height) - at(x + width, y) + at(x, y)
+    }
+}
+
+/// Map an error from 0 to 1 onto black, red, yellow and white
+fn heat_color(error: f64) -> Color {
+    let channel = |offset: f64| ((error * 3.0 - offset).clamp(0.0, 1.0) * 255.0).round() as u8;
+    Color::new(channel(0.0), channel(1.0), channel(2.0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn solid(width: usize, height: usize, color: Color) -> ColorImage {
+        let mut image = ColorImage::new_w_h(width, height);
+        for y in 0..height {
+            for x in 0..width {
+                image.set_pixel(x, y, &color);
+            }
+        }
+        image
+    }
+
+    fn gray(width: usize, height: usize) -> ColorImage {
+        solid(width, height, Color::new(100, 100, 100))
+    }
+
+    #[test]
+    fn identical_images() {
+        let image = gray(10, 10);
+        let fidelity = compare_images(&image, &image).unwrap();
+        assert_eq!(fidelity.psnr, MAX_PSNR);
+        assert!((fidelity.ssim - 1.0).abs() < 1e-9);
+        assert_eq!(fidelity.heatmap.get_pixel(5, 5), Color::new(0, 0, 0));
+    }
+
+    #[test]
+    fn one_pixel_difference() {
+        let a = gray(4, 4);
+        let mut b = gray(4, 4);
+        b.set_pixel(1, 2, &Color::new(110, 100, 100));
+        let fidelity = compare_images(&a, &b).unwrap();
+
+        // a single channel off by 10, over 16 pixels of 3 channels
+        let mse: f64 = 10.0 * 10.0 / 48.0;
+        let expected = 10.0 * (255.0 * 255.0 / mse).log10();
+        assert!((fidelity.psnr - expected).abs() < 1e-9, "{}", fidelity.psnr);
+        assert!(fidelity.ssim < 1.0);
+        assert_ne!(fidelity.heatmap.get_pixel(1, 2), Color::new(0, 0, 0));
+        assert_eq!(fidelity.heatmap.get_pixel(0, 0), Color::new(0, 0, 0));
+    }
+
+    #[test]
+    fn transparent_pixels_compare_as_white() {
+        let a = ColorImage::new_w_h(3, 3);
+        let b = solid(3, 3, Color::new(255, 255, 255));
+        assert_eq!(compare_images(&a, &b).unwrap().psnr, MAX_PSNR);
+    }
+
+    #[test]
+    fn sizes_must_match() {
+        assert!(compare_images(&gray(2, 3), &gray(3, 2)).is_err());
+    }
+
+    #[test]
+    fn trimmed_conversion_is_measured_on_the_whole_image() {
+        let mut image = ColorImage::new_w_h(40, 40);
+        for y in 10..30 {
+            for x in 10..30 {
+                image.set_pixel(x, y, &Color::new(200, 30, 30));
+            }
+        }
+        let config = Config {
+            trim: true,
+            trim_padding: 1,
+            ..Config::default()
+        };
+        let (svg, stats, fidelity) = convert_and_compare(&image, config).unwrap();
+        assert!(svg.width < 40 && svg.height < 40);
+        assert_eq!((fidelity.heatmap.width, fidelity.heatmap.height), (40, 40));
+        assert!(fidelity.psnr > 30.0, "{}", fidelity.psnr);
+        assert_eq!(stats.output_bytes, svg.to_string().len());
+    }
+}
@@ -0,0 +1,594 @@
+use std::fmt;
+
+use visioncortex::{Color, ColorImage, NumberFormat, PointF64};
+
+use super::rasterizer::{distance, rasterize};
+use super::svg::SvgFile;
+
+/// Regions smaller than this many pixels keep their flat color
+const MIN_AREA: usize = 256;
+/// Number of bins the pixels of a region are averaged in along its gradient
+const NUM_BINS: usize = 32;
+/// Largest share of the pixels of a path that may lie outside the outline of the gradient under
+/// it for the path to be removed
+const MAX_OUTSIDE: f64 = 0.01;
+/// Largest difference of a color channel that dropping a stop of a gradient may cause
+const STOP_TOLERANCE: f64 = 1.0;
+
+/// How the colors of a [`Gradient`] are laid out on the canvas
+#[derive(Debug, Clone, PartialEq)]
+pub enum GradientShape {
+    /// Colors change along the line from `from` to `to`, and stay the same across it
+    Linear { from: PointF64, to: PointF64 },
+    /// Colors change with the distance from `center`, up to `radius`
+    Radial { center: PointF64, radius: f64 },
+}
+
+/// A fill whose color changes smoothly across its path, written as `<linearGradient>` or
+/// `<radialGradient>`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Gradient {
+    pub shape: GradientShape,
+    /// Offsets from 0 to 1 along the shape in increasing order, and their colors. Points before
+    /// the first stop or after the last one take its color.
+    pub stops: Vec<(f64, Color)>,
+}
+
+impl Gradient {
+    /// Enlarge the gradient by `factor` about the origin
+    pub(crate) fn scale(&mut self, factor: f64) {
+        match &mut self.shape {
+            GradientShape::Linear { from, to } => {
+                *from = PointF64::new(from.x * factor, from.y * factor);
+                *to = PointF64::new(to.x * factor, to.y * factor);
+            }
+            GradientShape::Radial { center, radius } => {
+                *center = PointF64::new(center.x * factor, center.y * factor);
+                *radius *= factor;
+            }
+        }
+    }
+
+    /// Move the gradient by `offset`
+    pub(crate) fn translate(&mut self, offset: PointF64) {
+        let translate = |p: &mut PointF64| *p = PointF64::new(p.x + offset.x, p.y + offset.y);
+        match &mut self.shape {
+            GradientShape::Linear { from, to } => {
+                translate(from);
+                translate(to);
+            }
+            GradientShape::Radial { center, .. } => translate(center),
+        }
+    }
+
+    /// The color painted at `p`
+    pub fn color_at(&self, p: PointF64) -> Color {
+        let offset = match self.shape {
+            GradientShape::Linear { from, to } => {
+                let (dx, dy) = (to.x - from.x, to.y - from.y);
+                ((p.x - from.x) * dx + (p.y - from.y) * dy) / (dx * dx + dy * dy)
+            }
+            GradientShape::Radial { center, radius } => distance(center, p) / radius,
+        };
+        let rgb = interpolate(&self.stops, offset, |color| {
+            [color.r as f64, color.g as f64, color.b as f64]
+        });
+        Color::new(
+            rgb[0].round() as u8,
+            rgb[1].round() as u8,
+            rgb[2].round() as u8,
+        )
+    }
+
+    /// Write the gradient as an SVG element with id `id`, in the coordinates of the canvas of
+    /// a path written with the inverse of `transform`
+    pub(crate) fn fmt_with_precision(
+        &self,
+        f: &mut fmt::Formatter,
+        id: &str,
+        transform: &str,
+        precision: Option<u32>,
+    ) -> fmt::Result {
+        let num = |x: f64| f64::number_format(x, precision);
+        let transform = if transform.is_empty() {
+            String::new()
+        } else {
+            format!(" gradientTransform=\"{}\"", transform)
+        };
+        let element = match self.shape {
+            GradientShape::Linear { from, to } => {
+                writeln!(
+                    f,
+                    "<linearGradient id=\"{}\" gradientUnits=\"userSpaceOnUse\" x1=\"{}\" y1=\"{}\" x2=\"{}\" y2=\"{}\"{}>",
+                    id,
+                    num(from.x),
+                    num(from.y),
+                    num(to.x),
+                    num(to.y),
+                    transform
+                )?;
+                "linearGradient"
+            }
+            GradientShape::Radial { center, radius } => {
+                writeln!(
+                    f,
+                    "<radialGradient id=\"{}\" gradientUnits=\"userSpaceOnUse\" cx=\"{}\" cy=\"{}\" r=\"{}\"{}>",
+                    id,
+                    num(center.x),
+                    num(center.y),
+                    num(radius),
+                    transform
+                )?;
+                "radialGradient"
+            }
+        };
+        for (offset, color) in self.stops.iter() {
+            writeln!(
+                f,
+                "<stop offset=\"{}\" stop-color=\"{}\"/>",
+                f64::number_format(*offset, Some(3)),
+                color.to_hex_string()
+            )?;
+        }
+        writeln!(f, "</{}>", element)
+    }
+}
+
+/// Fill the paths of `svg` with a gradient fitted to the pixels of `source` they cover, where
+/// it differs from them by at most `tolerance` (root mean square over the color channels).
+/// `regions` holds the pixel indices each path was traced from. Paths drawn over a gradient
+/// that it already paints are removed; these are the bands a smooth region is traced as.
+pub(crate) fn fit_gradients(
+    svg: &mut SvgFile,
+    regions: &[&[u32]],
+    source: &ColorImage,
+    tolerance: f64,
+) {
+    let mut owner = vec![usize::MAX; source.width * source.height];
+    let mut covered = vec![false; source.width * source.height];
+    let mut errors = vec![0.0f64; source.width * source.height];
+    let mut removed = vec![false; regions.len()];
+    let limit = tolerance * tolerance * 3.0;
+
+    for (i, region) in regions.iter().enumerate() {
+        if removed[i] || region.len() < MIN_AREA {
+            continue;
+        }
+        let pixels: Vec<(PointF64, [f64; 3])> = region
+            .iter()
+            .map(|&index| pixel(source, index as usize))
+            .collect();
+        if flat_error(&pixels) <= limit {
+            continue;
+        }
+        for &index in region.iter() {
+            owner[index as usize] = i;
+        }
+        // the paths traced from within the region, which are drawn after it
+        let inner: Vec<usize> = (i + 1..regions.len())
+            .filter(|&j| !removed[j] && regions[j].iter().all(|&index| owner[index as usize] == i))
+            .collect();
+
+        // fit to all of the region first, then again leaving out the paths that differ from it
+        let mut kept = vec![false; inner.len()];
+        let mut fitted = None;
+        for _ in 0..2 {
+            cover(&mut covered, region, regions, &inner, &kept);
+            let sample: Vec<(PointF64, [f64; 3])> = region
+                .iter()
+                .zip(pixels.iter())
+                .filter(|(&index, _)| !covered[index as usize])
+                .map(|(_, &pixel)| pixel)
+                .collect();
+            let gradient = match fit_gradient(&sample) {
+                Some(gradient) => gradient,
+                None => break,
+            };
+            for (&index, (p, rgb)) in region.iter().zip(pixels.iter()) {
+                errors[index as usize] = squared_error(&gradient, *p, rgb);
+            }
+            for (k, &j) in inner.iter().enumerate() {
+                let error: f64 = regions[j].iter().map(|&index| errors[index as usize]).sum();
+                kept[k] = kept[k] || error > limit * regions[j].len() as f64;
+            }
+            fitted = Some(gradient);
+        }
+        let gradient = match fitted {
+            Some(gradient) => gradient,
+            None => continue,
+        };
+        // kept paths hide the gradient, so it needs to fit the rest only
+        cover(&mut covered, region, regions, &inner, &kept);
+        let visible: Vec<f64> = region
+            .iter()
+            .filter(|&&index| !covered[index as usize])
+            .map(|&index| errors[index as usize])
+            .collect();
+        if visible.is_empty() || visible.iter().sum::<f64>() > limit * visible.len() as f64 {
+            continue;
+        }
+        svg.paths[i].gradient = Some(gradient);
+
+        // the traced outline falls short of the region where it is smoothed, as at the corners
+        // of the canvas, and the paths drawn over it are needed to fill in there
+        let mut outline = SvgFile::new(svg.width, svg.height, None);
+        outline.paths.push(svg.paths[i].clone());
+        let outline = rasterize(&outline, 1.0);
+        for (k, &j) in inner.iter().enumerate() {
+            // a path inside a kept one is painted over that, not over the gradient
+            if kept[k] || regions[j].iter().any(|&index| covered[index as usize]) {
+                continue;
+            }
+            let outside = regions[j]
+                .iter()
+                .filter(|&&index| {
+                    let index = index as usize;
+                    outline
+                        .get_pixel(index % outline.width, index / outline.width)
+                        .a
+                        < 128
+                })
+                .count();
+            if outside as f64 <= MAX_OUTSIDE * regions[j].len() as f64 {
+                removed[j] = true;
+            }
+        }
+    }
+
+    let mut removed = removed.into_iter();
+    svg.paths.retain(|_| !removed.next().unwrap());
+}
+
+/// Mark the pixels of `region` that the `kept` ones of the `inner` paths paint over
+fn cover(covered: &mut [bool], region: &[u32], regions: &[&[u32]], inner: &[usize], kept: &[bool]) {
+    for &index in region.iter() {
+        covered[index as usize] = false;
+    }
+    for (&j, _) in inner.iter().zip(kept.iter()).filter(|(_, &kept)| kept) {
+        for &index in regions[j].iter() {
+            covered[index as usize] = true;
+        }
+    }
+}
+
+/// The center and color of the pixel at `index`
+fn pixel(source: &ColorImage, index: usize) -> (PointF64, [f64; 3]) {
+    let (x, y) = (index % source.width, index / source.width);
+    let color = source.get_pixel(x, y);
+    (
+        PointF64::new(x as f64 + 0.5, y as f64 + 0.5),
+        [color.r as f64, color.g as f64, color.b as f64],
+    )
+}
+
+/// The mean squared error of filling `pixels` with their mean color
+fn flat_error(pixels: &[(PointF64, [f64; 3])]) -> f64 {
+    let n = pixels.len() as f64;
+    (0..3)
+        .map(|c| {
+            let mean = pixels.iter().map(|(_, rgb)| rgb[c]).sum::<f64>() / n;
+            pixels
+                .iter()
+                .map(|(_, rgb)| (rgb[c] - mean) * (rgb[c] - mean))
+                .sum::<f64>()
+                / n
+        })
+        .sum()
+}
+
+fn squared_error(gradient: &Gradient, p: PointF64, rgb: &[f64; 3]) -> f64 {
+    let color = gradient.color_at(p);
+    let predicted = [color.r as f64, color.g as f64, color.b as f64];
+    (0..3)
+        .map(|c| (rgb[c] - predicted[c]) * (rgb[c] - predicted[c]))
+        .sum()
+}
+
+/// The linear or radial gradient that fits `pixels` best
+fn fit_gradient(pixels: &[(PointF64, [f64; 3])]) -> Option<Gradient> {
+    if pixels.len() < MIN_AREA {
+        return None;
+    }
+    let error = |gradient: &Gradient| -> f64 {
+        pixels
+            .iter()
+            .map(|(p, rgb)| squared_error(gradient, *p, rgb))
+            .sum()
+    };
+    let linear = fit_linear(pixels).map(|gradient| {
+        let error = error(&gradient);
+        (gradient, error)
+    });
+    let radial = fit_radial(pixels).map(|gradient| {
+        let error = error(&gradient);
+        (gradient, error)
+    });
+    match (linear, radial) {
+        (Some(linear), Some(radial)) if radial.1 < linear.1 => Some(radial.0),
+        (Some(linear), _) => Some(linear.0),
+        (None, radial) => radial.map(|(gradient, _)| gradient),
+    }
+}
+
+/// A linear gradient along the direction in which the colors change the most
+fn fit_linear(pixels: &[(PointF64, [f64; 3])]) -> Option<Gradient> {
+    let n = pixels.len() as f64;
+    let mean_x = pixels.iter().map(|(p, _)| p.x).sum::<f64>() / n;
+    let mean_y = pixels.iter().map(|(p, _)| p.y).sum::<f64>() / n;
+    let mean_c: Vec<f64> = (0..3)
+        .map(|c| pixels.iter().map(|(_, rgb)| rgb[c]).sum::<f64>() / n)
+        .collect();
+    let (mut sxx, mut sxy, mut syy) = (0.0, 0.0, 0.0);
+    let mut sxc = [0.0; 3];
+    let mut syc = [0.0; 3];
+    for (p, rgb) in pixels.iter() {
+        let (dx, dy) = (p.x - mean_x, p.y - mean_y);
+        sxx += dx * dx;
+        sxy += dx * dy;
+        syy += dy * dy;
+        for c in 0..3 {
+            sxc[c] += dx * (rgb[c] - mean_c[c]);
+            syc[c] += dy * (rgb[c] - mean_c[c]);
+        }
+    }
+    let det = sxx * syy - sxy * sxy;
+    if det <= f64::EPSILON {
+        return None;
+    }
+    // the slopes of the channels in x and y, and the direction they agree on most
+    let (mut a, mut b, mut d) = (0.0, 0.0, 0.0);
+    for c in 0..3 {
+        let gx = (syy * sxc[c] - sxy * syc[c]) / det;
+        let gy = (sxx * syc[c] - sxy * sxc[c]) / det;
+        a += gx * gx;
+        b += gx * gy;
+        d += gy * gy;
+    }
+    if a + d <= f64::EPSILON {
+        return None;
+    }
+    let angle = 0.5 * (2.0 * b).atan2(a - d);
+    let direction = PointF64::new(angle.cos(), angle.sin());
+    let offsets: Vec<f64> = pixels
+        .iter()
+        .map(|(p, _)| (p.x - mean_x) * direction.x + (p.y - mean_y) * direction.y)
+        .collect();
+    let (low, high) = range(&offsets);
+    let at = |t: f64| PointF64::new(mean_x + t * direction.x, mean_y + t * direction.y);
+    Some(Gradient {
+        shape: GradientShape::Linear {
+            from: at(low),
+            to: at(high),
+        },
+        stops: fit_stops(pixels, &offsets, low, high)?,
+    })
+}
+
+/// A radial gradient about the center of the paraboloid fitted to the brightness of `pixels`
+fn fit_radial(pixels: &[(PointF64, [f64; 3])]) -> Option<Gradient> {
+    let n = pixels.len() as f64;
+    let mean_x = pixels.iter().map(|(p, _)| p.x).sum::<f64>() / n;
+    let mean_y = pixels.iter().map(|(p, _)| p.y).sum::<f64>() / n;
+    // least squares for brightness = k0 + k1 x + k2 y + k3 (x² + y²)
+    let mut normal = [[0.0f64; 5]; 4];
+    for (p, rgb) in pixels.iter() {
+        let (x, y) = (p.x - mean_x, p.y - mean_y);
+        let terms = [1.0, x, y, x * x + y * y];
+        let brightness = rgb[0] + rgb[1] + rgb[2];
+        for (row, &u) in terms.iter().enumerate() {
+            for (column, &v) in terms.iter().enumerate() {
+                normal[row][column] += u * v;
+            }
+            normal[row][4] += u * brightness;
+        }
+    }
+    let k = solve(normal)?;
+    if k[3].abs() <= f64::EPSILON {
+        return None;
+    }
+    let center = PointF64::new(mean_x - k[1] / (2.0 * k[3]), mean_y - k[2] / (2.0 * k[3]));
+    let offsets: Vec<f64> = pixels.iter().map(|(p, _)| distance(center, *p)).collect();
+    let (_, radius) = range(&offsets);
+    // a center far outside the region makes a linear gradient in effect
+    let (low_x, high_x) = range(&pixels.iter().map(|(p, _)| p.x).collect::<Vec<_>>());
+    let (low_y, high_y) = range(&pixels.iter().map(|(p, _)| p.y).collect::<Vec<_>>());
+    if radius > 2.0 * ((high_x - low_x).powi(2) + (high_y - low_y).powi(2)).sqrt() {
+        return None;
+    }
+    Some(Gradient {
+        shape: GradientShape::Radial { center, radius },
+        stops: fit_stops(pixels, &offsets, 0.0, radius)?,
+    })
+}
+
+/// The mean colors of `pixels` in bins along their `offsets` from `low` to `high`, leaving out
+/// the stops that the ones around them already make
+fn fit_stops(
+    pixels: &[(PointF64, [f64; 3])],
+    offsets: &[f64],
+    low: f64,
+    high: f64,
+) -> Option<Vec<(f64, Color)>> {
+    if high - low < 1.0 {
+        return None;
+    }
+    let mut bins = vec![(0.0, [0.0; 3], 0usize); NUM_BINS];
+    for ((_, rgb), &t) in pixels.iter().zip(offsets.iter()) {
+        let u = (t - low) / (high - low);
+        let bin = &mut bins[((u * NUM_BINS as f64) as usize).min(NUM_BINS - 1)];
+        bin.0 += u;
+        for (sum, value) in bin.1.iter_mut().zip(rgb.iter()) {
+            *sum += value;
+        }
+        bin.2 += 1;
+    }
+    let stops: Vec<(f64, [f64; 3])> = bins
+        .into_iter()
+        .filter(|bin| bin.2 > 0)
+        .map(|(u, rgb, count)| {
+            let count = count as f64;
+            (u / count, [rgb[0] / count, rgb[1] / count, rgb[2] / count])
+        })
+        .collect();
+    let mut keep = vec![false; stops.len()];
+    keep[0] = true;
+    keep[stops.len() - 1] = true;
+    simplify_stops(&stops, &mut keep);
+    Some(
+        stops
+            .iter()
+            .zip(keep)
+            .filter(|(_, keep)| *keep)
+            .map(|((u, rgb), _)| {
+                (
+                    *u,
+                    Color::new(
+                        rgb[0].round() as u8,
+                        rgb[1].round() as u8,
+                        rgb[2].round() as u8,
+                    ),
+                )
+            })
+            .collect(),
+    )
+}
+
+/// Mark the stops between the first and the last that differ most from a straight blend of those,
+/// recursively, as long as the difference exceeds `STOP_TOLERANCE`
+fn simplify_stops(stops: &[(f64, [f64; 3])], keep: &mut [bool]) {
+    if stops.len() < 3 {
+        return;
+    }
+    let (first, last) = (stops[0], stops[stops.len() - 1]);
+    let (mut worst, mut index) = (0.0, 0);
+    for (i, (u, rgb)) in stops.iter().enumerate().take(stops.len() - 1).skip(1) {
+        let blend = interpolate(&[first, last], *u, |rgb| *rgb);
+        let difference = (0..3)
+            .map(|c| (rgb[c] - blend[c]).abs())
+            .fold(0.0, f64::max);
+        if difference > worst {
+            worst = difference;
+            index = i;
+        }
+    }
+    if worst > STOP_TOLERANCE {
+        keep[index] = true;
+        simplify_stops(&stops[..=index], &mut keep[..=index]);
+        simplify_stops(&stops[index..], &mut keep[index..]);
+    }
+}
+
+/// The color of a gradient with `stops` at `offset`, reading the channels of a stop with `rgb`
+fn interpolate<C>(stops: &[(f64, C)], offset: f64, rgb: impl Fn(&C) -> [f64; 3]) -> [f64; 3] {
+    match stops.iter().position(|(u, _)| *u > offset) {
+        Some(0) => rgb(&stops[0].1),
+        None => rgb(&stops[stops.len() - 1].1),
+        Some(i) => {
+            let ((u0, a), (u1, b)) = (&stops[i - 1], &stops[i]);
+            let (a, b) = (rgb(a), rgb(b));
+            let t = (offset - u0) / (u1 - u0);
+            [
+                a[0] + (b[0] - a[0]) * t,
+                a[1] + (b[1] - a[1]) * t,
+                a[2] + (b[2] - a[2]) * t,
+            ]
+        }
+    }
+}
+
+fn range(values: &[f64]) -> (f64, f64) {
+    values.iter().fold((f64::MAX, f64::MIN), |(low, high), &v| {
+        (low.min(v), high.max(v))
+    })
+}
+
+/// Solve a system of linear equations given as an augmented matrix by Gaussian elimination
+fn solve<const N: usize, const M: usize>(mut matrix: [[f64; M]; N]) -> Option<[f64; N]> {
+    for column in 0..N {
+        let pivot = (column..N)
+            .max_by(|&a, &b| matrix[a][column].abs().total_cmp(&matrix[b][column].abs()))?;
+        if matrix[pivot][column].abs() <= f64::EPSILON {
+            return None;
+        }
+        matrix.swap(column, pivot);
+        let pivot_row = matrix[column];
+        for (i, row) in matrix.iter_mut().enumerate() {
+            if i != column {
+                let factor = row[column] / pivot_row[column];
+                for (value, pivot_value) in row.iter_mut().zip(pivot_row.iter()).skip(column) {
+                    *value -= factor * pivot_value;
+                }
+            }
+        }
+    }
+    let mut solution = [0.0; N];
+    for (i, value) in solution.iter_mut().enumerate() {
+        *value = matrix[i][M - 1] / matrix[i][i];
+    }
+    Some(solution)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The pixels of a `width` by `height` region, colored by `color(x, y)` at their centers
+    fn region(
+        width: usize,
+        height: usize,
+        color: impl Fn(f64, f64) -> [f64; 3],
+    ) -> Vec<(PointF64, [f64; 3])> {
+        (0..width * height)
+            .map(|i| {
+                let p = PointF64::new((i % width) as f64 + 0.5, (i / width) as f64 + 0.5);
+                (p, color(p.x, p.y))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn horizontal_ramp_is_linear() {
+        // from black at the left edge to orange at the right
+        let pixels = region(64, 16, |x, _| [(x * 4.0).round(), (x * 2.0).round(), 0.0]);
+        let gradient = fit_gradient(&pixels).unwrap();
+        match gradient.shape {
+            GradientShape::Linear { from, to } => {
+                // either way along the x axis
+                assert!((from.y - to.y).abs() < 1e-6, "{:?} {:?}", from, to);
+                assert!(
+                    ((from.x - to.x).abs() - 63.0).abs() < 1e-6,
+                    "{:?} {:?}",
+                    from,
+                    to
+                );
+            }
+            ref other => panic!("expected a linear gradient, got {:?}", other),
+        }
+        for (p, rgb) in pixels.iter() {
+            let color = gradient.color_at(*p);
+            assert!((color.r as f64 - rgb[0]).abs() <= 3.0, "at {:?}", p);
+            assert!((color.g as f64 - rgb[1]).abs() <= 3.0, "at {:?}", p);
+        }
+        // a straight blend needs no stops in between
+        assert_eq!(gradient.stops.len(), 2);
+    }
+
+    #[test]
+    fn flat_region_has_no_gradient() {
+        let pixels = region(32, 32, |_, _| [40.0, 80.0, 120.0]);
+        assert_eq!(fit_gradient(&pixels), None);
+    }
+
+    #[test]
+    fn solve_regular() {
+        // x + y = 3, x - y = 1
+        let solution = solve([[1.0, 1.0, 3.0], [1.0, -1.0, 1.0]]).unwrap();
+        assert!((solution[0] - 2.0).abs() < 1e-12 && (solution[1] - 1.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn solve_singular() {
+        assert_eq!(solve([[1.0, 2.0, 3.0], [2.0, 4.0, 6.0]]), None);
+        assert_eq!(solve([[0.0, 0.0, 1.0], [0.0, 0.0, 1.0]]), None);
+    }
+}
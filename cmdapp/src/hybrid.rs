@@ -0,0 +1,424 @@
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+use image::{DynamicImage, ImageBuffer, ImageOutputFormat, Rgba};
+use visioncortex::clusters::Cluster;
+use visioncortex::{
+    BinaryImage, ColorImage, CompoundPath, CompoundPathElement, PathSimplifyMode, PointF64,
+    PointI32,
+};
+
+use super::svg::{translate_path, SvgFile};
+
+/// Side in pixels of the square tiles the canvas is measured in
+const TILE_SIZE: usize = 32;
+/// Quality from 1 to 100 that pictures are encoded at as JPEG
+const JPEG_QUALITY: u8 = 85;
+
+/// The encoding of the pictures embedded by [`Config::hybrid`](crate::Config::hybrid)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum RasterFormat {
+    /// Lossy and small, for photographs. Pictures with any transparency are written as PNG.
+    #[cfg_attr(feature = "serde", serde(rename = "jpeg"))]
+    Jpeg,
+    /// Lossless
+    #[cfg_attr(feature = "serde", serde(rename = "png"))]
+    Png,
+}
+
+impl FromStr for RasterFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "jpeg" | "jpg" => Ok(Self::Jpeg),
+            "png" => Ok(Self::Png),
+            _ => Err(format!("unknown RasterFormat {}", s)),
+        }
+    }
+}
+
+impl RasterFormat {
+    fn mime_type(&self) -> &'static str {
+        match self {
+            Self::Jpeg => "image/jpeg",
+            Self::Png => "image/png",
+        }
+    }
+}
+
+/// A part of the image too detailed to trace, embedded as a picture clipped to its outline
+#[derive(Debug, Clone)]
+pub struct EmbeddedImage {
+    /// The outline the picture shows through
+    pub clip: CompoundPath,
+    /// The top left corner of the picture on the canvas
+    pub position: PointF64,
+    /// The size of the picture on the canvas, which is its size in pixels unless scaled
+    pub width: f64,
+    pub height: f64,
+    pub format: RasterFormat,
+    /// The encoded picture
+    pub data: Vec<u8>,
+}
+
+impl EmbeddedImage {
+    /// Enlarge the picture and its outline by an integer `factor` about the origin
+    pub(crate) fn scale(&mut self, factor: usize) {
+        self.position = PointF64::new(
+            self.position.x * factor as f64,
+            self.position.y * factor as f64,
+        );
+        self.width *= factor as f64;
+        self.height *= factor as f64;
+        for element in self.clip.iter_mut() {
+            match element {
+                CompoundPathElement::PathI32(path) => {
+                    for point in path.path.iter_mut() {
+                        point.x *= factor as i32;
+                        point.y *= factor as i32;
+                    }
+                }
+                CompoundPathElement::PathF64(path) => {
+                    for point in path.path.iter_mut() {
+                        point.x *= factor as f64;
+                        point.y *= factor as f64;
+                    }
+                }
+                CompoundPathElement::Spline(spline) => {
+                    for point in spline.points.iter_mut() {
+                        point.x *= factor as f64;
+                        point.y *= factor as f64;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Move the picture and its outline by `offset`
+    pub(crate) fn translate(&mut self, offset: PointI32) {
+        self.position = PointF64::new(
+            self.position.x + offset.x as f64,
+            self.position.y + offset.y as f64,
+        );
+        translate_path(&mut self.clip, offset);
+    }
+
+    /// Decode the picture, for rendering it
+    pub(crate) fn decode(&self) -> Option<ColorImage> {
+        let image = image::load_from_memory(&self.data).ok()?.to_rgba8();
+        let mut decoded = ColorImage::new_w_h(image.width() as usize, image.height() as usize);
+        decoded.pixels = image.into_raw();
+        Some(decoded)
+    }
+
+    /// Write the outline as a `<clipPath>` with id `id`
+    pub(crate) fn fmt_clip(
+        &self,
+        f: &mut fmt::Formatter,
+        id: &str,
+        precision: Option<u32>,
+    ) -> fmt::Result {
+        let (string, offset) = self
+            .clip
+            .to_svg_string(true, PointF64::default(), precision);
+        writeln!(
+            f,
+            "<clipPath id=\"{}\"><path d=\"{}\" transform=\"translate({},{})\"/></clipPath>",
+            id, string, offset.x, offset.y
+        )
+    }
+
+    /// Write the picture as an `<image>` clipped by the `<clipPath>` with id `clip_id`
+    pub(crate) fn fmt_image(&self, f: &mut fmt::Formatter, clip_id: &str) -> fmt::Result {
+        write!(
+            f,
+            "<image x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" preserveAspectRatio=\"none\" clip-path=\"url(#{})\" xlink:href=\"data:{};base64,",
+            self.position.x,
+            self.position.y,
+            self.width,
+            self.height,
+            clip_id,
+            self.format.mime_type()
+        )?;
+        write_base64(f, &self.data)?;
+        writeln!(f, "\"/>")
+    }
+}
+
+/// Embed the parts of `source` where the paths of `svg` are crowded as pictures, and remove the
+/// paths hidden under them. `regions` holds the pixel indices each path was traced from, and is
+/// kept in step with the paths. A tile of the canvas is crowded where more than `threshold`
+/// paths show in it.
+pub(crate) fn embed_crowded_regions(
+    svg: &mut SvgFile,
+    regions: &mut Vec<&[u32]>,
+    source: &ColorImage,
+    threshold: usize,
+    format: RasterFormat,
+) -> Result<(), String> {
+    let (width, height) = (source.width, source.height);
+    if width == 0 || height == 0 {
+        return Ok(());
+    }
+    // the path showing at each pixel, the last one drawn over it
+    let mut top = vec![usize::MAX; width * height];
+    for (i, region) in regions.iter().enumerate() {
+        for &index in region.iter() {
+            top[index as usize] = i;
+        }
+    }
+
+    let (columns, rows) = (width.div_ceil(TILE_SIZE), height.div_ceil(TILE_SIZE));
+    let mut crowded = vec![false; columns * rows];
+    let mut shown = vec![];
+    for row in 0..rows {
+        for column in 0..columns {
+            shown.clear();
+            for y in row * TILE_SIZE..((row + 1) * TILE_SIZE).min(height) {
+                for x in column * TILE_SIZE..((column + 1) * TILE_SIZE).min(width) {
+                    shown.push(top[y * width + x]);
+                }
+            }
+            shown.sort_unstable();
+            shown.dedup();
+            crowded[row * columns + column] = shown.len() > threshold;
+        }
+    }
+
+    let mut embedded = vec![false; width * height];
+    let mut grouped = vec![false; columns * rows];
+    for start in 0..crowded.len() {
+        if !crowded[start] || grouped[start] {
+            continue;
+        }
+        // the crowded tiles connected to this one
+        let mut tiles = vec![start];
+        grouped[start] = true;
+        let mut next = 0;
+        while next < tiles.len() {
+            let tile = tiles[next];
+            next += 1;
+            let (column, row) = (tile % columns, tile / columns);
+            let neighbours = [
+                (column > 0).then(|| tile - 1),
+                (column + 1 < columns).then(|| tile + 1),
+                (row > 0).then(|| tile - columns),
+                (row + 1 < rows).then(|| tile + columns),
+            ];
+            for neighbour in neighbours.into_iter().flatten() {
+                if crowded[neighbour] && !grouped[neighbour] {
+                    grouped[neighbour] = true;
+                    tiles.push(neighbour);
+                }
+            }
+        }
+
+        // the bounding box of the tiles
+        let left = tiles.iter().map(|tile| tile % columns).min().unwrap() * TILE_SIZE;
+        let upper = tiles.iter().map(|tile| tile / columns).min().unwrap() * TILE_SIZE;
+        let right =
+            ((tiles.iter().map(|tile| tile % columns).max().unwrap() + 1) * TILE_SIZE).min(width);
+        let lower =
+            ((tiles.iter().map(|tile| tile / columns).max().unwrap() + 1) * TILE_SIZE).min(height);
+        let mut mask = BinaryImage::new_w_h(right - left, lower - upper);
+        for &tile in tiles.iter() {
+            let (column, row) = (tile % columns, tile / columns);
+            for y in row * TILE_SIZE..((row + 1) * TILE_SIZE).min(height) {
+                for x in column * TILE_SIZE..((column + 1) * TILE_SIZE).min(width) {
+                    mask.set_pixel(x - left, y - upper, true);
+                    embedded[y * width + x] = true;
+                }
+            }
+        }
+        let clip = Cluster::image_to_compound_path(
+            &PointI32::new(left as i32, upper as i32),
+            &mask,
+            PathSimplifyMode::Polygon,
+            0.0,
+            0.0,
+            0,
+            0.0,
+        );
+        let (format, data) = encode(source, left, upper, right, lower, format)?;
+        svg.images.push(EmbeddedImage {
+            clip,
+            position: PointF64::new(left as f64, upper as f64),
+            width: (right - left) as f64,
+            height: (lower - upper) as f64,
+            format,
+            data,
+        });
+    }
+
+    // the paths entirely under a picture are hidden by it
+    let hidden: Vec<bool> = regions
+        .iter()
+        .map(|region| region.iter().all(|&index| embedded[index as usize]))
+        .collect();
+    let mut flags = hidden.iter();
+    svg.paths.retain(|_| !flags.next().unwrap());
+    let mut flags = hidden.iter();
+    regions.retain(|_| !flags.next().unwrap());
+    Ok(())
+}
+
+/// Encode the part of `source` from (`left`, `top`) up to (`right`, `bottom`), and return the
+/// format it is in: PNG instead of JPEG if the part has any transparency, which JPEG would paint
+/// black
+fn encode(
+    source: &ColorImage,
+    left: usize,
+    top: usize,
+    right: usize,
+    bottom: usize,
+    format: RasterFormat,
+) -> Result<(RasterFormat, Vec<u8>), String> {
+    let crop = ImageBuffer::from_fn((right - left) as u32, (bottom - top) as u32, |x, y| {
+        let color = source.get_pixel(left + x as usize, top + y as usize);
+        Rgba([color.r, color.g, color.b, color.a])
+    });
+    let format = if crop.pixels().any(|pixel| pixel[3] < 255) {
+        RasterFormat::Png
+    } else {
+        format
+    };
+    let (picture, output) = match format {
+        RasterFormat::Jpeg => (
+            DynamicImage::ImageRgb8(DynamicImage::ImageRgba8(crop).to_rgb8()),
+            ImageOutputFormat::Jpeg(JPEG_QUALITY),
+        ),
+        RasterFormat::Png => (DynamicImage::ImageRgba8(crop), ImageOutputFormat::Png),
+    };
+    let mut data = vec![];
+    picture
+        .write_to(&mut data, output)
+        .map_err(|err| format!("Failed to encode image: {}", err))?;
+    Ok((format, data))
+}
+
+fn write_base64(f: &mut fmt::Formatter, data: &[u8]) -> fmt::Result {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let bytes = [
+            chunk[0],
+            chunk.get(1).copied().unwrap_or(0),
+            chunk.get(2).copied().unwrap_or(0),
+        ];
+        let bits = (bytes[0] as u32) << 16 | (bytes[1] as u32) << 8 | bytes[2] as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(bits >> (18 - 6 * i) & 63) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    f.write_str(&encoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use visioncortex::Color;
+
+    use super::*;
+
+    /// A 64 by 32 image of two tiles, traced as a path for each of 20 one pixel wide stripes
+    /// down the left tile, one for the rest of both tiles, and one for the bottom row. So 22
+    /// paths show in the left tile.
+    fn striped() -> (SvgFile, ColorImage, Vec<Vec<u32>>) {
+        let (width, height) = (64, 32);
+        let mut source = ColorImage::new_w_h(width, height);
+        let mut regions = vec![vec![]; 22];
+        for y in 0..height {
+            for x in 0..width {
+                let region = if y == height - 1 {
+                    21
+                } else if x < 20 {
+                    x
+                } else {
+                    20
+                };
+                regions[region].push((y * width + x) as u32);
+                let shade = (region * 10) as u8;
+                source.set_pixel(x, y, &Color::new(shade, 255 - shade, 128));
+            }
+        }
+        let mut svg = SvgFile::new(width, height, None);
+        for (i, _) in regions.iter().enumerate() {
+            svg.add_path(CompoundPath::new(), Color::new(i as u8, 0, 0));
+        }
+        (svg, source, regions)
+    }
+
+    fn embed(
+        svg: &mut SvgFile,
+        source: &ColorImage,
+        regions: &[Vec<u32>],
+        threshold: usize,
+        format: RasterFormat,
+    ) -> Vec<usize> {
+        let mut regions: Vec<&[u32]> = regions.iter().map(|region| region.as_slice()).collect();
+        embed_crowded_regions(svg, &mut regions, source, threshold, format).unwrap();
+        assert_eq!(regions.len(), svg.paths.len());
+        svg.paths.iter().map(|path| path.color.r as usize).collect()
+    }
+
+    #[test]
+    fn crowded_tile_is_embedded() {
+        let (mut svg, source, regions) = striped();
+        let kept = embed(&mut svg, &source, &regions, 16, RasterFormat::Jpeg);
+        assert_eq!(svg.images.len(), 1);
+        let image = &svg.images[0];
+        assert_eq!(image.position, PointF64::new(0.0, 0.0));
+        assert_eq!((image.width, image.height), (32.0, 32.0));
+        assert_eq!(image.format, RasterFormat::Jpeg);
+        let decoded = image.decode().unwrap();
+        assert_eq!((decoded.width, decoded.height), (32, 32));
+
+        // the stripes are hidden under the picture, the paths reaching outside it are kept
+        assert_eq!(kept, [20, 21]);
+    }
+
+    #[test]
+    fn sparse_tiles_are_kept() {
+        let (mut svg, source, regions) = striped();
+        let kept = embed(&mut svg, &source, &regions, 22, RasterFormat::Jpeg);
+        assert!(svg.images.is_empty());
+        assert_eq!(kept, (0..22).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn transparent_pictures_are_png() {
+        let (mut svg, mut source, regions) = striped();
+        source.set_pixel(3, 3, &Color::new_rgba(0, 0, 0, 0));
+        embed(&mut svg, &source, &regions, 16, RasterFormat::Jpeg);
+        let image = &svg.images[0];
+        assert_eq!(image.format, RasterFormat::Png);
+        let decoded = image.decode().unwrap();
+        assert_eq!(decoded.get_pixel(3, 3).a, 0);
+        assert_eq!(decoded.get_pixel(4, 3), source.get_pixel(4, 3));
+    }
+
+    struct Base64<'a>(&'a [u8]);
+
+    impl fmt::Display for Base64<'_> {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write_base64(f, self.0)
+        }
+    }
+
+    #[test]
+    fn base64_is_padded() {
+        assert_eq!(Base64(b"").to_string(), "");
+        assert_eq!(Base64(b"M").to_string(), "TQ==");
+        assert_eq!(Base64(b"Ma").to_string(), "TWE=");
+        assert_eq!(Base64(b"Man").to_string(), "TWFu");
+        assert_eq!(Base64(b"Many").to_string(), "TWFueQ==");
+        assert_eq!(Base64(&[0xff, 0xfe, 0xfd]).to_string(), "//79");
+    }
+}
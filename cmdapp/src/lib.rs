@@ -8,15 +8,43 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
+mod analysis;
+mod arc;
+mod background;
+mod centerline;
+mod color_space;
 mod config;
 mod converter;
+mod fidelity;
+mod gradient;
+mod hybrid;
+mod orthogonal;
+mod palette;
+mod primitive;
 #[cfg(feature = "python-binding")]
 mod python;
+mod quantize;
+mod rasterizer;
+mod stats;
 mod svg;
+mod tune;
 
+pub use analysis::*;
+pub use arc::*;
+pub use background::*;
+pub use color_space::*;
 pub use config::*;
 pub use converter::*;
+pub use fidelity::*;
+pub use gradient::*;
+pub use hybrid::*;
+pub use palette::*;
+pub use primitive::*;
 #[cfg(feature = "python-binding")]
 pub use python::*;
+pub use quantize::*;
+pub use rasterizer::*;
+pub use stats::*;
 pub use svg::*;
+pub use tune::*;
 pub use visioncortex::ColorImage;
@@ -1,32 +1,165 @@
-mod config;
-mod converter;
-mod svg;
-
-use clap::{App, Arg};
-use config::{ColorMode, Config, Hierarchical, Preset};
-use std::path::PathBuf;
+mod batch;
+mod compare;
+mod error;
+mod report;
+mod sweep;
+mod tune_cmd;
+
+use batch::BatchOptions;
+use clap::{App, AppSettings, Arg, ArgMatches, ErrorKind};
+use compare::CompareArgs;
+use error::CliError;
+use report::Reporter;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
-use visioncortex::PathSimplifyMode;
-
-fn path_simplify_mode_from_str(s: &str) -> PathSimplifyMode {
-    match s {
-        "polygon" => PathSimplifyMode::Polygon,
-        "spline" => PathSimplifyMode::Spline,
-        "none" => PathSimplifyMode::None,
-        _ => panic!("unknown PathSimplifyMode {}", s),
+use sweep::SweepArgs;
+use tune_cmd::TuneArgs;
+use vtracer::{
+    path_simplify_mode_from_str, Background, ColorImage, ColorMode, ColorSpace, Config,
+    ConversionStats, Hierarchical, Palette, Preset, QuantizeMethod, RasterFormat,
+};
+
+pub enum Command {
+    Convert(Args),
+    Compare(CompareArgs),
+    Tune(TuneArgs),
+    Sweep(SweepArgs),
+}
+
+pub struct Args {
+    pub input_path: PathBuf,
+    pub output_path: PathBuf,
+    pub config: ConfigLayers,
+    pub batch_options: BatchOptions,
+    pub quiet: bool,
+    pub reporter: Option<Reporter>,
+}
+
+type ConfigOverrides = serde_json::Map<String, serde_json::Value>;
+
+/// Read the options of a TOML or JSON (by file extension) config file
+fn read_config_file(path: &Path) -> Result<ConfigOverrides, String> {
+    let content = std::fs::read_to_string(path)
+        .map_err(|err| format!("Cannot read config file {}: {}", path.display(), err))?;
+    let overrides: serde_json::Value = match path.extension().and_then(|ext| ext.to_str()) {
+        Some("json") => serde_json::from_str(&content).map_err(|err| err.to_string())?,
+        _ => toml::from_str(&content).map_err(|err| err.to_string())?,
+    };
+    match overrides {
+        serde_json::Value::Object(overrides) => Ok(overrides),
+        _ => Err(String::from("Config file must contain a table of options")),
+    }
+}
+
+/// Apply the options of a config file on top of `base`.
+/// Fields absent from the file keep their values in `base`.
+fn merge_config(base: Config, overrides: &ConfigOverrides) -> Result<Config, String> {
+    let mut merged = serde_json::to_value(base).map_err(|err| err.to_string())?;
+    if let serde_json::Value::Object(merged) = &mut merged {
+        merged.extend(overrides.clone());
+    }
+    serde_json::from_value(merged).map_err(|err| err.to_string())
+}
+
+/// The options that make up the `Config` of each image, in increasing precedence:
+/// a preset, a config file and the explicit options
+#[derive(Clone)]
+pub struct ConfigLayers {
+    /// `None` for `--preset auto`, which chooses the base from each image's content
+    base: Option<Config>,
+    file: Option<(String, ConfigOverrides)>,
+    options: Vec<(&'static str, String)>,
+}
+
+impl ConfigLayers {
+    fn from_matches(matches: &ArgMatches) -> Result<Self, CliError> {
+        let base = match matches.value_of("preset") {
+            Some("auto") => None,
+            Some(value) => Some(Config::from_preset(
+                Preset::from_str(value).map_err(CliError::InvalidArguments)?,
+            )),
+            None => Some(Config::default()),
+        };
+        let file = match matches.value_of("config") {
+            Some(value) => {
+                let overrides = read_config_file(Path::new(value)).map_err(|err| {
+                    CliError::InvalidArguments(format!("Config file {} is invalid: {}", value, err))
+                })?;
+                Some((value.to_owned(), overrides))
+            }
+            None => None,
+        };
+        let mut options: Vec<(&'static str, String)> = CONFIG_OPTIONS
+            .iter()
+            .filter_map(|&name| matches.value_of(name).map(|value| (name, value.to_owned())))
+            .collect();
+        for flag in [
+            "pixel_art",
+            "centerline",
+            "primitives",
+            "orthogonal",
+            "arcs",
+            "gradients",
+            "hybrid",
+            "alpha",
+            "trim",
+        ] {
+            if matches.is_present(flag) {
+                options.push((flag, String::from("true")));
+            }
+        }
+
+        let layers = Self {
+            base,
+            file,
+            options,
+        };
+        // report invalid options upfront rather than once per image
+        layers.apply(layers.base.clone().unwrap_or_default())?;
+        Ok(layers)
+    }
+
+    /// The config for converting `img`
+    pub fn for_image(&self, img: &ColorImage) -> Result<Config, CliError> {
+        match &self.base {
+            Some(base) => self.apply(base.clone()),
+            None => self.apply(Config::auto(img)),
+        }
+    }
+
+    fn apply(&self, mut config: Config) -> Result<Config, CliError> {
+        if let Some((path, overrides)) = &self.file {
+            config = merge_config(config, overrides).map_err(|err| {
+                CliError::InvalidArguments(format!("Config file {} is invalid: {}", path, err))
+            })?;
+        }
+        for (name, value) in self.options.iter() {
+            set_option(&mut config, name, value)?;
+        }
+        validate_config(&config)?;
+        Ok(config)
     }
 }
 
-pub fn config_from_args() -> (PathBuf, PathBuf, Config) {
+pub fn config_from_args() -> Result<Command, CliError> {
     let app = App::new("visioncortex VTracer ".to_owned() + env!("CARGO_PKG_VERSION"))
-        .about("A cmd app to convert images into vector graphics.");
+        .about("A cmd app to convert images into vector graphics.")
+        .after_help(error::EXIT_CODES_HELP)
+        .setting(AppSettings::SubcommandsNegateReqs)
+        .subcommand(compare::subcommand())
+        .subcommand(tune_cmd::subcommand())
+        .subcommand(sweep::subcommand());
 
     let app = app.arg(
         Arg::with_name("input")
             .long("input")
             .short("i")
             .takes_value(true)
-            .help("Path to input raster image")
+            .help(
+                "Path to input raster image, or `-` to read from stdin. \
+            A directory or a glob pattern like `scans/**/*.png` converts all matching images.",
+            )
             .required(true),
     );
 
@@ -35,18 +168,135 @@ pub fn config_from_args() -> (PathBuf, PathBuf, Config) {
             .long("output")
             .short("o")
             .takes_value(true)
-            .help("Path to output vector graphics")
+            .help(
+                "Path to output vector graphics, or `-` to write to stdout. \
+            In batch mode, the directory to write the mirrored input tree into.",
+            )
             .required(true),
     );
 
     let app = app.arg(
-        Arg::with_name("color_mode")
-            .long("colormode")
+        Arg::with_name("jobs")
+            .long("jobs")
+            .short("j")
             .takes_value(true)
-            .help("True color image `color` (default) or Binary image `bw`"),
+            .help("Maximum number of images to convert in parallel in batch mode (default: number of CPUs)"),
+    );
+
+    let app = app.arg(
+        Arg::with_name("force")
+            .long("force")
+            .help("In batch mode, also convert images whose output is newer than the input"),
+    );
+
+    let app = app.args(&config_args());
+
+    let app = app.arg(
+        Arg::with_name("report")
+            .long("report")
+            .takes_value(true)
+            .help("Write conversion statistics in the given format `json`, one line per image"),
     );
 
     let app = app.arg(
+        Arg::with_name("report_file")
+            .long("report_file")
+            .takes_value(true)
+            .requires("report")
+            .help("Path to write the report to (default: stderr)"),
+    );
+
+    let app = app.arg(
+        Arg::with_name("quiet")
+            .long("quiet")
+            .short("q")
+            .help("Do not print progress messages, only errors"),
+    );
+
+    // Extract matches
+    let matches = match app.get_matches_safe() {
+        Ok(matches) => matches,
+        Err(err) => match err.kind {
+            ErrorKind::HelpDisplayed | ErrorKind::VersionDisplayed => err.exit(),
+            _ => return Err(CliError::InvalidArguments(err.message)),
+        },
+    };
+
+    if let Some(matches) = matches.subcommand_matches("compare") {
+        return compare::args_from_matches(matches).map(Command::Compare);
+    }
+    if let Some(matches) = matches.subcommand_matches("tune") {
+        return tune_cmd::args_from_matches(matches).map(Command::Tune);
+    }
+    if let Some(matches) = matches.subcommand_matches("sweep") {
+        return sweep::args_from_matches(matches).map(Command::Sweep);
+    }
+
+    // Both are required, so clap guarantees they are present
+    let input_path = PathBuf::from(matches.value_of("input").unwrap());
+    let output_path = PathBuf::from(matches.value_of("output").unwrap());
+    let config = ConfigLayers::from_matches(&matches)?;
+
+    let jobs = parse_jobs(&matches)?;
+    let quiet = matches.is_present("quiet");
+    let batch_options = BatchOptions {
+        jobs,
+        force: matches.is_present("force"),
+        quiet,
+    };
+
+    let reporter = match matches.value_of("report") {
+        Some(format) => Some(Reporter::new(format, matches.value_of("report_file"))?),
+        None => None,
+    };
+
+    Ok(Command::Convert(Args {
+        input_path,
+        output_path,
+        config,
+        batch_options,
+        quiet,
+        reporter,
+    }))
+}
+
+/// The options that set a single `Config` field, in the order they are applied
+const CONFIG_OPTIONS: [&str; 26] = [
+    "color_mode",
+    "hierarchical",
+    "mode",
+    "filter_speckle",
+    "color_precision",
+    "gradient_step",
+    "corner_threshold",
+    "segment_length",
+    "splice_threshold",
+    "path_precision",
+    "palette",
+    "colors",
+    "quantize_method",
+    "quantize_space",
+    "color_space",
+    "primitive_tolerance",
+    "snap_angle",
+    "grid",
+    "arc_tolerance",
+    "gradient_tolerance",
+    "hybrid_threshold",
+    "raster_format",
+    "alpha_threshold",
+    "background",
+    "background_tolerance",
+    "trim_padding",
+];
+
+/// The options shared by conversion and the subcommands, that build up a `Config`
+fn config_args<'a, 'b>() -> Vec<Arg<'a, 'b>> {
+    vec![
+        Arg::with_name("color_mode")
+            .long("colormode")
+            .takes_value(true)
+            .help("True color image `color` (default) or Binary image `bw`"),
         Arg::with_name("hierarchical")
             .long("hierarchical")
             .takes_value(true)
@@ -54,229 +304,497 @@ pub fn config_from_args() -> (PathBuf, PathBuf, Config) {
                 "Hierarchical clustering `stacked` (default) or non-stacked `cutout`. \
             Only applies to color mode. ",
             ),
-    );
-
-    let app = app.arg(
         Arg::with_name("preset")
             .long("preset")
             .takes_value(true)
-            .help("Use one of the preset configs `bw`, `poster`, `photo`"),
-    );
-
-    let app = app.arg(
+            .help(
+                "Use one of the preset configs `bw`, `poster`, `photo`, \
+            or `auto` to choose one from the content of each image",
+            ),
+        Arg::with_name("config")
+            .long("config")
+            .takes_value(true)
+            .help(
+                "Load options from a TOML or JSON config file. \
+            Explicit options on the command line take precedence.",
+            ),
         Arg::with_name("filter_speckle")
             .long("filter_speckle")
             .short("f")
             .takes_value(true)
             .help("Discard patches smaller than X px in size"),
-    );
-
-    let app = app.arg(
         Arg::with_name("color_precision")
             .long("color_precision")
             .short("p")
             .takes_value(true)
             .help("Number of significant bits to use in an RGB channel"),
-    );
-
-    let app = app.arg(
         Arg::with_name("gradient_step")
             .long("gradient_step")
             .short("g")
             .takes_value(true)
             .help("Color difference between gradient layers"),
-    );
-
-    let app = app.arg(
         Arg::with_name("corner_threshold")
             .long("corner_threshold")
             .short("c")
             .takes_value(true)
             .help("Minimum momentary angle (degree) to be considered a corner"),
-    );
-
-    let app = app.arg(Arg::with_name("segment_length")
-        .long("segment_length")
-        .short("l")
-        .takes_value(true)
-        .help("Perform iterative subdivide smooth until all segments are shorter than this length"));
-
-    let app = app.arg(
+        Arg::with_name("segment_length")
+            .long("segment_length")
+            .short("l")
+            .takes_value(true)
+            .help(
+                "Perform iterative subdivide smooth until all segments are shorter than this length",
+            ),
         Arg::with_name("splice_threshold")
             .long("splice_threshold")
             .short("s")
             .takes_value(true)
             .help("Minimum angle displacement (degree) to splice a spline"),
-    );
-
-    let app = app.arg(
         Arg::with_name("mode")
             .long("mode")
             .short("m")
             .takes_value(true)
             .help("Curver fitting mode `pixel`, `polygon`, `spline`"),
-    );
-
-    let app = app.arg(
         Arg::with_name("path_precision")
             .long("path_precision")
             .takes_value(true)
             .help("Number of decimal places to use in path string"),
-    );
-
-    // Extract matches
-    let matches = app.get_matches();
+        Arg::with_name("pixel_art").long("pixel_art").help(
+            "Trace pixel art on its native pixel grid, detecting and undoing integer upscaling",
+        ),
+        Arg::with_name("palette")
+            .long("palette")
+            .takes_value(true)
+            .help(
+                "Restrict the output colors to a palette: a list of hex colors like \
+            `#e63946,#f1faee,#1d3557`, or a GIMP `.gpl` or Adobe `.ase` palette file",
+            ),
+        Arg::with_name("colors")
+            .long("colors")
+            .takes_value(true)
+            .help("Reduce the image to exactly N colors before tracing. Only applies to color mode."),
+        Arg::with_name("quantize_method")
+            .long("quantize_method")
+            .takes_value(true)
+            .help("How to choose the --colors: `median_cut` (default), `kmeans` or `octree`"),
+        Arg::with_name("quantize_space")
+            .long("quantize_space")
+            .takes_value(true)
+            .help(
+                "Color space to choose the --colors in: `rgb` (default), or perceptual `lab` or `oklab`",
+            ),
+        Arg::with_name("color_space")
+            .long("color_space")
+            .takes_value(true)
+            .help(
+                "Color space to compare colors in while clustering: `rgb` (default), or perceptual \
+            `lab` or `oklab` where --gradient_step is a ΔE threshold. Only applies to color mode.",
+            ),
+        Arg::with_name("centerline").long("centerline").help(
+            "Trace the middle of shapes as strokes of their average width instead of their outlines. \
+            Only applies to binary mode.",
+        ),
+        Arg::with_name("primitives").long("primitives").help(
+            "Write outlines that fit a circle, ellipse, rectangle or straight line as that shape",
+        ),
+        Arg::with_name("primitive_tolerance")
+            .long("primitive_tolerance")
+            .takes_value(true)
+            .help("Largest distance in pixels of an outline from a --primitives shape replacing it"),
+        Arg::with_name("orthogonal").long("orthogonal").help(
+            "Snap polygon edges near horizontal or vertical to the axes, and near right angles to right angles",
+        ),
+        Arg::with_name("snap_angle")
+            .long("snap_angle")
+            .takes_value(true)
+            .help("Largest angle (degree) an edge is turned by to snap it with --orthogonal (default: 3)"),
+        Arg::with_name("grid")
+            .long("grid")
+            .takes_value(true)
+            .help("Round polygon vertices to multiples of this many pixels"),
+        Arg::with_name("arcs").long("arcs").help(
+            "Write spline curves that fit a straight line or circular arc as that. Only applies to spline mode.",
+        ),
+        Arg::with_name("arc_tolerance")
+            .long("arc_tolerance")
+            .takes_value(true)
+            .help("Largest distance in pixels of a curve from an --arcs line or arc replacing it (default: 0.5)"),
+        Arg::with_name("gradients").long("gradients").help(
+            "Fill smooth regions with a linear or radial gradient instead of stacked bands. Only applies to color mode.",
+        ),
+        Arg::with_name("gradient_tolerance")
+            .long("gradient_tolerance")
+            .takes_value(true)
+            .help("Largest RMS color difference of a --gradients fill from the pixels it covers (default: 8)"),
+        Arg::with_name("hybrid").long("hybrid").help(
+            "Embed regions too detailed to trace, such as photographs, as clipped raster images. Only applies to color mode.",
+        ),
+        Arg::with_name("hybrid_threshold")
+            .long("hybrid_threshold")
+            .takes_value(true)
+            .help("Number of paths in a 32x32 tile above which --hybrid embeds it as an image (default: 16)"),
+        Arg::with_name("raster_format")
+            .long("raster_format")
+            .takes_value(true)
+            .help("Encoding of the images embedded by --hybrid: `jpeg` (default) or `png`. Images with transparency are always PNG."),
+        Arg::with_name("alpha").long("alpha").help(
+            "Keep partial transparency as the fill-opacity of each path, cutting layers out even with --hierarchical stacked. Only applies to color mode.",
+        ),
+        Arg::with_name("alpha_threshold")
+            .long("alpha_threshold")
+            .takes_value(true)
+            .help("Alpha from 1 to 255 below which pixels are background with --alpha (default: 16)"),
+        Arg::with_name("background")
+            .long("background")
+            .takes_value(true)
+            .help("Remove a solid background of this hex color, or `auto` for the color of the corners, leaving it transparent. Only applies to color mode."),
+        Arg::with_name("background_tolerance")
+            .long("background_tolerance")
+            .takes_value(true)
+            .help("Largest difference per channel from the --background color of a removed pixel (default: 24)"),
+        Arg::with_name("trim")
+            .long("trim")
+            .help("Crop the canvas to the paths drawn"),
+        Arg::with_name("trim_padding")
+            .long("trim_padding")
+            .takes_value(true)
+            .help("Margin in pixels left around the paths by --trim (default: 0)"),
+    ]
+}
 
-    let mut config = Config::default();
-    let input_path = matches
-        .value_of("input")
-        .expect("Input path is required, please specify it by --input or -i.");
-    let output_path = matches
-        .value_of("output")
-        .expect("Output path is required, please specify it by --output or -o.");
+/// Report every out of range field of `config` under its command line option name
+fn validate_config(config: &Config) -> Result<(), CliError> {
+    if let Err(errors) = config.validate() {
+        let errors: Vec<String> = errors
+            .iter()
+            .map(|error| format!("--{} {}", option_name(error.field), error.message))
+            .collect();
+        return Err(CliError::InvalidArguments(errors.join("\nerror: ")));
+    }
+    Ok(())
+}
 
-    let input_path = PathBuf::from(input_path);
-    let output_path = PathBuf::from(output_path);
+/// Set the `Config` field of option `name` from the command line `value`.
+/// The field names of a config file are accepted too.
+fn set_option(config: &mut Config, name: &str, value: &str) -> Result<(), CliError> {
+    match name {
+        "color_mode" | "colormode" => {
+            config.color_mode = match value.trim() {
+                "bw" | "BW" | "binary" => ColorMode::Binary,
+                "color" => ColorMode::Color,
+                _ => {
+                    return Err(CliError::InvalidArguments(format!(
+                        "unknown ColorMode {}",
+                        value
+                    )))
+                }
+            }
+        }
+        "hierarchical" => {
+            config.hierarchical =
+                Hierarchical::from_str(value.trim()).map_err(CliError::InvalidArguments)?
+        }
+        "mode" => {
+            config.mode =
+                path_simplify_mode_from_str(value.trim()).map_err(CliError::InvalidArguments)?
+        }
+        "filter_speckle" => {
+            config.filter_speckle = parse_value(name, value, "a non-negative integer")?
+        }
+        "color_precision" => config.color_precision = parse_value(name, value, "an integer")?,
+        "gradient_step" | "layer_difference" => {
+            config.layer_difference = parse_value(name, value, "an integer")?
+        }
+        "corner_threshold" => config.corner_threshold = parse_value(name, value, "an integer")?,
+        "segment_length" | "length_threshold" => {
+            config.length_threshold = parse_value(name, value, "numeric")?
+        }
+        "splice_threshold" => config.splice_threshold = parse_value(name, value, "an integer")?,
+        "max_iterations" => {
+            config.max_iterations = parse_value(name, value, "a non-negative integer")?
+        }
+        "path_precision" => {
+            config.path_precision = Some(parse_value(name, value, "a non-negative integer")?)
+        }
+        "pixel_art" => config.pixel_art = parse_value(name, value, "`true` or `false`")?,
+        "palette" => {
+            let path = Path::new(value.trim());
+            let is_palette_file = matches!(
+                path.extension().and_then(|ext| ext.to_str()),
+                Some("gpl" | "ase")
+            );
+            let palette = if is_palette_file || path.is_file() {
+                Palette::read(path)
+            } else {
+                Palette::from_hex_list(value)
+            };
+            config.palette = Some(
+                palette.map_err(|err| CliError::InvalidArguments(format!("--palette {}", err)))?,
+            );
+        }
+        "colors" => config.colors = Some(parse_value(name, value, "a positive integer")?),
+        "quantize_method" => {
+            config.quantize_method =
+                QuantizeMethod::from_str(value.trim()).map_err(CliError::InvalidArguments)?
+        }
+        "quantize_space" => {
+            config.quantize_space =
+                ColorSpace::from_str(value.trim()).map_err(CliError::InvalidArguments)?
+        }
+        "color_space" => {
+            config.color_space =
+                ColorSpace::from_str(value.trim()).map_err(CliError::InvalidArguments)?
+        }
+        "centerline" => config.centerline = parse_value(name, value, "`true` or `false`")?,
+        "primitives" => config.primitives = parse_value(name, value, "`true` or `false`")?,
+        "orthogonal" => config.orthogonal = parse_value(name, value, "`true` or `false`")?,
+        "snap_angle" => config.snap_angle = parse_value(name, value, "an integer")?,
+        "grid" => config.grid = Some(parse_value(name, value, "a positive number")?),
+        "primitive_tolerance" => {
+            config.primitive_tolerance = parse_value(name, value, "a positive number")?
+        }
+        "arcs" => config.arcs = parse_value(name, value, "`true` or `false`")?,
+        "arc_tolerance" => config.arc_tolerance = parse_value(name, value, "a positive number")?,
+        "gradients" => config.gradients = parse_value(name, value, "`true` or `false`")?,
+        "gradient_tolerance" => {
+            config.gradient_tolerance = parse_value(name, value, "a positive number")?
+        }
+        "hybrid" => config.hybrid = parse_value(name, value, "`true` or `false`")?,
+        "hybrid_threshold" => {
+            config.hybrid_threshold = parse_value(name, value, "a positive integer")?
+        }
+        "alpha" => config.alpha = parse_value(name, value, "`true` or `false`")?,
+        "alpha_threshold" => {
+            config.alpha_threshold = parse_value(name, value, "an integer from 1 to 255")?
+        }
+        "background" => {
+            config.background =
+                Some(Background::from_str(value.trim()).map_err(CliError::InvalidArguments)?)
+        }
+        "background_tolerance" => {
+            config.background_tolerance = parse_value(name, value, "an integer from 0 to 255")?
+        }
+        "trim" => config.trim = parse_value(name, value, "`true` or `false`")?,
+        "trim_padding" => config.trim_padding = parse_value(name, value, "a non-negative integer")?,
+        "raster_format" => {
+            config.raster_format =
+                RasterFormat::from_str(value.trim()).map_err(CliError::InvalidArguments)?
+        }
+        _ => {
+            return Err(CliError::InvalidArguments(format!(
+                "unknown option {}",
+                name
+            )))
+        }
+    }
+    Ok(())
+}
 
-    if let Some(value) = matches.value_of("preset") {
-        config = Config::from_preset(Preset::from_str(value).unwrap());
+/// The `--jobs` option, defaulting to the number of CPUs
+fn parse_jobs(matches: &ArgMatches) -> Result<usize, CliError> {
+    match parse_option::<usize>(matches, "jobs", "a positive integer")? {
+        Some(0) => Err(CliError::InvalidArguments(String::from(
+            "--jobs is not a positive integer: 0",
+        ))),
+        Some(value) => Ok(value),
+        None => Ok(std::thread::available_parallelism().map_or(1, |n| n.get())),
     }
+}
+
+/// Parse the value of option `name`, if given, describing the expected `kind` of value on failure
+fn parse_option<T: FromStr>(
+    matches: &ArgMatches,
+    name: &str,
+    kind: &str,
+) -> Result<Option<T>, CliError> {
+    matches
+        .value_of(name)
+        .map(|value| parse_value(name, value, kind))
+        .transpose()
+}
 
-    if let Some(value) = matches.value_of("color_mode") {
-        config.color_mode = ColorMode::from_str(if value.trim() == "bw" || value.trim() == "BW" {
-            "binary"
-        } else {
-            "color"
-        })
-        .unwrap()
+/// Parse `value` given for option `name`, describing the expected `kind` of value on failure
+fn parse_value<T: FromStr>(name: &str, value: &str, kind: &str) -> Result<T, CliError> {
+    value
+        .trim()
+        .parse::<T>()
+        .map_err(|_| CliError::InvalidArguments(format!("--{} is not {}: {}", name, kind, value)))
+}
+
+/// The command line option corresponding to a `Config` field
+fn option_name(field: &str) -> &str {
+    match field {
+        "layer_difference" => "gradient_step",
+        "length_threshold" => "segment_length",
+        _ => field,
     }
+}
 
-    if let Some(value) = matches.value_of("hierarchical") {
-        config.hierarchical = Hierarchical::from_str(value).unwrap()
+/// Whether `path` is `-`, standing for stdin or stdout
+fn is_stdio(path: &Path) -> bool {
+    path == Path::new("-")
+}
+
+fn convert(
+    input_path: &Path,
+    output_path: &Path,
+    config: &ConfigLayers,
+) -> Result<ConversionStats, CliError> {
+    let img = if is_stdio(input_path) {
+        let mut bytes = vec![];
+        io::stdin()
+            .read_to_end(&mut bytes)
+            .map_err(|err| CliError::UnreadableInput(format!("Cannot read from stdin: {}", err)))?;
+        vtracer::read_image_from_memory(&bytes)
+    } else {
+        vtracer::read_image(input_path)
+    }
+    .map_err(CliError::UnreadableInput)?;
+
+    let config = config.for_image(&img)?;
+    let (svg, stats) =
+        vtracer::convert_with_stats(img, config).map_err(CliError::ConversionFailed)?;
+
+    if is_stdio(output_path) {
+        let mut stdout = io::stdout().lock();
+        write!(stdout, "{}", svg)
+            .and_then(|()| stdout.flush())
+            .map_err(|err| {
+                CliError::UnwritableOutput(format!("Cannot write to stdout: {}", err))
+            })?;
+    } else {
+        vtracer::write_svg(svg, output_path).map_err(CliError::UnwritableOutput)?;
     }
+    Ok(stats)
+}
 
-    if let Some(value) = matches.value_of("mode") {
-        let value = value.trim();
-        config.mode = path_simplify_mode_from_str(if value == "pixel" {
-            "none"
-        } else if value == "polygon" {
-            "polygon"
-        } else if value == "spline" {
-            "spline"
-        } else {
-            panic!("Parser Error: Curve fitting mode is invalid: {}", value);
-        });
+fn run() -> Result<(), CliError> {
+    let args = match config_from_args()? {
+        Command::Convert(args) => args,
+        Command::Compare(args) => return compare::run(args),
+        Command::Tune(args) => return tune_cmd::run(args),
+        Command::Sweep(args) => return sweep::run(args),
+    };
+    let Args {
+        input_path,
+        output_path,
+        config,
+        batch_options,
+        quiet,
+        reporter,
+    } = args;
+
+    let input = input_path.to_string_lossy();
+    if batch::is_batch_input(&input) {
+        return batch::convert_batch(
+            &input,
+            &output_path,
+            &config,
+            &batch_options,
+            reporter.as_ref(),
+        );
     }
 
-    if let Some(value) = matches.value_of("filter_speckle") {
-        if value.trim().parse::<usize>().is_ok() {
-            // is numeric
-            let value = value.trim().parse::<usize>().unwrap();
-            if value > 16 {
-                panic!("Out of Range Error: Filter speckle is invalid at {}. It must be within [0,16].", value);
-            }
-            config.filter_speckle = value;
-        } else {
-            panic!(
-                "Parser Error: Filter speckle is not a positive integer: {}.",
-                value
-            );
-        }
+    let stats = convert(&input_path, &output_path, &config)?;
+    if let Some(reporter) = reporter {
+        reporter.write(&input_path, &output_path, &stats)?;
+    }
+    if !quiet && !is_stdio(&output_path) {
+        println!("Conversion successful.");
     }
+    Ok(())
+}
 
-    if let Some(value) = matches.value_of("color_precision") {
-        if value.trim().parse::<i32>().is_ok() {
-            // is numeric
-            let value = value.trim().parse::<i32>().unwrap();
-            if value < 1 || value > 8 {
-                panic!("Out of Range Error: Color precision is invalid at {}. It must be within [1,8].", value);
-            }
-            config.color_precision = value;
-        } else {
-            panic!(
-                "Parser Error: Color precision is not an integer: {}.",
-                value
-            );
-        }
+fn main() {
+    if let Err(err) = run() {
+        eprintln!("{}", err);
+        std::process::exit(err.exit_code());
     }
+}
 
-    if let Some(value) = matches.value_of("gradient_step") {
-        if value.trim().parse::<i32>().is_ok() {
-            // is numeric
-            let value = value.trim().parse::<i32>().unwrap();
-            if value < 0 || value > 255 {
-                panic!("Out of Range Error: Gradient step is invalid at {}. It must be within [0,255].", value);
-            }
-            config.layer_difference = value;
-        } else {
-            panic!("Parser Error: Gradient step is not an integer: {}.", value);
-        }
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The config layers of a command line made of `args` after the program name
+    fn layers(args: &[&str]) -> Result<ConfigLayers, CliError> {
+        let matches = App::new("vtracer")
+            .args(&config_args())
+            .get_matches_from_safe(std::iter::once("vtracer").chain(args.iter().copied()))
+            .map_err(|err| CliError::InvalidArguments(err.message))?;
+        ConfigLayers::from_matches(&matches)
     }
 
-    if let Some(value) = matches.value_of("corner_threshold") {
-        if value.trim().parse::<i32>().is_ok() {
-            // is numeric
-            let value = value.trim().parse::<i32>().unwrap();
-            if value < 0 || value > 180 {
-                panic!("Out of Range Error: Corner threshold is invalid at {}. It must be within [0,180].", value);
-            }
-            config.corner_threshold = value
-        } else {
-            panic!("Parser Error: Corner threshold is not numeric: {}.", value);
-        }
+    fn config(args: &[&str]) -> Config {
+        layers(args)
+            .unwrap()
+            .for_image(&ColorImage::new_w_h(1, 1))
+            .unwrap()
     }
 
-    if let Some(value) = matches.value_of("segment_length") {
-        if value.trim().parse::<f64>().is_ok() {
-            // is numeric
-            let value = value.trim().parse::<f64>().unwrap();
-            if value < 3.5 || value > 10.0 {
-                panic!("Out of Range Error: Segment length is invalid at {}. It must be within [3.5,10].", value);
-            }
-            config.length_threshold = value;
-        } else {
-            panic!("Parser Error: Segment length is not numeric: {}.", value);
-        }
+    /// Write `content` to a config file named `name` in a directory of its own
+    fn config_file(name: &str, content: &str) -> String {
+        let dir = std::env::temp_dir().join(format!("vtracer-config-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(name);
+        std::fs::write(&path, content).unwrap();
+        path.to_string_lossy().into_owned()
     }
 
-    if let Some(value) = matches.value_of("splice_threshold") {
-        if value.trim().parse::<i32>().is_ok() {
-            // is numeric
-            let value = value.trim().parse::<i32>().unwrap();
-            if value < 0 || value > 180 {
-                panic!("Out of Range Error: Segment length is invalid at {}. It must be within [0,180].", value);
-            }
-            config.splice_threshold = value;
-        } else {
-            panic!("Parser Error: Segment length is not numeric: {}.", value);
-        }
+    #[test]
+    fn toml_file_overrides_preset() {
+        let path = config_file("speckle.toml", "filter_speckle = 2\nmode = \"polygon\"\n");
+        let config = config(&["--preset", "photo", "--config", &path]);
+        assert_eq!(config.filter_speckle, 2);
+        assert_eq!(vtracer::path_simplify_mode_to_str(&config.mode), "polygon");
+        // the rest of the preset stays
+        assert_eq!(config.corner_threshold, 180);
+        assert_eq!(config.layer_difference, 48);
     }
 
-    if let Some(value) = matches.value_of("path_precision") {
-        if value.trim().parse::<u32>().is_ok() {
-            // is numeric
-            let value = value.trim().parse::<u32>().ok();
-            config.path_precision = value;
-        } else {
-            panic!(
-                "Parser Error: Path precision is not an unsigned integer: {}.",
-                value
-            );
-        }
+    #[test]
+    fn json_file_overrides_preset() {
+        let path = config_file(
+            "precision.json",
+            r#"{ "color_precision": 3, "gradients": true }"#,
+        );
+        let config = config(&["--preset", "poster", "--config", &path]);
+        assert_eq!(config.color_precision, 3);
+        assert!(config.gradients);
+        assert_eq!(config.filter_speckle, 4);
     }
 
-    (input_path, output_path, config)
-}
+    #[test]
+    fn flags_override_file() {
+        let path = config_file("flags.toml", "filter_speckle = 2\ncolor_precision = 3\n");
+        let config = config(&[
+            "--preset",
+            "photo",
+            "--config",
+            &path,
+            "--filter_speckle",
+            "7",
+            "--arcs",
+        ]);
+        assert_eq!(config.filter_speckle, 7);
+        assert_eq!(config.color_precision, 3);
+        assert!(config.arcs);
+    }
 
-fn main() {
-    let (input_path, output_path, config) = config_from_args();
-    let result = converter::convert_image_to_svg(&input_path, &output_path, config);
-    match result {
-        Ok(()) => {
-            println!("Conversion successful.");
-        }
-        Err(msg) => {
-            panic!("Conversion failed with error message: {}", msg);
+    #[test]
+    fn invalid_file_is_an_invalid_argument() {
+        for (name, content) in [
+            ("unknown.toml", "no_such_option = 1\n"),
+            ("type.toml", "filter_speckle = \"many\"\n"),
+            ("unknown.json", r#"{ "no_such_option": 1 }"#),
+            ("type.json", r#"{ "color_precision": [] }"#),
+            ("range.toml", "color_precision = 12\n"),
+        ] {
+            let path = config_file(name, content);
+            match layers(&["--config", &path]) {
+                Err(err) => assert_eq!(err.exit_code(), 2, "{}: {}", name, err),
+                Ok(_) => panic!("{} was accepted", name),
+            }
         }
     }
 }
@@ -0,0 +1,371 @@
+use std::f64::consts::FRAC_PI_2;
+
+use visioncortex::{CompoundPathElement, PathF64, PointF64};
+
+use super::centerline::simplify;
+use super::rasterizer::distance;
+use super::svg::SvgFile;
+
+/// Longest edge cut across a corner by tracing that is removed to make the corner square
+const CHAMFER_LENGTH: f64 = 3.0;
+
+/// The line an edge lies on after snapping
+struct Line {
+    point: PointF64,
+    /// Unit direction
+    direction: PointF64,
+    length: f64,
+    snapped: bool,
+    /// The index of the vertex the line starts at
+    start: usize,
+}
+
+/// Straighten the polygons of `svg` for technical drawings. Edges within `snap_angle` radians
+/// of the axes are made exactly horizontal or vertical; in a polygon that is turned as a whole,
+/// edges near its own two perpendicular directions are snapped to those instead, so its corners
+/// become right angles. The vertices are then rounded to multiples of `grid` pixels, if given.
+pub(crate) fn orthogonalize(svg: &mut SvgFile, snap_angle: Option<f64>, grid: Option<f64>) {
+    for path in svg.paths.iter_mut() {
+        let closed = path.stroke_width.is_none();
+        for element in path.path.iter_mut() {
+            let points: Vec<PointF64> = match element {
+                CompoundPathElement::PathI32(path) => path
+                    .iter()
+                    .map(|p| PointF64::new(p.x as f64, p.y as f64))
+                    .collect(),
+                CompoundPathElement::PathF64(path) => path.iter().copied().collect(),
+                // only polygons are straightened
+                CompoundPathElement::Spline(_) => continue,
+            };
+            let mut points = match snap_angle {
+                Some(snap_angle) => snap_edges(points, closed, snap_angle),
+                None => points,
+            };
+            if let Some(grid) = grid {
+                points = snap_to_grid(points, closed, grid);
+            }
+            *element = CompoundPathElement::PathF64(PathF64::from_points(points));
+        }
+    }
+}
+
+fn snap_edges(mut points: Vec<PointF64>, closed: bool, snap_angle: f64) -> Vec<PointF64> {
+    points.dedup();
+    let repeats_first = closed && points.len() > 1 && points.first() == points.last();
+    if repeats_first {
+        points.pop();
+    }
+    if points.len() < 3 {
+        return restore(points, repeats_first);
+    }
+    // merge the pixel steps along slightly slanted edges into one edge
+    let points = if closed {
+        let mut ring = points.clone();
+        ring.push(points[0]);
+        let mut ring = simplify(&ring);
+        ring.pop();
+        ring
+    } else {
+        simplify(&points)
+    };
+    let edges = if closed {
+        points.len()
+    } else {
+        points.len() - 1
+    };
+    if edges < 2 {
+        return restore(points, repeats_first);
+    }
+
+    let angles: Vec<(f64, f64)> = (0..edges)
+        .map(|i| {
+            let (a, b) = (points[i], points[(i + 1) % points.len()]);
+            ((b.y - a.y).atan2(b.x - a.x), distance(a, b))
+        })
+        .collect();
+    let grain = rectilinear_grain(&angles, snap_angle).unwrap_or(0.0);
+
+    let mut lines: Vec<Line> = vec![];
+    for (i, &(angle, length)) in angles.iter().enumerate() {
+        let (a, b) = (points[i], points[(i + 1) % points.len()]);
+        let (quarter, turn) = turn(angle, grain);
+        let snapped = turn.abs() <= snap_angle;
+        let direction = if snapped {
+            // turned by whole quarters exactly, so that the axes stay exact
+            let (sin, cos) = grain.sin_cos();
+            match quarter.rem_euclid(4.0) as i32 {
+                0 => PointF64::new(cos, sin),
+                1 => PointF64::new(-sin, cos),
+                2 => PointF64::new(-cos, -sin),
+                _ => PointF64::new(sin, -cos),
+            }
+        } else {
+            PointF64::new(angle.cos(), angle.sin())
+        };
+        let midpoint = PointF64::new((a.x + b.x) / 2.0, (a.y + b.y) / 2.0);
+
+        // a run of edges snapped to the same direction becomes one line through their
+        // weighted middle
+        if let Some(last) = lines.last_mut() {
+            if snapped && last.snapped && parallel(last.direction, direction) {
+                let total = last.length + length;
+                last.point = PointF64::new(
+                    (last.point.x * last.length + midpoint.x * length) / total,
+                    (last.point.y * last.length + midpoint.y * length) / total,
+                );
+                last.length = total;
+                continue;
+            }
+        }
+        lines.push(Line {
+            point: midpoint,
+            direction,
+            length,
+            snapped,
+            start: i,
+        });
+    }
+    if closed && lines.len() > 1 {
+        let (first, last) = (&lines[0], &lines[lines.len() - 1]);
+        if first.snapped && last.snapped && parallel(first.direction, last.direction) {
+            let last = lines.pop().unwrap();
+            let first = &mut lines[0];
+            let total = first.length + last.length;
+            first.point = PointF64::new(
+                (first.point.x * first.length + last.point.x * last.length) / total,
+                (first.point.y * first.length + last.point.y * last.length) / total,
+            );
+            first.length = total;
+            first.start = last.start;
+        }
+    }
+    // square off the corners that tracing cut across
+    let count = lines.len();
+    let chamfers: Vec<bool> = (0..count)
+        .map(|i| {
+            let line = &lines[i];
+            if line.snapped
+                || line.length > CHAMFER_LENGTH
+                || (!closed && (i == 0 || i == count - 1))
+            {
+                return false;
+            }
+            let (previous, next) = (&lines[(i + count - 1) % count], &lines[(i + 1) % count]);
+            previous.snapped && next.snapped && !parallel(previous.direction, next.direction)
+        })
+        .collect();
+    let mut chamfers = chamfers.into_iter();
+    lines.retain(|_| !chamfers.next().unwrap());
+    if closed && lines.len() < 3 {
+        return restore(points, repeats_first);
+    }
+
+    let mut snapped: Vec<PointF64> = Vec::with_capacity(lines.len() + 1);
+    if !closed {
+        snapped.push(project(&lines[0], points[0]));
+    }
+    let joints = if closed { lines.len() } else { lines.len() - 1 };
+    for i in 0..joints {
+        let (previous, next) = if closed {
+            (&lines[(i + lines.len() - 1) % lines.len()], &lines[i])
+        } else {
+            (&lines[i], &lines[i + 1])
+        };
+        let original = points[next.start];
+        // keep the vertex where the lines are nearly parallel and meet far away
+        let reach = (previous.length + next.length) / 2.0;
+        snapped.push(
+            intersect(previous, next)
+                .filter(|&p| distance(p, original) <= reach)
+                .unwrap_or(original),
+        );
+    }
+    if !closed {
+        snapped.push(project(&lines[lines.len() - 1], points[points.len() - 1]));
+    }
+    restore(snapped, repeats_first)
+}
+
+/// The direction, modulo a right angle, of the sides of a shape made of edges at right angles,
+/// like a rectangle turned as a whole. `None` unless edges near two perpendicular directions
+/// make up at least half the outline.
+fn rectilinear_grain(angles: &[(f64, f64)], snap_angle: f64) -> Option<f64> {
+    let grain = mean_direction(angles);
+    let mut lengths = [0.0; 2];
+    let mut aligned = vec![];
+    for &(angle, length) in angles {
+        let (quarter, turn) = turn(angle, grain);
+        if turn.abs() <= snap_angle {
+            lengths[quarter.rem_euclid(2.0) as usize] += length;
+            aligned.push((angle, length));
+        }
+    }
+    let total: f64 = angles.iter().map(|&(_, length)| length).sum();
+    if lengths[0] == 0.0 || lengths[1] == 0.0 || lengths[0] + lengths[1] < total / 2.0 {
+        return None;
+    }
+    let grain = mean_direction(&aligned);
+    Some(if grain.abs() <= snap_angle {
+        0.0
+    } else {
+        grain
+    })
+}
+
+/// The length weighted mean direction of edges modulo a right angle, taken as the mean of four
+/// times their angles, in which directions a right angle apart coincide
+fn mean_direction(angles: &[(f64, f64)]) -> f64 {
+    let (sin, cos) = angles
+        .iter()
+        .fold((0.0, 0.0), |(sin, cos), &(angle, length)| {
+            (
+                sin + length * (4.0 * angle).sin(),
+                cos + length * (4.0 * angle).cos(),
+            )
+        });
+    sin.atan2(cos) / 4.0
+}
+
+/// The nearest of the four directions of `grain` to `angle`, in quarter turns, and how far
+/// `angle` is turned from it
+fn turn(angle: f64, grain: f64) -> (f64, f64) {
+    let quarter = ((angle - grain) / FRAC_PI_2).round();
+    (quarter, angle - grain - FRAC_PI_2 * quarter)
+}
+
+/// Round the vertices to multiples of `grid`, unless that collapses the polygon
+fn snap_to_grid(points: Vec<PointF64>, closed: bool, grid: f64) -> Vec<PointF64> {
+    let round = |c: f64| (c / grid).round() * grid;
+    let mut rounded: Vec<PointF64> = points
+        .iter()
+        .map(|p| PointF64::new(round(p.x), round(p.y)))
+        .collect();
+    rounded.dedup();
+    let distinct = if closed && rounded.len() > 1 && rounded.first() == rounded.last() {
+        rounded.len() - 1
+    } else {
+        rounded.len()
+    };
+    if distinct < if closed { 3 } else { 2 } {
+        return points;
+    }
+    rounded
+}
+
+fn restore(mut points: Vec<PointF64>, repeats_first: bool) -> Vec<PointF64> {
+    if repeats_first {
+        points.push(points[0]);
+    }
+    points
+}
+
+fn parallel(a: PointF64, b: PointF64) -> bool {
+    (a.x * b.y - a.y * b.x).abs() < 1e-9
+}
+
+fn intersect(a: &Line, b: &Line) -> Option<PointF64> {
+    let cross = a.direction.x * b.direction.y - a.direction.y * b.direction.x;
+    if cross.abs() < 1e-9 {
+        return None;
+    }
+    let (dx, dy) = (b.point.x - a.point.x, b.point.y - a.point.y);
+    let t = (dx * b.direction.y - dy * b.direction.x) / cross;
+    Some(PointF64::new(
+        a.point.x + t * a.direction.x,
+        a.point.y + t * a.direction.y,
+    ))
+}
+
+fn project(line: &Line, p: PointF64) -> PointF64 {
+    let t = (p.x - line.point.x) * line.direction.x + (p.y - line.point.y) * line.direction.y;
+    PointF64::new(
+        line.point.x + t * line.direction.x,
+        line.point.y + t * line.direction.y,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SNAP_ANGLE: f64 = 3.0 * std::f64::consts::PI / 180.0;
+
+    fn points(coordinates: &[(f64, f64)]) -> Vec<PointF64> {
+        coordinates
+            .iter()
+            .map(|&(x, y)| PointF64::new(x, y))
+            .collect()
+    }
+
+    fn assert_near(a: PointF64, b: PointF64) {
+        assert!(distance(a, b) < 1e-6, "{:?} is not {:?}", a, b);
+    }
+
+    #[test]
+    fn edge_slightly_off_axis_is_snapped() {
+        // the first edge is about 1.7 degrees off horizontal
+        let snapped = snap_edges(
+            points(&[(0.0, 0.0), (50.0, 1.5), (50.0, 40.0)]),
+            false,
+            SNAP_ANGLE,
+        );
+        assert_eq!(snapped.len(), 3);
+        assert_near(snapped[0], PointF64::new(0.0, 0.75));
+        assert_near(snapped[1], PointF64::new(50.0, 0.75));
+        assert_near(snapped[2], PointF64::new(50.0, 40.0));
+    }
+
+    #[test]
+    fn edge_beyond_snap_angle_is_kept() {
+        // the top edge is about 20 degrees off horizontal
+        let outline = points(&[(0.0, 0.0), (40.0, 0.0), (40.0, 30.0), (0.0, 15.0)]);
+        let snapped = snap_edges(outline.clone(), true, SNAP_ANGLE);
+        assert_eq!(snapped.len(), outline.len());
+        for p in outline {
+            assert!(snapped.iter().any(|&q| distance(p, q) < 1e-6), "{:?}", p);
+        }
+    }
+
+    #[test]
+    fn rotated_rectangle_gets_right_angles() {
+        let (sin, cos) = 10f64.to_radians().sin_cos();
+        let rotate = |x: f64, y: f64| (x * cos - y * sin + 20.0, x * sin + y * cos + 20.0);
+        let mut corners = vec![
+            rotate(0.0, 0.0),
+            rotate(60.0, 0.0),
+            rotate(60.0, 30.0),
+            rotate(0.0, 30.0),
+        ];
+        // traced a little off
+        corners[2].0 += 0.6;
+        corners[3].1 -= 0.4;
+        let snapped = snap_edges(points(&corners), true, SNAP_ANGLE);
+        assert_eq!(snapped.len(), 4);
+        for i in 0..4 {
+            let (a, b, c) = (snapped[i], snapped[(i + 1) % 4], snapped[(i + 2) % 4]);
+            let (u, v) = (b - a, c - b);
+            let cosine = (u.x * v.x + u.y * v.y) / (distance(a, b) * distance(b, c));
+            assert!(cosine.abs() < 1e-9, "corner {} is not square", i);
+        }
+        // the sides keep the rectangle's own direction instead of the axes'
+        let side = snapped[1] - snapped[0];
+        assert!((side.y.atan2(side.x).to_degrees() - 10.0).abs() < 1.0);
+    }
+
+    #[test]
+    fn grid_rounds_vertices() {
+        let rounded = snap_to_grid(
+            points(&[(1.2, 0.9), (9.7, 1.4), (10.3, 11.9), (0.4, 10.2)]),
+            true,
+            5.0,
+        );
+        assert_eq!(
+            rounded,
+            points(&[(0.0, 0.0), (10.0, 0.0), (10.0, 10.0), (0.0, 10.0)])
+        );
+
+        // rounding that would collapse the polygon leaves it alone
+        let small = points(&[(1.0, 1.0), (2.0, 1.0), (2.0, 2.0)]);
+        assert_eq!(snap_to_grid(small.clone(), true, 10.0), small);
+    }
+}
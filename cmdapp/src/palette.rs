@@ -0,0 +1,372 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use visioncortex::{Color, ColorImage};
+
+use super::color_space::{lab_to_srgb, srgb_to_lab, Lab};
+use super::svg::SvgFile;
+
+/// A fixed set of colors the output is restricted to.
+/// Colors are matched to their nearest entry by CIELAB distance, which follows perceived
+/// difference much more closely than distance in RGB.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Palette {
+    colors: Vec<Color>,
+    labs: Vec<Lab>,
+}
+
+impl Palette {
+    pub fn new(colors: Vec<Color>) -> Self {
+        let labs = colors.iter().map(srgb_to_lab).collect();
+        Self { colors, labs }
+    }
+
+    pub fn colors(&self) -> &[Color] {
+        &self.colors
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.colors.is_empty()
+    }
+
+    /// Parse a list of hex colors like `#ff8800, #08f`, separated by commas or whitespace.
+    /// The leading `#` is optional.
+    pub fn from_hex_list(list: &str) -> Result<Self, String> {
+        let colors = list
+            .split(|c: char| c == ',' || c == ';' || c.is_whitespace())
+            .filter(|hex| !hex.is_empty())
+            .map(parse_hex)
+            .collect::<Result<Vec<_>, _>>()?;
+        non_empty(colors)
+    }
+
+    /// Parse a GIMP palette (`.gpl`), one `R G B name` line per color
+    pub fn from_gpl(text: &str) -> Result<Self, String> {
+        let mut lines = text.lines();
+        if lines.next().map(str::trim) != Some("GIMP Palette") {
+            return Err(String::from(
+                "Not a GIMP palette: missing `GIMP Palette` header",
+            ));
+        }
+        let mut colors = vec![];
+        for line in lines {
+            let line = line.trim();
+            if line.is_empty()
+                || line.starts_with('#')
+                || line.starts_with("Name:")
+                || line.starts_with("Columns:")
+            {
+                continue;
+            }
+            let channels = line
+                .split_whitespace()
+                .take(3)
+                .map(|channel| channel.parse::<u8>())
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|_| format!("Invalid color in GIMP palette: {}", line))?;
+            match channels[..] {
+                [r, g, b] => colors.push(Color::new(r, g, b)),
+                _ => return Err(format!("Invalid color in GIMP palette: {}", line)),
+            }
+        }
+        non_empty(colors)
+    }
+
+    /// Parse an Adobe swatch exchange file (`.ase`). RGB, CMYK, LAB and gray swatches are
+    /// supported; groups are flattened.
+    pub fn from_ase(bytes: &[u8]) -> Result<Self, String> {
+        let truncated = || String::from("Truncated ASE palette");
+        let mut reader = Reader { bytes, pos: 0 };
+        if reader.take(4).ok_or_else(truncated)? != b"ASEF" {
+            return Err(String::from("Not an ASE palette: missing `ASEF` signature"));
+        }
+        reader.take(4).ok_or_else(truncated)?; // version
+        let num_blocks = reader.u32().ok_or_else(truncated)?;
+
+        let mut colors = vec![];
+        for _ in 0..num_blocks {
+            let block_type = reader.u16().ok_or_else(truncated)?;
+            let length = reader.u32().ok_or_else(truncated)? as usize;
+            let block = reader.take(length).ok_or_else(truncated)?;
+            // group start and end blocks only hold a name
+            if block_type != 0x0001 {
+                continue;
+            }
+            let mut block = Reader {
+                bytes: block,
+                pos: 0,
+            };
+            let name_length = block.u16().ok_or_else(truncated)? as usize;
+            block.take(2 * name_length).ok_or_else(truncated)?;
+            let model = block.take(4).ok_or_else(truncated)?;
+            let num_values = match model {
+                b"RGB " | b"LAB " => 3,
+                b"CMYK" => 4,
+                b"Gray" => 1,
+                _ => {
+                    return Err(format!(
+                        "Unsupported color model in ASE palette: {}",
+                        String::from_utf8_lossy(model)
+                    ))
+                }
+            };
+            let values = (0..num_values)
+                .map(|_| block.f32().map(|value| value as f64))
+                .collect::<Option<Vec<_>>>()
+                .ok_or_else(truncated)?;
+            let channel = |value: f64| (value.clamp(0.0, 1.0) * 255.0).round() as u8;
+            colors.push(match (model, &values[..]) {
+                (b"RGB ", &[r, g, b]) => Color::new(channel(r), channel(g), channel(b)),
+                (b"CMYK", &[c, m, y, k]) => Color::new(
+                    channel((1.0 - c) * (1.0 - k)),
+                    channel((1.0 - m) * (1.0 - k)),
+                    channel((1.0 - y) * (1.0 - k)),
+                ),
+                // lightness is stored as a fraction
+                (b"LAB ", &[l, a, b]) => lab_to_srgb(&Lab { l: l * 100.0, a, b }),
+                (_, &[gray]) => Color::new(channel(gray), channel(gray), channel(gray)),
+                _ => unreachable!("the number of values matches the model"),
+            });
+        }
+        non_empty(colors)
+    }
+
+    /// Read a palette file: a GIMP `.gpl` or Adobe `.ase` palette, or a text file listing hex colors
+    pub fn read(path: &Path) -> Result<Self, String> {
+        let bytes = fs::read(path)
+            .map_err(|err| format!("Cannot read palette {}: {}", path.display(), err))?;
+        let extension = path
+            .extension()
+            .map(|extension| extension.to_string_lossy().to_lowercase());
+        match extension.as_deref() {
+            Some("ase") => Self::from_ase(&bytes),
+            Some("gpl") => Self::from_gpl(&String::from_utf8_lossy(&bytes)),
+            _ => Self::from_hex_list(&String::from_utf8_lossy(&bytes)),
+        }
+    }
+
+    /// The entry perceptually closest to `color`
+    pub fn nearest(&self, color: &Color) -> Color {
+        let lab = srgb_to_lab(color);
+        let index = self
+            .labs
+            .iter()
+            .enumerate()
+            .min_by(|(_, a), (_, b)| lab.distance_squared(a).total_cmp(&lab.distance_squared(b)))
+            .map_or(0, |(index, _)| index);
+        self.colors[index]
+    }
+
+    /// Replace the color of every visible pixel by its nearest entry, keeping alpha
+    pub(crate) fn snap_image(&self, img: &mut ColorImage) {
+        let mut nearest: HashMap<[u8; 3], Color> = HashMap::new();
+        for pixel in img.pixels.chunks_exact_mut(4) {
+            if pixel[3] == 0 {
+                continue;
+            }
+            let rgb = [pixel[0], pixel[1], pixel[2]];
+            let color = *nearest
+                .entry(rgb)
+                .or_insert_with(|| self.nearest(&Color::new(rgb[0], rgb[1], rgb[2])));
+            pixel[..3].copy_from_slice(&[color.r, color.g, color.b]);
+        }
+    }
+
+    /// Replace the color of every path by its nearest entry, then merge consecutive paths that
+    /// ended up with the same color
+    pub(crate) fn snap_svg(&self, svg: &mut SvgFile) {
+        for path in svg.paths.iter_mut() {
+            // the opacity of a path is kept, as palettes are of opaque colors
+            path.color = Color {
+                a: path.color.a,
+                ..self.nearest(&path.color)
+            };
+        }
+        svg.merge_same_color_paths();
+    }
+
+    /// The entries as `#RRGGBB` strings
+    pub fn to_hex_strings(&self) -> Vec<String> {
+        self.colors.iter().map(Color::to_hex_string).collect()
+    }
+}
+
+pub(crate) fn parse_hex(hex: &str) -> Result<Color, String> {
+    let invalid = || format!("Invalid hex color: {}", hex);
+    let digits = hex.strip_prefix('#').unwrap_or(hex);
+    if !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(invalid());
+    }
+    let channel = |s: &str| u8::from_str_radix(s, 16).map_err(|_| invalid());
+    match digits.len() {
+        3 => {
+            let [r, g, b] = [0, 1, 2].map(|i| channel(&digits[i..i + 1]).map(|c| c * 17));
+            Ok(Color::new(r?, g?, b?))
+        }
+        6 => Ok(Color::new(
+            channel(&digits[0..2])?,
+            channel(&digits[2..4])?,
+            channel(&digits[4..6])?,
+        )),
+        _ => Err(invalid()),
+    }
+}
+
+fn non_empty(colors: Vec<Color>) -> Result<Palette, String> {
+    if colors.is_empty() {
+        Err(String::from("Palette has no colors"))
+    } else {
+        Ok(Palette::new(colors))
+    }
+}
+
+/// Reads big-endian values from an ASE file
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        let slice = self.bytes.get(self.pos..self.pos.checked_add(len)?)?;
+        self.pos += len;
+        Some(slice)
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        self.take(2).map(|b| u16::from_be_bytes([b[0], b[1]]))
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        self.take(4)
+            .map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    fn f32(&mut self) -> Option<f32> {
+        self.u32().map(f32::from_bits)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for Palette {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.to_hex_strings())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Palette {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let colors = Vec::<String>::deserialize(deserializer)?;
+        Self::from_hex_list(&colors.join(",")).map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An ASE file holding the given color blocks, each a model and its values
+    fn ase(swatches: &[(&[u8; 4], &[f32])]) -> Vec<u8> {
+        let mut bytes = b"ASEF".to_vec();
+        bytes.extend_from_slice(&[0, 1, 0, 0]);
+        bytes.extend_from_slice(&(swatches.len() as u32).to_be_bytes());
+        for (model, values) in swatches {
+            // the name `a`, as a null terminated UTF-16 string
+            let mut block = vec![0, 2, 0, b'a', 0, 0];
+            block.extend_from_slice(*model);
+            for value in values.iter() {
+                block.extend_from_slice(&value.to_be_bytes());
+            }
+            block.extend_from_slice(&[0, 2]); // normal color type
+            bytes.extend_from_slice(&[0, 1]);
+            bytes.extend_from_slice(&(block.len() as u32).to_be_bytes());
+            bytes.extend_from_slice(&block);
+        }
+        bytes
+    }
+
+    #[test]
+    fn gpl() {
+        let text =
+            "GIMP Palette\nName: Test\nColumns: 2\n# comment\n\n255 0 0 Red\n  0 128 255\tBlue\n";
+        let palette = Palette::from_gpl(text).unwrap();
+        assert_eq!(
+            palette.colors(),
+            &[Color::new(255, 0, 0), Color::new(0, 128, 255)]
+        );
+    }
+
+    #[test]
+    fn malformed_gpl() {
+        assert!(Palette::from_gpl("255 0 0 Red\n").is_err());
+        assert!(Palette::from_gpl("GIMP Palette\n256 0 0 Red\n").is_err());
+        assert!(Palette::from_gpl("GIMP Palette\n255 0\n").is_err());
+        assert!(Palette::from_gpl("GIMP Palette\nName: Empty\n").is_err());
+    }
+
+    #[test]
+    fn ase_swatches() {
+        let bytes = ase(&[
+            (b"RGB ", &[1.0, 0.5, 0.0]),
+            (b"CMYK", &[0.0, 1.0, 1.0, 0.0]),
+            (b"Gray", &[0.2]),
+        ]);
+        let palette = Palette::from_ase(&bytes).unwrap();
+        assert_eq!(
+            palette.colors(),
+            &[
+                Color::new(255, 128, 0),
+                Color::new(255, 0, 0),
+                Color::new(51, 51, 51)
+            ]
+        );
+    }
+
+    #[test]
+    fn malformed_ase() {
+        let bytes = ase(&[(b"RGB ", &[1.0, 0.5, 0.0])]);
+        for len in 0..bytes.len() {
+            assert!(Palette::from_ase(&bytes[..len]).is_err(), "length {}", len);
+        }
+        assert!(Palette::from_ase(b"GIMP Palette\n").is_err());
+        assert!(Palette::from_ase(&ase(&[(b"HSV ", &[0.0, 0.0, 0.0])])).is_err());
+        assert!(Palette::from_ase(&ase(&[])).is_err());
+    }
+
+    #[test]
+    fn hex_list() {
+        let palette = Palette::from_hex_list("#ff8800, 08f;\n#000000").unwrap();
+        assert_eq!(
+            palette.colors(),
+            &[
+                Color::new(255, 136, 0),
+                Color::new(0, 136, 255),
+                Color::new(0, 0, 0)
+            ]
+        );
+        assert!(Palette::from_hex_list("#ff88").is_err());
+        assert!(Palette::from_hex_list("#gg0000").is_err());
+        assert!(Palette::from_hex_list(" , ").is_err());
+    }
+
+    #[test]
+    fn nearest_is_perceptual() {
+        let palette = Palette::new(vec![
+            Color::new(0, 0, 0),
+            Color::new(0, 0, 255),
+            Color::new(128, 128, 128),
+            Color::new(255, 255, 255),
+        ]);
+        // closer to gray in RGB, but looks blue
+        assert_eq!(
+            palette.nearest(&Color::new(60, 60, 170)),
+            Color::new(0, 0, 255)
+        );
+        assert_eq!(
+            palette.nearest(&Color::new(250, 250, 250)),
+            Color::new(255, 255, 255)
+        );
+    }
+}
@@ -0,0 +1,648 @@
+use std::f64::consts::PI;
+use std::fmt;
+
+use visioncortex::{CompoundPath, NumberFormat, PathF64, PointF64};
+
+use super::centerline::distance_to_segment;
+use super::rasterizer::{distance, polylines};
+use super::svg::{opacity, SvgFile};
+
+/// A rectangle at least this many times as long as it is wide is drawn as a line
+const LINE_ASPECT: f64 = 8.0;
+/// Largest spacing in pixels of the points an outline is measured against a shape at
+const SAMPLE_SPACING: f64 = 1.0;
+
+/// A geometric shape standing in for the traced outline of a path
+#[derive(Debug, Clone, PartialEq)]
+pub enum Primitive {
+    Circle {
+        center: PointF64,
+        radius: f64,
+    },
+    /// An ellipse with radii `rx` and `ry` along its own axes, turned by `angle` radians
+    Ellipse {
+        center: PointF64,
+        rx: f64,
+        ry: f64,
+        angle: f64,
+    },
+    /// A rectangle centered on `center`, turned by `angle` radians
+    Rect {
+        center: PointF64,
+        width: f64,
+        height: f64,
+        angle: f64,
+    },
+    /// A straight line, drawn with the stroke width of its path and round caps
+    Line {
+        from: PointF64,
+        to: PointF64,
+    },
+}
+
+impl Primitive {
+    /// Enlarge the shape by `factor` about the origin
+    pub(crate) fn scale(&mut self, factor: f64) {
+        let scale = |p: &mut PointF64| {
+            p.x *= factor;
+            p.y *= factor;
+        };
+        match self {
+            Self::Circle { center, radius } => {
+                scale(center);
+                *radius *= factor;
+            }
+            Self::Ellipse { center, rx, ry, .. } => {
+                scale(center);
+                *rx *= factor;
+                *ry *= factor;
+            }
+            Self::Rect {
+                center,
+                width,
+                height,
+                ..
+            } => {
+                scale(center);
+                *width *= factor;
+                *height *= factor;
+            }
+            Self::Line { from, to } => {
+                scale(from);
+                scale(to);
+            }
+        }
+    }
+
+    /// Move the shape by `offset`
+    pub(crate) fn translate(&mut self, offset: PointF64) {
+        let translate = |p: &mut PointF64| *p = PointF64::new(p.x + offset.x, p.y + offset.y);
+        match self {
+            Self::Circle { center, .. }
+            | Self::Ellipse { center, .. }
+            | Self::Rect { center, .. } => translate(center),
+            Self::Line { from, to } => {
+                translate(from);
+                translate(to);
+            }
+        }
+    }
+
+    /// The outline of the shape as a polygon, or the line itself
+    pub(crate) fn to_path(&self) -> CompoundPath {
+        let points = match *self {
+            Self::Circle { center, radius } => ellipse_points(center, radius, radius, 0.0),
+            Self::Ellipse {
+                center,
+                rx,
+                ry,
+                angle,
+            } => ellipse_points(center, rx, ry, angle),
+            Self::Rect {
+                center,
+                width,
+                height,
+                angle,
+            } => [(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)]
+                .iter()
+                .map(|&(u, v)| rotate(center, u * width / 2.0, v * height / 2.0, angle))
+                .collect(),
+            Self::Line { from, to } => vec![from, to],
+        };
+        let mut path = CompoundPath::new();
+        path.add_path_f64(PathF64::from_points(points));
+        path
+    }
+
+    /// Write the shape as an SVG element painted in `color` with opacity `alpha`
+    pub(crate) fn fmt_with_precision(
+        &self,
+        f: &mut fmt::Formatter,
+        color: &str,
+        alpha: u8,
+        stroke_width: Option<f64>,
+        precision: Option<u32>,
+    ) -> fmt::Result {
+        let num = |x: f64| f64::number_format(x, precision);
+        let rotation = |angle: f64, center: PointF64| {
+            if angle == 0.0 {
+                String::new()
+            } else {
+                format!(
+                    " transform=\"rotate({} {} {})\"",
+                    num(angle.to_degrees()),
+                    num(center.x),
+                    num(center.y)
+                )
+            }
+        };
+        match *self {
+            Self::Circle { center, radius } => writeln!(
+                f,
+                "<circle cx=\"{}\" cy=\"{}\" r=\"{}\" fill=\"{}\"{}/>",
+                num(center.x),
+                num(center.y),
+                num(radius),
+                color,
+                opacity("fill", alpha)
+            ),
+            Self::Ellipse {
+                center,
+                rx,
+                ry,
+                angle,
+            } => writeln!(
+                f,
+                "<ellipse cx=\"{}\" cy=\"{}\" rx=\"{}\" ry=\"{}\" fill=\"{}\"{}{}/>",
+                num(center.x),
+                num(center.y),
+                num(rx),
+                num(ry),
+                color,
+                opacity("fill", alpha),
+                rotation(angle, center)
+            ),
+            Self::Rect {
+                center,
+                width,
+                height,
+                angle,
+            } => writeln!(
+                f,
+                "<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" fill=\"{}\"{}{}/>",
+                num(center.x - width / 2.0),
+                num(center.y - height / 2.0),
+                num(width),
+                num(height),
+                color,
+                opacity("fill", alpha),
+                rotation(angle, center)
+            ),
+            Self::Line { from, to } => writeln!(
+                f,
+                "<line x1=\"{}\" y1=\"{}\" x2=\"{}\" y2=\"{}\" stroke=\"{}\"{} stroke-width=\"{}\" stroke-linecap=\"round\"/>",
+                num(from.x),
+                num(from.y),
+                num(to.x),
+                num(to.y),
+                color,
+                opacity("stroke", alpha),
+                num(stroke_width.unwrap_or(1.0))
+            ),
+        }
+    }
+}
+
+/// Replace the outline of every path that a circle, ellipse, rectangle or straight line fits
+/// within `tolerance` pixels by that shape. Paths with holes are left as they are.
+pub(crate) fn detect_primitives(svg: &mut SvgFile, tolerance: f64) {
+    for path in svg.paths.iter_mut() {
+        let mut polylines = polylines(&path.path, 1.0);
+        if polylines.len() != 1 {
+            continue;
+        }
+        let points = polylines.pop().unwrap();
+        if path.stroke_width.is_some() {
+            path.primitive = fit_line(&points, tolerance);
+            continue;
+        }
+        match fit_outline(&points, tolerance) {
+            Some(Primitive::Rect {
+                center,
+                width,
+                height,
+                angle,
+            }) if width.max(height) >= LINE_ASPECT * width.min(height) => {
+                // round caps reach half the stroke width past the ends of the line
+                let (length, thickness, angle) = if width >= height {
+                    (width, height, angle)
+                } else {
+                    (height, width, angle + PI / 2.0)
+                };
+                let reach = (length - thickness) / 2.0;
+                path.primitive = Some(Primitive::Line {
+                    from: rotate(center, -reach, 0.0, angle),
+                    to: rotate(center, reach, 0.0, angle),
+                });
+                path.stroke_width = Some(thickness);
+            }
+            primitive => path.primitive = primitive,
+        }
+    }
+}
+
+/// The statistics of the region enclosed by a polygon
+struct Moments {
+    area: f64,
+    center: PointF64,
+    /// The covariance of the region, `[xx, xy, yy]`
+    covariance: [f64; 3],
+}
+
+impl Moments {
+    fn of(points: &[PointF64]) -> Option<Self> {
+        // relative to a point on the outline, for precision far from the origin
+        let origin = points[0];
+        let (mut area, mut cx, mut cy, mut xx, mut xy, mut yy) = (0.0, 0.0, 0.0, 0.0, 0.0, 0.0);
+        for (i, &p) in points.iter().enumerate() {
+            let q = points[(i + 1) % points.len()];
+            let (px, py, qx, qy) = (
+                p.x - origin.x,
+                p.y - origin.y,
+                q.x - origin.x,
+                q.y - origin.y,
+            );
+            let cross = px * qy - qx * py;
+            area += cross;
+            cx += (px + qx) * cross;
+            cy += (py + qy) * cross;
+            xx += (px * px + px * qx + qx * qx) * cross;
+            yy += (py * py + py * qy + qy * qy) * cross;
+            xy += (px * qy + 2.0 * px * py + 2.0 * qx * qy + qx * py) * cross;
+        }
+        area /= 2.0;
+        if area.abs() < f64::EPSILON {
+            return None;
+        }
+        let (cx, cy) = (cx / (6.0 * area), cy / (6.0 * area));
+        Some(Self {
+            area: area.abs(),
+            center: PointF64::new(cx + origin.x, cy + origin.y),
+            covariance: [
+                xx / (12.0 * area) - cx * cx,
+                xy / (24.0 * area) - cx * cy,
+                yy / (12.0 * area) - cy * cy,
+            ],
+        })
+    }
+
+    /// The variances along the principal axes, largest first, and the angle of the first axis
+    fn principal_axes(&self) -> (f64, f64, f64) {
+        let [xx, xy, yy] = self.covariance;
+        let mean = (xx + yy) / 2.0;
+        let spread = ((xx - yy) * (xx - yy) / 4.0 + xy * xy).sqrt();
+        (
+            mean + spread,
+            (mean - spread).max(0.0),
+            0.5 * (2.0 * xy).atan2(xx - yy),
+        )
+    }
+}
+
+/// The shape best fitting a closed outline, if any fits within `tolerance`
+fn fit_outline(points: &[PointF64], tolerance: f64) -> Option<Primitive> {
+    let mut points = points.to_vec();
+    if points.len() > 1 && points.first() == points.last() {
+        points.pop();
+    }
+    if points.len() < 3 {
+        return None;
+    }
+    let moments = Moments::of(&points)?;
+    let samples = densify(&points, true);
+
+    // the simpler shape of each kind, unless only the general one fits
+    let round = [Some(circle(&moments, &samples)), ellipse(&moments)]
+        .into_iter()
+        .flatten()
+        .find_map(|primitive| measure(primitive, &samples, &moments, tolerance));
+    let rect = [Some(axis_aligned_rect(&points)), minimum_rect(&points)]
+        .into_iter()
+        .flatten()
+        .find_map(|primitive| measure(primitive, &samples, &moments, tolerance));
+
+    match (round, rect) {
+        (Some(round), Some(rect)) if rect.1 < round.1 => Some(rect.0),
+        (Some(round), _) => Some(round.0),
+        (None, rect) => rect.map(|(primitive, _)| primitive),
+    }
+}
+
+/// `primitive` with the root mean square distance of `samples` from its outline, if no sample
+/// is further than `tolerance` from it and it encloses the same area as the outline
+fn measure(
+    primitive: Primitive,
+    samples: &[PointF64],
+    moments: &Moments,
+    tolerance: f64,
+) -> Option<(Primitive, f64)> {
+    let mut max: f64 = 0.0;
+    let mut sum = 0.0;
+    for &p in samples {
+        let error = distance_to_outline(&primitive, p);
+        max = max.max(error);
+        sum += error * error;
+    }
+    let (area, perimeter) = match primitive {
+        Primitive::Circle { radius, .. } => (PI * radius * radius, 2.0 * PI * radius),
+        Primitive::Ellipse { rx, ry, .. } => (PI * rx * ry, PI * (rx + ry)),
+        Primitive::Rect { width, height, .. } => (width * height, 2.0 * (width + height)),
+        Primitive::Line { .. } => unreachable!("lines have no area"),
+    };
+    if max > tolerance || (area - moments.area).abs() > tolerance * perimeter {
+        return None;
+    }
+    Some((primitive, (sum / samples.len() as f64).sqrt()))
+}
+
+fn circle(moments: &Moments, samples: &[PointF64]) -> Primitive {
+    let radius = samples
+        .iter()
+        .map(|&p| distance(p, moments.center))
+        .sum::<f64>()
+        / samples.len() as f64;
+    Primitive::Circle {
+        center: moments.center,
+        radius,
+    }
+}
+
+/// The ellipse with the same second moments as the outline
+fn ellipse(moments: &Moments) -> Option<Primitive> {
+    let (major, minor, angle) = moments.principal_axes();
+    if minor == 0.0 {
+        return None;
+    }
+    Some(Primitive::Ellipse {
+        center: moments.center,
+        rx: 2.0 * major.sqrt(),
+        ry: 2.0 * minor.sqrt(),
+        angle,
+    })
+}
+
+fn axis_aligned_rect(points: &[PointF64]) -> Primitive {
+    let (min, max) = bounds(points.iter().copied());
+    Primitive::Rect {
+        center: PointF64::new((min.x + max.x) / 2.0, (min.y + max.y) / 2.0),
+        width: max.x - min.x,
+        height: max.y - min.y,
+        angle: 0.0,
+    }
+}
+
+/// The smallest rectangle enclosing the outline, which has a side along an edge of its
+/// convex hull
+fn minimum_rect(points: &[PointF64]) -> Option<Primitive> {
+    let hull = convex_hull(points);
+    let mut best: Option<(f64, Primitive)> = None;
+    for (i, &a) in hull.iter().enumerate() {
+        let b = hull[(i + 1) % hull.len()];
+        let angle = (b.y - a.y).atan2(b.x - a.x);
+        let (min, max) = bounds(
+            hull.iter()
+                .map(|&p| rotate(PointF64::default(), p.x, p.y, -angle)),
+        );
+        let area = (max.x - min.x) * (max.y - min.y);
+        if best.as_ref().is_none_or(|(best, _)| area < *best) {
+            let center = rotate(
+                PointF64::default(),
+                (min.x + max.x) / 2.0,
+                (min.y + max.y) / 2.0,
+                angle,
+            );
+            best = Some((
+                area,
+                Primitive::Rect {
+                    center,
+                    width: max.x - min.x,
+                    height: max.y - min.y,
+                    angle,
+                },
+            ));
+        }
+    }
+    best.map(|(_, rect)| rect)
+}
+
+/// A straight line through an open stroke, if no point of it is further than `tolerance` away
+fn fit_line(points: &[PointF64], tolerance: f64) -> Option<Primitive> {
+    let (&from, &to) = (points.first()?, points.last()?);
+    if from == to {
+        return None;
+    }
+    let straight = densify(points, false)
+        .into_iter()
+        .all(|p| distance_to_segment(p, from, to) <= tolerance);
+    straight.then_some(Primitive::Line { from, to })
+}
+
+fn distance_to_outline(primitive: &Primitive, p: PointF64) -> f64 {
+    match *primitive {
+        Primitive::Circle { center, radius } => (distance(p, center) - radius).abs(),
+        Primitive::Ellipse {
+            center,
+            rx,
+            ry,
+            angle,
+        } => {
+            let q = rotate(PointF64::default(), p.x - center.x, p.y - center.y, -angle);
+            let r = (q.x * q.x + q.y * q.y).sqrt();
+            let scaled = ((q.x / rx).powi(2) + (q.y / ry).powi(2)).sqrt();
+            if scaled == 0.0 {
+                rx.min(ry)
+            } else {
+                // along the ray from the center, which is close to the distance for a fitting
+                // ellipse
+                (r - r / scaled).abs()
+            }
+        }
+        Primitive::Rect {
+            center,
+            width,
+            height,
+            angle,
+        } => {
+            let q = rotate(PointF64::default(), p.x - center.x, p.y - center.y, -angle);
+            let (dx, dy) = (q.x.abs() - width / 2.0, q.y.abs() - height / 2.0);
+            if dx <= 0.0 && dy <= 0.0 {
+                -dx.max(dy)
+            } else {
+                (dx.max(0.0).powi(2) + dy.max(0.0).powi(2)).sqrt()
+            }
+        }
+        Primitive::Line { from, to } => distance_to_segment(p, from, to),
+    }
+}
+
+/// The points of a polyline with more added along its long segments, so that the distance of
+/// the segments from a shape is measured too
+fn densify(points: &[PointF64], closed: bool) -> Vec<PointF64> {
+    let count = if closed {
+        points.len()
+    } else {
+        points.len() - 1
+    };
+    let mut samples = Vec::with_capacity(points.len());
+    for i in 0..count {
+        let (a, b) = (points[i], points[(i + 1) % points.len()]);
+        let steps = (distance(a, b) / SAMPLE_SPACING).ceil().max(1.0) as usize;
+        for step in 0..steps {
+            let t = step as f64 / steps as f64;
+            samples.push(PointF64::new(a.x + (b.x - a.x) * t, a.y + (b.y - a.y) * t));
+        }
+    }
+    if !closed {
+        samples.push(points[points.len() - 1]);
+    }
+    samples
+}
+
+/// The convex hull by Andrew's monotone chain, counterclockwise
+fn convex_hull(points: &[PointF64]) -> Vec<PointF64> {
+    let mut sorted = points.to_vec();
+    sorted.sort_by(|a, b| a.x.total_cmp(&b.x).then(a.y.total_cmp(&b.y)));
+    sorted.dedup();
+    if sorted.len() < 3 {
+        return sorted;
+    }
+    let half = |points: &mut dyn Iterator<Item = &PointF64>| {
+        let mut chain: Vec<PointF64> = vec![];
+        for &p in points {
+            while let [.., o, a] = chain[..] {
+                if (a.x - o.x) * (p.y - o.y) - (a.y - o.y) * (p.x - o.x) > 0.0 {
+                    break;
+                }
+                chain.pop();
+            }
+            chain.push(p);
+        }
+        // the last point starts the other half
+        chain.pop();
+        chain
+    };
+    let mut hull = half(&mut sorted.iter());
+    hull.extend(half(&mut sorted.iter().rev()));
+    hull
+}
+
+fn bounds(points: impl Iterator<Item = PointF64>) -> (PointF64, PointF64) {
+    points.fold(
+        (
+            PointF64::new(f64::MAX, f64::MAX),
+            PointF64::new(f64::MIN, f64::MIN),
+        ),
+        |(min, max), p| {
+            (
+                PointF64::new(min.x.min(p.x), min.y.min(p.y)),
+                PointF64::new(max.x.max(p.x), max.y.max(p.y)),
+            )
+        },
+    )
+}
+
+/// The point at (`u`, `v`) in a frame centered on `center` and turned by `angle`
+fn rotate(center: PointF64, u: f64, v: f64, angle: f64) -> PointF64 {
+    let (sin, cos) = angle.sin_cos();
+    PointF64::new(center.x + u * cos - v * sin, center.y + u * sin + v * cos)
+}
+
+fn ellipse_points(center: PointF64, rx: f64, ry: f64, angle: f64) -> Vec<PointF64> {
+    // about one side per pixel of circumference
+    let sides = (PI * (rx + ry)).ceil().clamp(16.0, 1024.0) as usize;
+    (0..sides)
+        .map(|i| {
+            let t = 2.0 * PI * i as f64 / sides as f64;
+            rotate(center, rx * t.cos(), ry * t.sin(), angle)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use visioncortex::{Color, ColorImage};
+
+    use super::*;
+    use crate::{convert, ColorMode, Config};
+
+    /// Trace a black shape on white, the pixels `inside` says, into its paths
+    fn trace(width: usize, height: usize, inside: impl Fn(f64, f64) -> bool) -> SvgFile {
+        let mut image = ColorImage::new_w_h(width, height);
+        for y in 0..height {
+            for x in 0..width {
+                let color = if inside(x as f64 + 0.5, y as f64 + 0.5) {
+                    Color::new(0, 0, 0)
+                } else {
+                    Color::new(255, 255, 255)
+                };
+                image.set_pixel(x, y, &color);
+            }
+        }
+        let config = Config {
+            color_mode: ColorMode::Binary,
+            ..Config::default()
+        };
+        convert(image, config).unwrap()
+    }
+
+    fn primitives(mut svg: SvgFile) -> Vec<Option<Primitive>> {
+        detect_primitives(&mut svg, 1.0);
+        svg.paths.into_iter().map(|path| path.primitive).collect()
+    }
+
+    #[test]
+    fn circle() {
+        let svg = trace(64, 64, |x, y| {
+            (x - 30.0) * (x - 30.0) + (y - 34.0) * (y - 34.0) <= 20.0 * 20.0
+        });
+        match primitives(svg)[..] {
+            [Some(Primitive::Circle { center, radius })] => {
+                assert!(
+                    distance(center, PointF64::new(30.0, 34.0)) < 0.5,
+                    "{:?}",
+                    center
+                );
+                assert!((radius - 20.0).abs() < 0.5, "{}", radius);
+            }
+            ref other => panic!("expected a circle, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn rect() {
+        let svg = trace(64, 48, |x, y| {
+            (10.0..50.0).contains(&x) && (8.0..30.0).contains(&y)
+        });
+        match primitives(svg)[..] {
+            [Some(Primitive::Rect {
+                center,
+                width,
+                height,
+                angle,
+            })] => {
+                assert!(
+                    distance(center, PointF64::new(30.0, 19.0)) < 0.5,
+                    "{:?}",
+                    center
+                );
+                assert!((width - 40.0).abs() < 1.0, "{}", width);
+                assert!((height - 22.0).abs() < 1.0, "{}", height);
+                assert!(angle.abs() < 0.01, "{}", angle);
+            }
+            ref other => panic!("expected a rect, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn thin_rect_is_a_line() {
+        let svg = trace(64, 16, |x, y| {
+            (4.0..60.0).contains(&x) && (6.0..9.0).contains(&y)
+        });
+        let primitives = primitives(svg);
+        assert!(
+            matches!(primitives[..], [Some(Primitive::Line { .. })]),
+            "{:?}",
+            primitives
+        );
+    }
+
+    #[test]
+    fn irregular_outline_is_kept() {
+        // an L shape
+        let svg = trace(40, 40, |x, y| {
+            (5.0..35.0).contains(&x) && (5.0..15.0).contains(&y)
+                || (5.0..15.0).contains(&x) && (5.0..35.0).contains(&y)
+        });
+        assert_eq!(primitives(svg), [None]);
+    }
+}
@@ -1,9 +1,12 @@
 use crate::*;
 use image::{io::Reader, ImageFormat};
-use pyo3::{exceptions::PyException, prelude::*};
+use pyo3::{
+    exceptions::{PyException, PyValueError},
+    prelude::*,
+};
 use std::io::{BufReader, Cursor};
 use std::path::PathBuf;
-use visioncortex::PathSimplifyMode;
+use std::str::FromStr;
 
 /// Python binding
 #[pyfunction]
@@ -21,11 +24,16 @@ fn convert_image_to_svg_py(
     max_iterations: Option<usize>, // default: 10
     splice_threshold: Option<i32>, // default: 45
     path_precision: Option<u32>,   // default: 8
+    preset: Option<&str>,          // "bw", "poster", "photo" or "auto"
+    pixel_art: Option<bool>,       // default: False
 ) -> PyResult<()> {
     let input_path = PathBuf::from(image_path);
     let output_path = PathBuf::from(out_path);
 
+    let img = read_image(&input_path).map_err(PyException::new_err)?;
     let config = construct_config(
+        &img,
+        preset,
         colormode,
         hierarchical,
         mode,
@@ -37,9 +45,11 @@ fn convert_image_to_svg_py(
         max_iterations,
         splice_threshold,
         path_precision,
-    );
+        pixel_art,
+    )?;
 
-    convert_image_to_svg(&input_path, &output_path, config).unwrap();
+    let svg = convert(img, config).map_err(PyException::new_err)?;
+    write_svg(svg, &output_path).map_err(PyException::new_err)?;
     Ok(())
 }
 
@@ -58,20 +68,9 @@ fn convert_raw_image_to_svg(
     max_iterations: Option<usize>, // default: 10
     splice_threshold: Option<i32>, // default: 45
     path_precision: Option<u32>, // default: 8
+    preset: Option<&str>,     // "bw", "poster", "photo" or "auto"
+    pixel_art: Option<bool>,  // default: False
 ) -> PyResult<String> {
-    let config = construct_config(
-        colormode,
-        hierarchical,
-        mode,
-        filter_speckle,
-        color_precision,
-        layer_difference,
-        corner_threshold,
-        length_threshold,
-        max_iterations,
-        splice_threshold,
-        path_precision,
-    );
     let mut img_reader = Reader::new(BufReader::new(Cursor::new(img_bytes)));
     let img_format = img_format.and_then(|ext_name| ImageFormat::from_extension(ext_name));
     let img = match img_format {
@@ -94,6 +93,22 @@ fn convert_raw_image_to_svg(
         width,
         height,
     };
+    let config = construct_config(
+        &img,
+        preset,
+        colormode,
+        hierarchical,
+        mode,
+        filter_speckle,
+        color_precision,
+        layer_difference,
+        corner_threshold,
+        length_threshold,
+        max_iterations,
+        splice_threshold,
+        path_precision,
+        pixel_art,
+    )?;
     let svg =
         convert(img, config).map_err(|_| PyException::new_err("Failed to convert the image. "))?;
     Ok(format!("{}", svg))
@@ -114,6 +129,8 @@ fn convert_pixels_to_svg(
     max_iterations: Option<usize>, // default: 10
     splice_threshold: Option<i32>, // default: 45
     path_precision: Option<u32>,   // default: 8
+    preset: Option<&str>,          // "bw", "poster", "photo" or "auto"
+    pixel_art: Option<bool>,       // default: False
 ) -> PyResult<String> {
     let expected_pixel_count = size.0 * size.1;
     if rgba_pixels.len() != expected_pixel_count {
@@ -125,7 +142,20 @@ fn convert_pixels_to_svg(
             rgba_pixels.len()
         )));
     }
+    let mut flat_pixels: Vec<u8> = vec![];
+    for (r, g, b, a) in rgba_pixels {
+        flat_pixels.push(r);
+        flat_pixels.push(g);
+        flat_pixels.push(b);
+        flat_pixels.push(a);
+    }
+    let mut img = ColorImage::new();
+    img.pixels = flat_pixels;
+    (img.width, img.height) = size;
+
     let config = construct_config(
+        &img,
+        preset,
         colormode,
         hierarchical,
         mode,
@@ -137,17 +167,8 @@ fn convert_pixels_to_svg(
         max_iterations,
         splice_threshold,
         path_precision,
-    );
-    let mut flat_pixels: Vec<u8> = vec![];
-    for (r, g, b, a) in rgba_pixels {
-        flat_pixels.push(r);
-        flat_pixels.push(g);
-        flat_pixels.push(b);
-        flat_pixels.push(a);
-    }
-    let mut img = ColorImage::new();
-    img.pixels = flat_pixels;
-    (img.width, img.height) = size;
+        pixel_art,
+    )?;
 
     let svg =
         convert(img, config).map_err(|_| PyException::new_err("Failed to convert the image. "))?;
@@ -155,6 +176,8 @@ fn convert_pixels_to_svg(
 }
 
 fn construct_config(
+    img: &ColorImage,
+    preset: Option<&str>,
     colormode: Option<&str>,
     hierarchical: Option<&str>,
     mode: Option<&str>,
@@ -166,50 +189,44 @@ fn construct_config(
     max_iterations: Option<usize>,
     splice_threshold: Option<i32>,
     path_precision: Option<u32>,
-) -> Config {
-    // TODO: enforce color mode with an enum so that we only
-    // accept the strings 'color' or 'binary'
-    let color_mode = match colormode.unwrap_or("color") {
-        "color" => ColorMode::Color,
-        "binary" => ColorMode::Binary,
-        _ => ColorMode::Color,
-    };
-
-    let hierarchical = match hierarchical.unwrap_or("stacked") {
-        "stacked" => Hierarchical::Stacked,
-        "cutout" => Hierarchical::Cutout,
-        _ => Hierarchical::Stacked,
-    };
-
-    let mode = match mode.unwrap_or("spline") {
-        "spline" => PathSimplifyMode::Spline,
-        "polygon" => PathSimplifyMode::Polygon,
-        "none" => PathSimplifyMode::None,
-        _ => PathSimplifyMode::Spline,
+    pixel_art: Option<bool>,
+) -> PyResult<Config> {
+    let mut config = match preset {
+        None => Config {
+            path_precision: None,
+            ..Default::default()
+        },
+        Some("auto") => Config::auto(img),
+        Some(preset) => {
+            Config::from_preset(Preset::from_str(preset).map_err(PyValueError::new_err)?)
+        }
     };
 
-    let filter_speckle = filter_speckle.unwrap_or(4);
-    let color_precision = color_precision.unwrap_or(6);
-    let layer_difference = layer_difference.unwrap_or(16);
-    let corner_threshold = corner_threshold.unwrap_or(60);
-    let length_threshold = length_threshold.unwrap_or(4.0);
-    let splice_threshold = splice_threshold.unwrap_or(45);
-    let max_iterations = max_iterations.unwrap_or(10);
-
-    Config {
-        color_mode,
-        hierarchical,
-        filter_speckle,
-        color_precision,
-        layer_difference,
-        mode,
-        corner_threshold,
-        length_threshold,
-        max_iterations,
-        splice_threshold,
-        path_precision,
-        ..Default::default()
+    if let Some(colormode) = colormode {
+        config.color_mode = ColorMode::from_str(colormode).map_err(PyValueError::new_err)?;
+    }
+    if let Some(hierarchical) = hierarchical {
+        config.hierarchical =
+            Hierarchical::from_str(hierarchical).map_err(PyValueError::new_err)?;
     }
+    if let Some(mode) = mode {
+        config.mode = path_simplify_mode_from_str(mode).map_err(PyValueError::new_err)?;
+    }
+    config.filter_speckle = filter_speckle.unwrap_or(config.filter_speckle);
+    config.color_precision = color_precision.unwrap_or(config.color_precision);
+    config.layer_difference = layer_difference.unwrap_or(config.layer_difference);
+    config.corner_threshold = corner_threshold.unwrap_or(config.corner_threshold);
+    config.length_threshold = length_threshold.unwrap_or(config.length_threshold);
+    config.max_iterations = max_iterations.unwrap_or(config.max_iterations);
+    config.splice_threshold = splice_threshold.unwrap_or(config.splice_threshold);
+    config.path_precision = path_precision.or(config.path_precision);
+    config.pixel_art = pixel_art.unwrap_or(config.pixel_art);
+
+    config.validate().map_err(|errors| {
+        let errors: Vec<String> = errors.iter().map(|error| error.to_string()).collect();
+        PyValueError::new_err(errors.join("; "))
+    })?;
+    Ok(config)
 }
 
 /// A Python module implemented in Rust.
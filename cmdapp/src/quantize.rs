@@ -0,0 +1,401 @@
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::ops::Range;
+use std::str::FromStr;
+
+use visioncortex::{Color, ColorImage};
+
+use super::color_space::{distance_squared, ColorSpace};
+
+/// Upper bound on the number of refinement passes of k-means
+const KMEANS_ITERATIONS: usize = 10;
+/// K-means stops once no center moves further than this
+const KMEANS_TOLERANCE: f64 = 0.5;
+
+/// How [`quantize`] chooses the colors
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum QuantizeMethod {
+    /// Repeatedly split the box of colors with the widest extent at its median
+    #[cfg_attr(feature = "serde", serde(rename = "median_cut"))]
+    MedianCut,
+    /// Refine the median cut colors by k-means, which lowers the average error but is slower
+    #[cfg_attr(feature = "serde", serde(rename = "kmeans"))]
+    KMeans,
+    /// Merge the least used branches of an octree of the colors, favouring frequent colors
+    #[cfg_attr(feature = "serde", serde(rename = "octree"))]
+    Octree,
+}
+
+impl FromStr for QuantizeMethod {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "median_cut" => Ok(Self::MedianCut),
+            "kmeans" => Ok(Self::KMeans),
+            "octree" => Ok(Self::Octree),
+            _ => Err(format!("unknown QuantizeMethod {}", s)),
+        }
+    }
+}
+
+/// A distinct color of the image, with the number of pixels of that color
+#[derive(Clone)]
+struct Entry {
+    color: Color,
+    count: usize,
+    point: [f64; 3],
+}
+
+/// Choose `num_colors` colors that represent the visible pixels of `img`, averaging in `space`.
+/// Returns every distinct color if the image has no more than `num_colors` of them.
+pub fn quantize(
+    img: &ColorImage,
+    num_colors: usize,
+    method: QuantizeMethod,
+    space: ColorSpace,
+) -> Vec<Color> {
+    let entries = histogram(img, space);
+    if entries.len() <= num_colors {
+        return entries.iter().map(|entry| entry.color).collect();
+    }
+
+    let centers = match method {
+        QuantizeMethod::MedianCut => median_cut(&entries, num_colors),
+        QuantizeMethod::KMeans => kmeans(&entries, median_cut(&entries, num_colors)),
+        QuantizeMethod::Octree => octree(&entries, num_colors),
+    };
+    let mut colors: Vec<Color> = vec![];
+    for center in centers {
+        let color = space.to_color(center);
+        if !colors.contains(&color) {
+            colors.push(color);
+        }
+    }
+    colors
+}
+
+/// Replace every visible pixel of `img` by the color of `colors` nearest to it in `space`
+pub(crate) fn remap(img: &mut ColorImage, colors: &[Color], space: ColorSpace) {
+    let points: Vec<[f64; 3]> = colors.iter().map(|color| space.to_point(color)).collect();
+    let mut nearest: HashMap<[u8; 3], Color> = HashMap::new();
+    for pixel in img.pixels.chunks_exact_mut(4) {
+        if pixel[3] == 0 {
+            continue;
+        }
+        let rgb = [pixel[0], pixel[1], pixel[2]];
+        let color = *nearest.entry(rgb).or_insert_with(|| {
+            let point = space.to_point(&Color::new(rgb[0], rgb[1], rgb[2]));
+            colors[nearest_index(&point, &points)]
+        });
+        pixel[..3].copy_from_slice(&[color.r, color.g, color.b]);
+    }
+}
+
+fn histogram(img: &ColorImage, space: ColorSpace) -> Vec<Entry> {
+    // sorting groups equal colors together, which is faster than hashing every pixel
+    let mut colors: Vec<u32> = img
+        .pixels
+        .chunks_exact(4)
+        .filter(|p| p[3] > 0)
+        .map(|p| u32::from_be_bytes([0, p[0], p[1], p[2]]))
+        .collect();
+    colors.sort_unstable();
+    colors
+        .chunk_by(|a, b| a == b)
+        .map(|run| {
+            let [_, r, g, b] = run[0].to_be_bytes();
+            let color = Color::new(r, g, b);
+            Entry {
+                color,
+                count: run.len(),
+                point: space.to_point(&color),
+            }
+        })
+        .collect()
+}
+
+fn nearest_index(point: &[f64; 3], centers: &[[f64; 3]]) -> usize {
+    centers
+        .iter()
+        .enumerate()
+        .min_by(|(_, a), (_, b)| distance_squared(point, a).total_cmp(&distance_squared(point, b)))
+        .map_or(0, |(index, _)| index)
+}
+
+/// The mean of `entries` weighted by their pixel counts
+fn mean(entries: &[Entry]) -> [f64; 3] {
+    let mut sum = [0.0; 3];
+    let mut count = 0;
+    for entry in entries {
+        for (sum, c) in sum.iter_mut().zip(entry.point) {
+            *sum += c * entry.count as f64;
+        }
+        count += entry.count;
+    }
+    sum.map(|sum| sum / count.max(1) as f64)
+}
+
+/// The axis along which `entries` spread the most, and their extent along it
+fn widest_axis(entries: &[Entry]) -> (usize, f64) {
+    (0..3)
+        .map(|axis| {
+            let (min, max) = entries
+                .iter()
+                .fold((f64::MAX, f64::MIN), |(min, max), entry| {
+                    (min.min(entry.point[axis]), max.max(entry.point[axis]))
+                });
+            (axis, max - min)
+        })
+        .max_by(|a, b| a.1.total_cmp(&b.1))
+        .unwrap()
+}
+
+fn median_cut(entries: &[Entry], num_colors: usize) -> Vec<[f64; 3]> {
+    let mut entries = entries.to_vec();
+    let (axis, extent) = widest_axis(&entries);
+    let mut boxes: Vec<(Range<usize>, usize, f64)> = vec![(0..entries.len(), axis, extent)];
+
+    while boxes.len() < num_colors {
+        // a box of a single color cannot be split, and there are more colors than boxes
+        let Some(index) = (0..boxes.len())
+            .filter(|&i| boxes[i].0.len() > 1)
+            .max_by(|&a, &b| boxes[a].2.total_cmp(&boxes[b].2))
+        else {
+            break;
+        };
+        let (range, axis, _) = boxes.swap_remove(index);
+        let colors = &mut entries[range.clone()];
+        colors.sort_by(|a, b| a.point[axis].total_cmp(&b.point[axis]));
+
+        // split at the weighted median, leaving at least one color on either side
+        let total: usize = colors.iter().map(|entry| entry.count).sum();
+        let mut count = 0;
+        let split = colors
+            .iter()
+            .position(|entry| {
+                count += entry.count;
+                2 * count >= total
+            })
+            .map_or(1, |i| i + 1)
+            .clamp(1, colors.len() - 1);
+
+        for half in [
+            range.start..range.start + split,
+            range.start + split..range.end,
+        ] {
+            let (axis, extent) = widest_axis(&entries[half.clone()]);
+            boxes.push((half, axis, extent));
+        }
+    }
+
+    boxes
+        .iter()
+        .map(|(range, _, _)| mean(&entries[range.clone()]))
+        .collect()
+}
+
+fn kmeans(entries: &[Entry], mut centers: Vec<[f64; 3]>) -> Vec<[f64; 3]> {
+    for _ in 0..KMEANS_ITERATIONS {
+        let mut sums = vec![([0.0; 3], 0); centers.len()];
+        for entry in entries {
+            let (sum, count) = &mut sums[nearest_index(&entry.point, &centers)];
+            for (sum, c) in sum.iter_mut().zip(entry.point) {
+                *sum += c * entry.count as f64;
+            }
+            *count += entry.count;
+        }
+
+        let mut moved = false;
+        for (center, (sum, count)) in centers.iter_mut().zip(sums) {
+            // a center that attracted no colors stays where it is
+            if count == 0 {
+                continue;
+            }
+            let next = sum.map(|sum| sum / count as f64);
+            moved |= distance_squared(center, &next) > KMEANS_TOLERANCE * KMEANS_TOLERANCE;
+            *center = next;
+        }
+        if !moved {
+            break;
+        }
+    }
+    centers
+}
+
+#[derive(Default)]
+struct OctreeNode {
+    children: [Option<usize>; 8],
+    count: usize,
+    sum: [f64; 3],
+}
+
+fn octree(entries: &[Entry], num_colors: usize) -> Vec<[f64; 3]> {
+    let mut nodes = vec![OctreeNode::default()];
+    // the inner nodes at every depth, the leaves being at depth 8
+    let mut levels: Vec<Vec<usize>> = vec![vec![]; 8];
+    levels[0].push(0);
+
+    for entry in entries {
+        let Color { r, g, b, .. } = entry.color;
+        let mut node = 0;
+        nodes[node].count += entry.count;
+        for depth in 0..8 {
+            let shift = 7 - depth;
+            let octant = ((((r >> shift) & 1) << 2)
+                | (((g >> shift) & 1) << 1)
+                | ((b >> shift) & 1)) as usize;
+            node = match nodes[node].children[octant] {
+                Some(child) => child,
+                None => {
+                    let child = nodes.len();
+                    nodes.push(OctreeNode::default());
+                    nodes[node].children[octant] = Some(child);
+                    if depth < 7 {
+                        levels[depth + 1].push(child);
+                    }
+                    child
+                }
+            };
+            nodes[node].count += entry.count;
+        }
+        for (sum, c) in nodes[node].sum.iter_mut().zip(entry.point) {
+            *sum += c * entry.count as f64;
+        }
+    }
+
+    // every distinct color is a leaf of its own; fold the least used nodes of the deepest level
+    // into their parents until the target is reached
+    let mut leaves = entries.len();
+    'reduce: for depth in (0..8).rev() {
+        let mut level = std::mem::take(&mut levels[depth]);
+        level.sort_by_key(|&node| nodes[node].count);
+        for node in level {
+            if leaves <= num_colors {
+                break 'reduce;
+            }
+            let mut children: Vec<usize> = nodes[node].children.iter().flatten().copied().collect();
+            children.sort_by_key(|&child| nodes[child].count);
+            // merging k leaves removes k - 1 of them, so merge only as many as needed to land on
+            // the target exactly
+            let merged = children.len().min(leaves - num_colors + 1);
+            let mut sum = [0.0; 3];
+            for &child in &children[..merged] {
+                for (sum, c) in sum.iter_mut().zip(nodes[child].sum) {
+                    *sum += c;
+                }
+            }
+            if merged == children.len() {
+                nodes[node].children = [None; 8];
+                nodes[node].sum = sum;
+            } else {
+                let into = children[0];
+                nodes[into].sum = sum;
+                nodes[into].count = children[..merged].iter().map(|&c| nodes[c].count).sum();
+                for slot in nodes[node].children.iter_mut() {
+                    if slot.is_some_and(|child| children[1..merged].contains(&child)) {
+                        *slot = None;
+                    }
+                }
+            }
+            leaves -= merged - 1;
+        }
+    }
+
+    let mut centers = vec![];
+    let mut stack = vec![0];
+    while let Some(node) = stack.pop() {
+        let node = &nodes[node];
+        if node.children.iter().all(Option::is_none) {
+            centers.push(node.sum.map(|sum| sum / node.count.max(1) as f64));
+        } else {
+            stack.extend(node.children.iter().flatten());
+        }
+    }
+    centers
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const METHODS: [QuantizeMethod; 3] = [
+        QuantizeMethod::MedianCut,
+        QuantizeMethod::KMeans,
+        QuantizeMethod::Octree,
+    ];
+
+    /// A 32 by 32 image of smoothly varying colors, every pixel distinct
+    fn gradient() -> ColorImage {
+        let mut image = ColorImage::new_w_h(32, 32);
+        for y in 0..32 {
+            for x in 0..32 {
+                let color = Color::new((x * 8) as u8, (y * 8) as u8, ((x + y) * 4) as u8);
+                image.set_pixel(x, y, &color);
+            }
+        }
+        image
+    }
+
+    #[test]
+    fn at_most_num_colors() {
+        let image = gradient();
+        for method in METHODS {
+            for space in [ColorSpace::Rgb, ColorSpace::Lab] {
+                for num_colors in [1, 2, 5, 16, 64] {
+                    let colors = quantize(&image, num_colors, method, space);
+                    assert!(
+                        !colors.is_empty() && colors.len() <= num_colors,
+                        "{:?} in {:?} chose {} of {} colors",
+                        method,
+                        space,
+                        colors.len(),
+                        num_colors
+                    );
+                }
+            }
+        }
+
+        let entries = histogram(&image, ColorSpace::Rgb);
+        assert!(median_cut(&entries, 7).len() <= 7);
+        assert!(kmeans(&entries, median_cut(&entries, 7)).len() <= 7);
+        assert!(octree(&entries, 7).len() <= 7);
+    }
+
+    #[test]
+    fn few_colors_are_kept() {
+        let palette = [
+            Color::new(255, 0, 0),
+            Color::new(0, 255, 0),
+            Color::new(0, 0, 255),
+        ];
+        let mut image = ColorImage::new_w_h(6, 4);
+        for y in 0..4 {
+            for x in 0..6 {
+                image.set_pixel(x, y, &palette[(x + y) % 3]);
+            }
+        }
+        // transparent pixels are not counted
+        image.set_pixel(0, 0, &Color::new_rgba(255, 255, 255, 0));
+
+        for method in METHODS {
+            let mut colors = quantize(&image, 8, method, ColorSpace::Rgb);
+            colors.sort_by_key(|color| (color.r, color.g, color.b));
+            assert_eq!(colors, [palette[2], palette[1], palette[0]], "{:?}", method);
+        }
+    }
+
+    #[test]
+    fn kmeans_is_deterministic() {
+        let image = gradient();
+        let first = quantize(&image, 12, QuantizeMethod::KMeans, ColorSpace::Lab);
+        for _ in 0..3 {
+            assert_eq!(
+                quantize(&image, 12, QuantizeMethod::KMeans, ColorSpace::Lab),
+                first
+            );
+        }
+    }
+}
@@ -0,0 +1,432 @@
+use visioncortex::{Color, ColorImage, CompoundPath, CompoundPathElement, PointF64};
+
+use super::svg::SvgFile;
+
+/// Number of sub-scanlines sampled per pixel row; horizontal coverage is computed exactly
+const SUBSAMPLES: usize = 5;
+/// Maximum length in output pixels of a line segment approximating a curve
+const CURVE_TOLERANCE: f64 = 0.5;
+/// Bounds on the number of sides of the polygons standing in for the round joins of strokes
+const ROUND_SIDES: (usize, usize) = (8, 64);
+
+/// Render `svg` into an image `scale` times the size of its canvas.
+/// Paths are filled with the nonzero rule and painted in order over a transparent background;
+/// stroked paths are drawn with round caps and joins. Embedded pictures are painted last.
+pub fn rasterize(svg: &SvgFile, scale: f64) -> ColorImage {
+    let width = (svg.width as f64 * scale).round() as usize;
+    let height = (svg.height as f64 * scale).round() as usize;
+    let mut image = ColorImage::new_w_h(width, height);
+
+    for path in svg.paths.iter() {
+        let shape = path.outline();
+        let edges = match path.stroke_width {
+            None => flatten(&shape, scale),
+            Some(width) => stroke(&shape, width * scale, scale),
+        };
+        let paint = |x: usize, y: usize| match &path.gradient {
+            Some(gradient) => Color {
+                a: path.color.a,
+                ..gradient.color_at(PointF64::new(
+                    (x as f64 + 0.5) / scale,
+                    (y as f64 + 0.5) / scale,
+                ))
+            },
+            None => path.color,
+        };
+        fill_edges(&mut image, edges, paint);
+    }
+    for embedded in svg.images.iter() {
+        let picture = match embedded.decode() {
+            Some(picture) => picture,
+            None => continue,
+        };
+        // nearest neighbour sampling of the picture stretched over its place
+        let paint = |x: usize, y: usize| {
+            let u = ((x as f64 + 0.5) / scale - embedded.position.x) / embedded.width;
+            let v = ((y as f64 + 0.5) / scale - embedded.position.y) / embedded.height;
+            picture.get_pixel(
+                ((u * picture.width as f64) as usize).min(picture.width - 1),
+                ((v * picture.height as f64) as usize).min(picture.height - 1),
+            )
+        };
+        fill_edges(&mut image, flatten(&embedded.clip, scale), paint);
+    }
+
+    image
+}
+
+/// A line segment of a path outline, oriented downwards, remembering its original direction
+struct Edge {
+    x0: f64,
+    y0: f64,
+    x1: f64,
+    y1: f64,
+    winding: i32,
+}
+
+impl Edge {
+    fn new(a: PointF64, b: PointF64) -> Option<Self> {
+        if a.y == b.y {
+            // horizontal edges never cross a scanline
+            return None;
+        }
+        let (top, bottom, winding) = if a.y < b.y { (a, b, 1) } else { (b, a, -1) };
+        Some(Self {
+            x0: top.x,
+            y0: top.y,
+            x1: bottom.x,
+            y1: bottom.y,
+            winding,
+        })
+    }
+
+    fn x_at(&self, y: f64) -> f64 {
+        self.x0 + (self.x1 - self.x0) * (y - self.y0) / (self.y1 - self.y0)
+    }
+}
+
+/// Approximate every outline of `path` by line segments, in output pixel coordinates
+fn flatten(path: &CompoundPath, scale: f64) -> Vec<Edge> {
+    let mut edges = vec![];
+    for points in polylines(path, scale) {
+        // outlines are closed, whether or not the last point repeats the first
+        for (i, &a) in points.iter().enumerate() {
+            let b = points[(i + 1) % points.len()];
+            edges.extend(Edge::new(a, b));
+        }
+    }
+    edges
+}
+
+/// The outline of the area covered by a round pen `width` output pixels wide moving along
+/// `path`, as the union of a rectangle per line segment and a disc per vertex. All the pieces
+/// wind the same way, so the nonzero rule fills their union.
+fn stroke(path: &CompoundPath, width: f64, scale: f64) -> Vec<Edge> {
+    let radius = width / 2.0;
+    let sides =
+        ((std::f64::consts::PI * width).ceil() as usize).clamp(ROUND_SIDES.0, ROUND_SIDES.1);
+    let disc: Vec<PointF64> = (0..sides)
+        .map(|i| {
+            let angle = 2.0 * std::f64::consts::PI * i as f64 / sides as f64;
+            PointF64::new(radius * angle.cos(), radius * angle.sin())
+        })
+        .collect();
+
+    let mut edges = vec![];
+    let mut add_polygon = |polygon: &[PointF64]| {
+        for (i, &a) in polygon.iter().enumerate() {
+            edges.extend(Edge::new(a, polygon[(i + 1) % polygon.len()]));
+        }
+    };
+    for points in polylines(path, scale) {
+        for &p in points.iter() {
+            let circle: Vec<PointF64> = disc
+                .iter()
+                .map(|d| PointF64::new(p.x + d.x, p.y + d.y))
+                .collect();
+            add_polygon(&circle);
+        }
+        for segment in points.windows(2) {
+            let (a, b) = (segment[0], segment[1]);
+            let length = distance(a, b);
+            if length == 0.0 {
+                continue;
+            }
+            // the normal on the same side as the disc's winding, y pointing down
+            let (nx, ny) = (
+                -(b.y - a.y) / length * radius,
+                (b.x - a.x) / length * radius,
+            );
+            add_polygon(&[
+                PointF64::new(a.x - nx, a.y - ny),
+                PointF64::new(b.x - nx, b.y - ny),
+                PointF64::new(b.x + nx, b.y + ny),
+                PointF64::new(a.x + nx, a.y + ny),
+            ]);
+        }
+    }
+    edges
+}
+
+/// The points of every element of `path`, curves approximated by line segments, in output
+/// pixel coordinates
+pub(crate) fn polylines(path: &CompoundPath, scale: f64) -> Vec<Vec<PointF64>> {
+    let mut polylines = vec![];
+    for element in path.iter() {
+        let points: Vec<PointF64> = match element {
+            CompoundPathElement::PathI32(path) => path
+                .iter()
+                .map(|p| PointF64::new(p.x as f64, p.y as f64))
+                .collect(),
+            CompoundPathElement::PathF64(path) => path.iter().copied().collect(),
+            CompoundPathElement::Spline(spline) => flatten_spline(&spline.points, scale),
+        };
+        polylines.push(
+            points
+                .into_iter()
+                .map(|p| PointF64::new(p.x * scale, p.y * scale))
+                .collect(),
+        );
+    }
+    polylines
+}
+
+/// Subdivide each cubic Bezier curve of a spline into line segments short enough for `scale`
+fn flatten_spline(points: &[PointF64], scale: f64) -> Vec<PointF64> {
+    let mut flat = vec![];
+    if let Some(&first) = points.first() {
+        flat.push(first);
+    }
+    for curve in points.windows(4).step_by(3) {
+        let (p0, p1, p2, p3) = (curve[0], curve[1], curve[2], curve[3]);
+        let hull_length = distance(p0, p1) + distance(p1, p2) + distance(p2, p3);
+        let steps = ((hull_length * scale / CURVE_TOLERANCE).sqrt().ceil() as usize).clamp(1, 256);
+        for step in 1..=steps {
+            let t = step as f64 / steps as f64;
+            let u = 1.0 - t;
+            let (a, b, c, d) = (u * u * u, 3.0 * u * u * t, 3.0 * u * t * t, t * t * t);
+            flat.push(PointF64::new(
+                a * p0.x + b * p1.x + c * p2.x + d * p3.x,
+                a * p0.y + b * p1.y + c * p2.y + d * p3.y,
+            ));
+        }
+    }
+    flat
+}
+
+pub(crate) fn distance(a: PointF64, b: PointF64) -> f64 {
+    ((a.x - b.x) * (a.x - b.x) + (a.y - b.y) * (a.y - b.y)).sqrt()
+}
+
+/// Scan convert the outline given by `edges` and composite it onto `image`, in the color `paint`
+/// gives for each pixel
+fn fill_edges(image: &mut ColorImage, mut edges: Vec<Edge>, paint: impl Fn(usize, usize) -> Color) {
+    if edges.is_empty() || image.width == 0 || image.height == 0 {
+        return;
+    }
+    edges.sort_by(|a, b| a.y0.total_cmp(&b.y0));
+
+    let top = edges[0].y0.floor().max(0.0) as usize;
+    let bottom = edges
+        .iter()
+        .map(|edge| edge.y1)
+        .fold(f64::MIN, f64::max)
+        .ceil()
+        .min(image.height as f64) as usize;
+
+    let mut coverage = vec![0.0f64; image.width + 1];
+    let mut crossings: Vec<(f64, i32)> = vec![];
+    let mut active: Vec<usize> = vec![];
+    let mut next_edge = 0;
+    let weight = 1.0 / SUBSAMPLES as f64;
+
+    for row in top..bottom {
+        let (mut min_x, mut max_x) = (image.width, 0);
+        for sub in 0..SUBSAMPLES {
+            let y = row as f64 + (sub as f64 + 0.5) * weight;
+
+            while next_edge < edges.len() && edges[next_edge].y0 <= y {
+                active.push(next_edge);
+                next_edge += 1;
+            }
+            active.retain(|&i| edges[i].y1 > y);
+
+            crossings.clear();
+            crossings.extend(active.iter().map(|&i| (edges[i].x_at(y), edges[i].winding)));
+            crossings.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+            let mut winding = 0;
+            for pair in crossings.windows(2) {
+                winding += pair[0].1;
+                if winding != 0 {
+                    let x0 = pair[0].0.clamp(0.0, image.width as f64);
+                    let x1 = pair[1].0.clamp(0.0, image.width as f64);
+                    if x1 > x0 {
+                        accumulate_span(&mut coverage, x0, x1, weight);
+                        min_x = min_x.min(x0 as usize);
+                        max_x = max_x.max(x1.ceil() as usize);
+                    }
+                }
+            }
+        }
+
+        let end = max_x.min(image.width);
+        for (x, cell) in coverage.iter_mut().enumerate().take(end).skip(min_x) {
+            let alpha = cell.min(1.0);
+            *cell = 0.0;
+            if alpha > 0.0 {
+                blend_pixel(image, x, row, &paint(x, row), alpha);
+            }
+        }
+        coverage[image.width] = 0.0;
+    }
+}
+
+/// Add the horizontal coverage of the span `[x0, x1)` to the pixels it overlaps
+fn accumulate_span(coverage: &mut [f64], x0: f64, x1: f64, weight: f64) {
+    let first = x0.floor() as usize;
+    let last = x1.floor() as usize;
+    if first == last {
+        coverage[first] += (x1 - x0) * weight;
+        return;
+    }
+    coverage[first] += (first as f64 + 1.0 - x0) * weight;
+    for cell in coverage.iter_mut().take(last).skip(first + 1) {
+        *cell += weight;
+    }
+    coverage[last] += (x1 - last as f64) * weight;
+}
+
+/// Composite `color`, its opacity scaled by the coverage `alpha`, over the pixel at (x, y)
+fn blend_pixel(image: &mut ColorImage, x: usize, y: usize, color: &Color, alpha: f64) {
+    let alpha = alpha * color.a as f64 / 255.0;
+    if alpha <= 0.0 {
+        return;
+    }
+    let dst = image.get_pixel(x, y);
+    let dst_alpha = dst.a as f64 / 255.0;
+    let out_alpha = alpha + dst_alpha * (1.0 - alpha);
+    let mix = |src: u8, dst: u8| {
+        ((src as f64 * alpha + dst as f64 * dst_alpha * (1.0 - alpha)) / out_alpha).round() as u8
+    };
+    image.set_pixel(
+        x,
+        y,
+        &Color::new_rgba(
+            mix(color.r, dst.r),
+            mix(color.g, dst.g),
+            mix(color.b, dst.b),
+            (out_alpha * 255.0).round() as u8,
+        ),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use visioncortex::{PathI32, PointI32, Spline};
+
+    use super::*;
+
+    fn square(x0: i32, y0: i32, x1: i32, y1: i32) -> PathI32 {
+        PathI32::from_points(vec![
+            PointI32::new(x0, y0),
+            PointI32::new(x1, y0),
+            PointI32::new(x1, y1),
+            PointI32::new(x0, y1),
+            PointI32::new(x0, y0),
+        ])
+    }
+
+    fn svg_with(path: CompoundPath, width: usize, height: usize) -> SvgFile {
+        let mut svg = SvgFile::new(width, height, None);
+        svg.add_path(path, Color::new(255, 0, 0));
+        svg
+    }
+
+    fn covered(image: &ColorImage) -> f64 {
+        (0..image.height)
+            .flat_map(|y| (0..image.width).map(move |x| (x, y)))
+            .map(|(x, y)| image.get_pixel(x, y).a as f64 / 255.0)
+            .sum()
+    }
+
+    #[test]
+    fn square_covers_exactly_its_pixels() {
+        let mut path = CompoundPath::new();
+        path.add_path_i32(square(2, 3, 6, 5));
+        let image = rasterize(&svg_with(path, 8, 8), 1.0);
+
+        for y in 0..8 {
+            for x in 0..8 {
+                let inside = (2..6).contains(&x) && (3..5).contains(&y);
+                let pixel = image.get_pixel(x, y);
+                assert_eq!(pixel.a, if inside { 255 } else { 0 }, "at ({}, {})", x, y);
+                if inside {
+                    assert_eq!((pixel.r, pixel.g, pixel.b), (255, 0, 0));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn hole_stays_empty() {
+        let mut path = CompoundPath::new();
+        path.add_path_i32(square(0, 0, 10, 10));
+        // wound the other way, so the nonzero rule leaves it out
+        let mut hole = square(3, 3, 7, 7);
+        hole.path.reverse();
+        path.add_path_i32(hole);
+        let image = rasterize(&svg_with(path, 10, 10), 1.0);
+
+        for y in 3..7 {
+            for x in 3..7 {
+                assert_eq!(image.get_pixel(x, y).a, 0, "at ({}, {})", x, y);
+            }
+        }
+        assert_eq!(image.get_pixel(1, 1).a, 255);
+        assert_eq!(covered(&image), 100.0 - 16.0);
+    }
+
+    #[test]
+    fn flattened_circle_keeps_its_area() {
+        // four cubic curves approximating a circle of radius 20 around (25, 25)
+        let (c, r) = (25.0, 20.0);
+        let k = 0.5523 * r;
+        let mut spline = Spline::new(PointF64::new(c + r, c));
+        spline.add(
+            PointF64::new(c + r, c + k),
+            PointF64::new(c + k, c + r),
+            PointF64::new(c, c + r),
+        );
+        spline.add(
+            PointF64::new(c - k, c + r),
+            PointF64::new(c - r, c + k),
+            PointF64::new(c - r, c),
+        );
+        spline.add(
+            PointF64::new(c - r, c - k),
+            PointF64::new(c - k, c - r),
+            PointF64::new(c, c - r),
+        );
+        spline.add(
+            PointF64::new(c + k, c - r),
+            PointF64::new(c + r, c - k),
+            PointF64::new(c + r, c),
+        );
+        let mut path = CompoundPath::new();
+        path.add_spline(spline);
+
+        let points = &polylines(&path, 1.0)[0];
+        let area = points
+            .iter()
+            .zip(points.iter().cycle().skip(1))
+            .map(|(a, b)| a.x * b.y - b.x * a.y)
+            .sum::<f64>()
+            .abs()
+            / 2.0;
+        let expected = std::f64::consts::PI * r * r;
+        assert!((area - expected).abs() / expected < 0.01, "area {}", area);
+
+        let image = rasterize(&svg_with(path, 50, 50), 1.0);
+        assert!((covered(&image) - expected).abs() / expected < 0.01);
+    }
+
+    #[test]
+    fn scale_enlarges_the_canvas_and_the_shapes() {
+        let mut path = CompoundPath::new();
+        path.add_path_i32(square(1, 1, 4, 3));
+        let svg = svg_with(path, 5, 4);
+
+        let image = rasterize(&svg, 2.0);
+        assert_eq!((image.width, image.height), (10, 8));
+        assert_eq!(covered(&image), 4.0 * 6.0);
+        assert_eq!(image.get_pixel(2, 2).a, 255);
+        assert_eq!(image.get_pixel(1, 1).a, 0);
+        assert_eq!(image.get_pixel(7, 5).a, 255);
+        assert_eq!(image.get_pixel(8, 6).a, 0);
+
+        let image = rasterize(&svg, 0.5);
+        assert_eq!((image.width, image.height), (3, 2));
+        assert!((covered(&image) - 6.0 / 4.0).abs() < 0.05);
+    }
+}
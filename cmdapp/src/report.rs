@@ -0,0 +1,60 @@
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::Mutex;
+
+use crate::error::CliError;
+use serde::Serialize;
+use vtracer::ConversionStats;
+
+/// Writes one JSON line of `ConversionStats` per converted image
+pub struct Reporter {
+    out: Mutex<Box<dyn Write + Send>>,
+}
+
+#[derive(Serialize)]
+struct Report<'a> {
+    input: &'a Path,
+    output: &'a Path,
+    #[serde(flatten)]
+    stats: &'a ConversionStats,
+}
+
+impl Reporter {
+    /// Report in `format` to the file at `path`, or to stderr if `path` is `None` or `-`
+    pub fn new(format: &str, path: Option<&str>) -> Result<Self, CliError> {
+        if format != "json" {
+            return Err(CliError::InvalidArguments(format!(
+                "unknown report format {}",
+                format
+            )));
+        }
+        let out: Box<dyn Write + Send> = match path {
+            None | Some("-") => Box::new(io::stderr()),
+            Some(path) => Box::new(File::create(path).map_err(|err| {
+                CliError::UnwritableOutput(format!("Cannot create report file {}: {}", path, err))
+            })?),
+        };
+        Ok(Self {
+            out: Mutex::new(out),
+        })
+    }
+
+    pub fn write(
+        &self,
+        input: &Path,
+        output: &Path,
+        stats: &ConversionStats,
+    ) -> Result<(), CliError> {
+        let report = Report {
+            input,
+            output,
+            stats,
+        };
+        let mut out = self.out.lock().unwrap();
+        serde_json::to_writer(&mut *out, &report)
+            .map_err(io::Error::from)
+            .and_then(|()| writeln!(out))
+            .map_err(|err| CliError::UnwritableOutput(format!("Cannot write report: {}", err)))
+    }
+}
@@ -0,0 +1,165 @@
+use std::collections::HashSet;
+use std::fmt::{self, Write};
+use std::time::Duration;
+
+#[cfg(feature = "serde")]
+use serde::{Serialize, Serializer};
+use visioncortex::CompoundPathElement;
+
+use super::arc::Segment;
+use super::svg::SvgFile;
+
+/// Measurements of a single conversion, returned by [`convert_with_stats`](crate::convert_with_stats)
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct ConversionStats {
+    /// Number of clusters formed by the clustering stage, before merging
+    pub clusters_found: usize,
+    /// Number of paths in the output
+    pub paths_emitted: usize,
+    /// Number of clusters discarded for being smaller than `filter_speckle`.
+    /// `None` in color mode, which merges speckles into a neighbouring cluster instead.
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub paths_dropped: Option<usize>,
+    /// Number of paths written as a circle, ellipse, rectangle or line
+    pub primitives: usize,
+    /// Number of paths filled with a gradient
+    pub gradients: usize,
+    /// Number of pictures embedded in place of paths
+    pub images: usize,
+    /// Number of points over all paths, not counting primitives
+    pub nodes: usize,
+    /// Number of straight or curved segments over all paths
+    pub segments: usize,
+    /// Number of distinct fill colors
+    pub distinct_colors: usize,
+    pub stage_times: StageTimes,
+    /// Size of the serialized SVG in bytes
+    pub output_bytes: usize,
+}
+
+/// Wall time spent in each stage of the conversion.
+/// Always zero on `wasm32`, where there is no clock.
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct StageTimes {
+    #[cfg_attr(
+        feature = "serde",
+        serde(rename = "keying_ms", serialize_with = "as_millis")
+    )]
+    pub keying: Duration,
+    #[cfg_attr(
+        feature = "serde",
+        serde(rename = "clustering_ms", serialize_with = "as_millis")
+    )]
+    pub clustering: Duration,
+    #[cfg_attr(
+        feature = "serde",
+        serde(rename = "reclustering_ms", serialize_with = "as_millis")
+    )]
+    pub reclustering: Duration,
+    #[cfg_attr(
+        feature = "serde",
+        serde(rename = "vectorizing_ms", serialize_with = "as_millis")
+    )]
+    pub vectorizing: Duration,
+}
+
+#[cfg(feature = "serde")]
+fn as_millis<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_f64(duration.as_secs_f64() * 1000.0)
+}
+
+impl ConversionStats {
+    /// Fill in the statistics that can be read off the finished SVG
+    pub(crate) fn record_output(&mut self, svg: &SvgFile) {
+        self.paths_emitted = svg.paths.len();
+        self.images = svg.images.len();
+        self.primitives = 0;
+        self.gradients = 0;
+        self.nodes = 0;
+        self.segments = 0;
+        for path in svg.paths.iter() {
+            if path.gradient.is_some() {
+                self.gradients += 1;
+            }
+            if path.primitive.is_some() {
+                self.primitives += 1;
+                continue;
+            }
+            if let Some(arcs) = &path.arcs {
+                for arcs in arcs.iter() {
+                    // the start, the end of every segment and the two controls of every curve
+                    self.nodes += 1;
+                    self.segments += arcs.segments.len();
+                    for segment in arcs.segments.iter() {
+                        self.nodes += match segment {
+                            Segment::Cubic { .. } => 3,
+                            _ => 1,
+                        };
+                    }
+                }
+                continue;
+            }
+            for element in path.path.iter() {
+                let (nodes, segments) = match element {
+                    CompoundPathElement::PathI32(path) => {
+                        (path.len(), path.len().saturating_sub(1))
+                    }
+                    CompoundPathElement::PathF64(path) => {
+                        (path.len(), path.len().saturating_sub(1))
+                    }
+                    CompoundPathElement::Spline(spline) => (spline.len(), spline.num_curves()),
+                };
+                self.nodes += nodes;
+                self.segments += segments;
+            }
+        }
+
+        self.distinct_colors = svg
+            .paths
+            .iter()
+            .map(|path| {
+                let color = path.color;
+                (color.r, color.g, color.b, color.a)
+            })
+            .collect::<HashSet<_>>()
+            .len();
+
+        let mut counter = ByteCounter(0);
+        write!(counter, "{}", svg).expect("counting bytes cannot fail");
+        self.output_bytes = counter.0;
+    }
+}
+
+/// Measures formatted output without allocating it
+struct ByteCounter(usize);
+
+impl Write for ByteCounter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.0 += s.len();
+        Ok(())
+    }
+}
+
+/// Measures the duration of a stage; a no-op on `wasm32`, where `Instant` is unavailable
+pub(crate) struct Stopwatch {
+    #[cfg(not(target_arch = "wasm32"))]
+    start: std::time::Instant,
+}
+
+impl Stopwatch {
+    pub(crate) fn start() -> Self {
+        Self {
+            #[cfg(not(target_arch = "wasm32"))]
+            start: std::time::Instant::now(),
+        }
+    }
+
+    pub(crate) fn elapsed(&self) -> Duration {
+        #[cfg(not(target_arch = "wasm32"))]
+        return self.start.elapsed();
+        #[cfg(target_arch = "wasm32")]
+        return Duration::default();
+    }
+}
@@ -1,5 +1,12 @@
+use std::borrow::Cow;
 use std::fmt;
-use visioncortex::{Color, CompoundPath, PointF64};
+
+use super::arc::ArcPath;
+use super::gradient::Gradient;
+use super::hybrid::EmbeddedImage;
+use super::primitive::Primitive;
+use super::rasterizer::polylines;
+use visioncortex::{Color, CompoundPath, CompoundPathElement, NumberFormat, PointF64, PointI32};
 
 #[derive(Debug, Clone)]
 pub struct SvgFile {
@@ -7,12 +14,22 @@ pub struct SvgFile {
     pub width: usize,
     pub height: usize,
     pub path_precision: Option<u32>,
+    /// Pictures drawn over the paths
+    pub images: Vec<EmbeddedImage>,
 }
 
 #[derive(Debug, Clone)]
 pub struct SvgPath {
     pub path: CompoundPath,
     pub color: Color,
+    /// The width of the pen tracing an open path, or `None` for a filled outline
+    pub stroke_width: Option<f64>,
+    /// A circle, ellipse, rectangle or line written in place of `path`, which it fits
+    pub primitive: Option<Primitive>,
+    /// The subpaths of `path` with lines and circular arcs fitted, written in place of it
+    pub arcs: Option<Vec<ArcPath>>,
+    /// A gradient filling the path in place of `color`
+    pub gradient: Option<Gradient>,
 }
 
 impl SvgFile {
@@ -22,11 +39,195 @@ impl SvgFile {
             width,
             height,
             path_precision,
+            images: vec![],
         }
     }
 
     pub fn add_path(&mut self, path: CompoundPath, color: Color) {
-        self.paths.push(SvgPath { path, color })
+        self.paths.push(SvgPath {
+            path,
+            color,
+            stroke_width: None,
+            primitive: None,
+            arcs: None,
+            gradient: None,
+        })
+    }
+
+    /// Add an open path drawn with a round pen `width` pixels wide
+    pub fn add_stroke(&mut self, path: CompoundPath, color: Color, width: f64) {
+        self.paths.push(SvgPath {
+            path,
+            color,
+            stroke_width: Some(width),
+            primitive: None,
+            arcs: None,
+            gradient: None,
+        })
+    }
+
+    /// Combine runs of consecutive paths of the same color (and stroke width) into one path each.
+    /// Every path fills its shape with a winding number of one, so under the nonzero fill rule
+    /// the combined path paints the union of the shapes, as they did one over the other.
+    pub fn merge_same_color_paths(&mut self) {
+        let mut merged: Vec<SvgPath> = Vec::with_capacity(self.paths.len());
+        for path in self.paths.drain(..) {
+            match merged.last_mut() {
+                Some(last)
+                    if last.color == path.color
+                        && last.stroke_width == path.stroke_width
+                        && last.primitive.is_none()
+                        && path.primitive.is_none()
+                        && last.gradient.is_none()
+                        && path.gradient.is_none()
+                        && last.arcs.is_some() == path.arcs.is_some() =>
+                {
+                    last.path.append(path.path);
+                    if let (Some(arcs), Some(more)) = (last.arcs.as_mut(), path.arcs) {
+                        arcs.extend(more);
+                    }
+                }
+                _ => merged.push(path),
+            }
+        }
+        self.paths = merged;
+    }
+
+    /// Enlarge the canvas and every path by an integer `factor`
+    pub fn scale(&mut self, factor: usize) {
+        self.width *= factor;
+        self.height *= factor;
+        for image in self.images.iter_mut() {
+            image.scale(factor);
+        }
+        for path in self.paths.iter_mut() {
+            if let Some(width) = path.stroke_width.as_mut() {
+                *width *= factor as f64;
+            }
+            if let Some(primitive) = path.primitive.as_mut() {
+                primitive.scale(factor as f64);
+            }
+            for arcs in path.arcs.iter_mut().flatten() {
+                arcs.scale(factor as f64);
+            }
+            if let Some(gradient) = path.gradient.as_mut() {
+                gradient.scale(factor as f64);
+            }
+            for element in path.path.iter_mut() {
+                match element {
+                    CompoundPathElement::PathI32(path) => {
+                        let factor = factor as i32;
+                        for point in path.path.iter_mut() {
+                            point.x *= factor;
+                            point.y *= factor;
+                        }
+                    }
+                    CompoundPathElement::PathF64(path) => {
+                        for point in path.path.iter_mut() {
+                            point.x *= factor as f64;
+                            point.y *= factor as f64;
+                        }
+                    }
+                    CompoundPathElement::Spline(spline) => {
+                        for point in spline.points.iter_mut() {
+                            point.x *= factor as f64;
+                            point.y *= factor as f64;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Move every path and picture by `offset`, leaving the canvas as it is
+    pub fn translate(&mut self, offset: PointI32) {
+        let offset_f64 = PointF64::new(offset.x as f64, offset.y as f64);
+        for image in self.images.iter_mut() {
+            image.translate(offset);
+        }
+        for path in self.paths.iter_mut() {
+            if let Some(primitive) = path.primitive.as_mut() {
+                primitive.translate(offset_f64);
+            }
+            for arcs in path.arcs.iter_mut().flatten() {
+                arcs.translate(offset_f64);
+            }
+            if let Some(gradient) = path.gradient.as_mut() {
+                gradient.translate(offset_f64);
+            }
+            translate_path(&mut path.path, offset);
+        }
+    }
+
+    /// The smallest box holding everything drawn, as its top left and bottom right corners, or
+    /// `None` if nothing is
+    pub fn bounds(&self) -> Option<(PointF64, PointF64)> {
+        let mut bounds: Option<(PointF64, PointF64)> = None;
+        let mut extend = |p: PointF64, margin: f64| {
+            let (min, max) = bounds.get_or_insert((p, p));
+            min.x = min.x.min(p.x - margin);
+            min.y = min.y.min(p.y - margin);
+            max.x = max.x.max(p.x + margin);
+            max.y = max.y.max(p.y + margin);
+        };
+        for path in self.paths.iter() {
+            // a stroke reaches half its width past the path
+            let margin = path.stroke_width.map_or(0.0, |width| width / 2.0);
+            for p in polylines(&path.outline(), 1.0).into_iter().flatten() {
+                extend(p, margin);
+            }
+        }
+        for image in self.images.iter() {
+            for p in polylines(&image.clip, 1.0).into_iter().flatten() {
+                extend(p, 0.0);
+            }
+        }
+        bounds
+    }
+
+    /// Crop the canvas to the whole pixels of it holding everything drawn, with `padding` pixels
+    /// around, and move the paths to match. A canvas with nothing drawn is left as it is.
+    pub fn trim(&mut self, padding: usize) {
+        let (min, max) = match self.bounds() {
+            Some(bounds) => bounds,
+            None => return,
+        };
+        // curves may stray past the canvas, where they were never shown
+        let (width, height) = (self.width as f64, self.height as f64);
+        let padding = padding as i32;
+        let left = min.x.clamp(0.0, width).floor() as i32 - padding;
+        let top = min.y.clamp(0.0, height).floor() as i32 - padding;
+        let right = max.x.clamp(0.0, width).ceil() as i32 + padding;
+        let bottom = max.y.clamp(0.0, height).ceil() as i32 + padding;
+        self.translate(PointI32::new(-left, -top));
+        self.width = (right - left) as usize;
+        self.height = (bottom - top) as usize;
+    }
+}
+
+/// Move every point of `path` by `offset`
+pub(crate) fn translate_path(path: &mut CompoundPath, offset: PointI32) {
+    for element in path.iter_mut() {
+        match element {
+            CompoundPathElement::PathI32(path) => {
+                for point in path.path.iter_mut() {
+                    point.x += offset.x;
+                    point.y += offset.y;
+                }
+            }
+            CompoundPathElement::PathF64(path) => {
+                for point in path.path.iter_mut() {
+                    point.x += offset.x as f64;
+                    point.y += offset.y as f64;
+                }
+            }
+            CompoundPathElement::Spline(spline) => {
+                for point in spline.points.iter_mut() {
+                    point.x += offset.x as f64;
+                    point.y += offset.y as f64;
+                }
+            }
+        }
     }
 }
 
@@ -40,12 +241,52 @@ impl fmt::Display for SvgFile {
         )?;
         writeln!(
             f,
-            r#"<svg version="1.1" xmlns="http://www.w3.org/2000/svg" width="{}" height="{}">"#,
-            self.width, self.height
+            r#"<svg version="1.1" xmlns="http://www.w3.org/2000/svg"{} width="{}" height="{}">"#,
+            if self.images.is_empty() {
+                ""
+            } else {
+                r#" xmlns:xlink="http://www.w3.org/1999/xlink""#
+            },
+            self.width,
+            self.height
         )?;
 
+        let gradients: Vec<&SvgPath> = self
+            .paths
+            .iter()
+            .filter(|path| path.gradient.is_some())
+            .collect();
+        if !gradients.is_empty() || !self.images.is_empty() {
+            writeln!(f, "<defs>")?;
+            for (id, path) in gradients.iter().enumerate() {
+                if let Some(gradient) = &path.gradient {
+                    gradient.fmt_with_precision(
+                        f,
+                        &format!("gradient{}", id),
+                        &path.gradient_transform(self.path_precision),
+                        self.path_precision,
+                    )?;
+                }
+            }
+            for (id, image) in self.images.iter().enumerate() {
+                image.fmt_clip(f, &format!("clip{}", id), self.path_precision)?;
+            }
+            writeln!(f, "</defs>")?;
+        }
+
+        let mut gradient_id = 0;
         for path in &self.paths {
-            path.fmt_with_precision(f, self.path_precision)?;
+            let paint = match path.gradient {
+                Some(_) => {
+                    gradient_id += 1;
+                    format!("url(#gradient{})", gradient_id - 1)
+                }
+                None => path.color.to_hex_string(),
+            };
+            path.fmt_with_precision(f, &paint, self.path_precision)?;
+        }
+        for (id, image) in self.images.iter().enumerate() {
+            image.fmt_image(f, &format!("clip{}", id))?;
         }
 
         writeln!(f, "</svg>")
@@ -54,22 +295,260 @@ impl fmt::Display for SvgFile {
 
 impl fmt::Display for SvgPath {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        self.fmt_with_precision(f, None)
+        self.fmt_with_precision(f, &self.color.to_hex_string(), None)
     }
 }
 
 impl SvgPath {
-    fn fmt_with_precision(&self, f: &mut fmt::Formatter, precision: Option<u32>) -> fmt::Result {
-        let (string, offset) = self
-            .path
-            .to_svg_string(true, PointF64::default(), precision);
-        writeln!(
-            f,
-            "<path d=\"{}\" fill=\"{}\" transform=\"translate({},{})\"/>",
-            string,
-            self.color.to_hex_string(),
-            offset.x,
-            offset.y
+    /// The outline drawn for the path: that of its primitive or arcs where written in its place
+    pub(crate) fn outline(&self) -> Cow<'_, CompoundPath> {
+        if let Some(primitive) = &self.primitive {
+            return Cow::Owned(primitive.to_path());
+        }
+        match &self.arcs {
+            Some(arcs) => {
+                let mut splines = CompoundPath::new();
+                for arcs in arcs.iter() {
+                    splines.add_spline(arcs.to_spline());
+                }
+                Cow::Owned(splines)
+            }
+            None => Cow::Borrowed(&self.path),
+        }
+    }
+
+    /// Write the path painted with `paint`, a color or a reference to a gradient
+    fn fmt_with_precision(
+        &self,
+        f: &mut fmt::Formatter,
+        paint: &str,
+        precision: Option<u32>,
+    ) -> fmt::Result {
+        if let Some(primitive) = &self.primitive {
+            return primitive.fmt_with_precision(
+                f,
+                paint,
+                self.color.a,
+                self.stroke_width,
+                precision,
+            );
+        }
+        let close = self.stroke_width.is_none();
+        let (string, offset) = match &self.arcs {
+            Some(arcs) => {
+                let origin = self.origin();
+                let string = arcs
+                    .iter()
+                    .map(|arcs| arcs.to_svg_string(close, origin, precision))
+                    .collect::<String>();
+                (string, origin)
+            }
+            None => self
+                .path
+                .to_svg_string(close, PointF64::default(), precision),
+        };
+        match self.stroke_width {
+            None => writeln!(
+                f,
+                "<path d=\"{}\" fill=\"{}\"{} transform=\"translate({},{})\"/>",
+                string,
+                paint,
+                opacity("fill", self.color.a),
+                offset.x,
+                offset.y
+            ),
+            Some(width) => writeln!(
+                f,
+                "<path d=\"{}\" fill=\"none\" stroke=\"{}\"{} stroke-width=\"{}\" stroke-linecap=\"round\" stroke-linejoin=\"round\" transform=\"translate({},{})\"/>",
+                string,
+                paint,
+                opacity("stroke", self.color.a),
+                f64::number_format(width, precision),
+                offset.x,
+                offset.y
+            ),
+        }
+    }
+
+    /// The point the path data is written relative to, which it is translated back by
+    fn origin(&self) -> PointF64 {
+        if let Some(arcs) = &self.arcs {
+            return arcs.first().map_or(PointF64::default(), |arcs| arcs.start);
+        }
+        match self.path.iter().next() {
+            Some(CompoundPathElement::PathI32(path)) => {
+                PointF64::new(path.path[0].x as f64, path.path[0].y as f64)
+            }
+            Some(CompoundPathElement::PathF64(path)) => path.path[0],
+            Some(CompoundPathElement::Spline(spline)) => spline.points[0],
+            None => PointF64::default(),
+        }
+    }
+
+    /// The inverse of the transform the path is written with, so that a gradient filling it is
+    /// placed in the coordinates of the canvas
+    fn gradient_transform(&self, precision: Option<u32>) -> String {
+        let num = |x: f64| f64::number_format(x, precision);
+        match self.primitive {
+            Some(Primitive::Ellipse { center, angle, .. })
+            | Some(Primitive::Rect { center, angle, .. })
+                if angle != 0.0 =>
+            {
+                format!(
+                    "rotate({} {} {})",
+                    num(-angle.to_degrees()),
+                    num(center.x),
+                    num(center.y)
+                )
+            }
+            Some(_) => String::new(),
+            None => {
+                let origin = self.origin();
+                format!("translate({},{})", 0.0 - origin.x, 0.0 - origin.y)
+            }
+        }
+    }
+}
+
+/// The `fill-opacity` or `stroke-opacity` attribute for `alpha`, if the paint is not opaque
+pub(crate) fn opacity(paint: &str, alpha: u8) -> String {
+    if alpha == 255 {
+        String::new()
+    } else {
+        format!(
+            " {}-opacity=\"{}\"",
+            paint,
+            f64::number_format(alpha as f64 / 255.0, Some(3))
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use visioncortex::PathI32;
+
+    use super::*;
+    use crate::arc::Segment;
+    use crate::gradient::GradientShape;
+    use crate::hybrid::RasterFormat;
+
+    fn square(x0: i32, y0: i32, x1: i32, y1: i32) -> CompoundPath {
+        let mut path = CompoundPath::new();
+        path.add_path_i32(PathI32::from_points(vec![
+            PointI32::new(x0, y0),
+            PointI32::new(x1, y0),
+            PointI32::new(x1, y1),
+            PointI32::new(x0, y1),
+            PointI32::new(x0, y0),
+        ]));
+        path
+    }
+
+    fn first_point(path: &CompoundPath) -> PointF64 {
+        polylines(path, 1.0)[0][0]
+    }
+
+    /// A canvas holding a plain path with a gradient, a primitive, arcs and an embedded picture,
+    /// spanning (20, 15) to (80, 70) together
+    fn drawing() -> SvgFile {
+        let mut svg = SvgFile::new(100, 80, None);
+        let black = Color::new(0, 0, 0);
+
+        svg.add_path(square(20, 30, 40, 50), black);
+        svg.paths[0].gradient = Some(Gradient {
+            shape: GradientShape::Linear {
+                from: PointF64::new(20.0, 30.0),
+                to: PointF64::new(40.0, 50.0),
+            },
+            stops: vec![(0.0, black), (1.0, Color::new(255, 255, 255))],
+        });
+
+        svg.add_path(square(55, 15, 65, 25), black);
+        svg.paths[1].primitive = Some(Primitive::Circle {
+            center: PointF64::new(60.0, 20.0),
+            radius: 5.0,
+        });
+
+        svg.add_path(square(25, 55, 35, 60), black);
+        svg.paths[2].arcs = Some(vec![ArcPath {
+            start: PointF64::new(25.0, 60.0),
+            segments: vec![Segment::Line {
+                to: PointF64::new(35.0, 60.0),
+            }],
+        }]);
+
+        svg.images.push(EmbeddedImage {
+            clip: square(70, 60, 80, 70),
+            position: PointF64::new(70.0, 60.0),
+            width: 10.0,
+            height: 10.0,
+            format: RasterFormat::Png,
+            data: vec![],
+        });
+        svg
+    }
+
+    #[test]
+    fn bounds_cover_everything_drawn() {
+        let (min, max) = drawing().bounds().unwrap();
+        assert!(
+            (min.x - 20.0).abs() < 1e-9 && (min.y - 15.0).abs() < 1e-9,
+            "{:?}",
+            min
+        );
+        assert!(
+            (max.x - 80.0).abs() < 1e-9 && (max.y - 70.0).abs() < 1e-9,
+            "{:?}",
+            max
+        );
+        assert_eq!(SvgFile::new(10, 10, None).bounds(), None);
+    }
+
+    #[test]
+    fn trim_moves_everything_by_the_same_offset() {
+        let mut svg = drawing();
+        svg.trim(2);
+        // the content is 60 by 55, with 2 pixels of padding on every side
+        assert_eq!((svg.width, svg.height), (64, 59));
+        let moved = |x: f64, y: f64| PointF64::new(x - 18.0, y - 13.0);
+
+        assert_eq!(first_point(&svg.paths[0].path), moved(20.0, 30.0));
+        match svg.paths[0].gradient.as_ref().unwrap().shape {
+            GradientShape::Linear { from, to } => {
+                assert_eq!((from, to), (moved(20.0, 30.0), moved(40.0, 50.0)))
+            }
+            ref other => panic!("expected a linear gradient, got {:?}", other),
+        }
+        assert_eq!(
+            svg.paths[1].primitive,
+            Some(Primitive::Circle {
+                center: moved(60.0, 20.0),
+                radius: 5.0
+            })
+        );
+        let arcs = &svg.paths[2].arcs.as_ref().unwrap()[0];
+        assert_eq!(arcs.start, moved(25.0, 60.0));
+        assert_eq!(
+            arcs.segments,
+            [Segment::Line {
+                to: moved(35.0, 60.0)
+            }]
+        );
+        assert_eq!(first_point(&svg.paths[2].path), moved(25.0, 55.0));
+        assert_eq!(svg.images[0].position, moved(70.0, 60.0));
+        assert_eq!(first_point(&svg.images[0].clip), moved(70.0, 60.0));
+    }
+
+    #[test]
+    fn trim_stays_within_the_canvas() {
+        let mut svg = SvgFile::new(20, 20, None);
+        svg.add_path(square(-5, 4, 10, 12), Color::new(0, 0, 0));
+        svg.trim(0);
+        assert_eq!((svg.width, svg.height), (10, 8));
+        assert_eq!(first_point(&svg.paths[0].path), PointF64::new(-5.0, 0.0));
+
+        let mut empty = SvgFile::new(20, 20, None);
+        empty.trim(4);
+        assert_eq!((empty.width, empty.height), (20, 20));
+    }
+}
@@ -0,0 +1,462 @@
+use std::fmt::Write as _;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+use crate::error::CliError;
+use crate::ConfigLayers;
+use clap::{App, Arg, ArgMatches, SubCommand};
+use vtracer::{Config, ConversionStats};
+
+/// Largest number of variations a sweep converts without `--force`
+pub const MAX_VARIATIONS: usize = 256;
+
+pub struct SweepArgs {
+    pub input_path: PathBuf,
+    pub output_dir: PathBuf,
+    pub axes: Vec<Axis>,
+    pub jobs: usize,
+    pub quiet: bool,
+    pub config: ConfigLayers,
+}
+
+/// An option and the values it takes in the sweep
+pub struct Axis {
+    name: String,
+    values: Vec<String>,
+}
+
+/// One configuration of the grid
+struct Cell {
+    settings: Vec<(String, String)>,
+    file_name: String,
+    config: Config,
+}
+
+pub fn subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("sweep")
+        .about(
+            "Convert an image with every combination of the given option values, \
+            and lay the results out side by side in an HTML page",
+        )
+        .arg(
+            Arg::with_name("input")
+                .long("input")
+                .short("i")
+                .takes_value(true)
+                .help("Path to input raster image")
+                .required(true),
+        )
+        .arg(
+            Arg::with_name("output")
+                .long("output")
+                .short("o")
+                .takes_value(true)
+                .help("Directory to write the vector graphics and index.html into")
+                .required(true),
+        )
+        .arg(
+            Arg::with_name("vary")
+                .long("vary")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .required(true)
+                .help(
+                    "An option or config field and its values, either a list like \
+                `gradient_step=8,16,32` or an inclusive range like `color_precision=4..8`, \
+                optionally with a step like `layer_difference=8..64:8`. Repeat to sweep a grid.",
+                ),
+        )
+        .arg(Arg::with_name("force").long("force").help(
+            "Convert grids of more than 256 variations, which are otherwise rejected as a likely \
+            mistake",
+        ))
+        .arg(
+            Arg::with_name("jobs")
+                .long("jobs")
+                .short("j")
+                .takes_value(true)
+                .help("Maximum number of conversions to run in parallel (default: number of CPUs)"),
+        )
+        .arg(
+            Arg::with_name("quiet")
+                .long("quiet")
+                .short("q")
+                .help("Do not print progress messages, only errors"),
+        )
+        .args(&crate::config_args())
+}
+
+pub fn args_from_matches(matches: &ArgMatches) -> Result<SweepArgs, CliError> {
+    let force = matches.is_present("force");
+    // Required, so clap guarantees they are present
+    let axes = matches
+        .values_of("vary")
+        .unwrap()
+        .map(|spec| parse_axis(spec, force))
+        .collect::<Result<Vec<_>, _>>()?;
+    check_variations(&axes, force)?;
+
+    Ok(SweepArgs {
+        input_path: PathBuf::from(matches.value_of("input").unwrap()),
+        output_dir: PathBuf::from(matches.value_of("output").unwrap()),
+        axes,
+        jobs: crate::parse_jobs(matches)?,
+        quiet: matches.is_present("quiet"),
+        config: ConfigLayers::from_matches(matches)?,
+    })
+}
+
+/// The number of cells of the grid of `axes`, an error if more than `MAX_VARIATIONS` unless
+/// `force`
+fn check_variations(axes: &[Axis], force: bool) -> Result<usize, CliError> {
+    let variations = axes.iter().fold(1usize, |count, axis| {
+        count.saturating_mul(axis.values.len())
+    });
+    if variations > MAX_VARIATIONS && !force {
+        return Err(too_many_variations(variations));
+    }
+    Ok(variations)
+}
+
+fn too_many_variations(count: usize) -> CliError {
+    CliError::InvalidArguments(format!(
+        "--vary asks for {} variations, more than {}; pass --force to convert them anyway",
+        count, MAX_VARIATIONS
+    ))
+}
+
+/// Parse `name=a,b,c`, `name=from..to` or `name=from..to:step`. Unless `force`, ranges are
+/// limited to `MAX_VARIATIONS` values.
+fn parse_axis(spec: &str, force: bool) -> Result<Axis, CliError> {
+    let invalid = || {
+        CliError::InvalidArguments(format!(
+            "--vary expects `option=a,b,c`, `option=from..to` or `option=from..to:step`: {}",
+            spec
+        ))
+    };
+    let (name, values) = spec.split_once('=').ok_or_else(invalid)?;
+    let name = name.trim().trim_start_matches("--").to_owned();
+
+    let values: Vec<String> = if let Some((from, to)) = values.split_once("..") {
+        let (to, step) = match to.split_once(':') {
+            Some((to, step)) => (to, step.trim().parse().map_err(|_| invalid())?),
+            None => (to, 1),
+        };
+        let from: i32 = from.trim().parse().map_err(|_| invalid())?;
+        let to: i32 = to.trim().parse().map_err(|_| invalid())?;
+        if step == 0 || from > to {
+            return Err(invalid());
+        }
+        let count = ((to as i64 - from as i64) / step as i64 + 1) as usize;
+        if count > MAX_VARIATIONS && !force {
+            return Err(too_many_variations(count));
+        }
+        (from..=to)
+            .step_by(step)
+            .map(|value| value.to_string())
+            .collect()
+    } else {
+        values
+            .split(',')
+            .map(|value| value.trim().to_owned())
+            .filter(|value| !value.is_empty())
+            .collect()
+    };
+    if values.is_empty() {
+        return Err(invalid());
+    }
+    Ok(Axis { name, values })
+}
+
+/// Every combination of the axes' values, applied on top of `base`
+fn grid(base: &Config, axes: &[Axis]) -> Result<Vec<Cell>, CliError> {
+    let mut cells = vec![Cell {
+        settings: vec![],
+        file_name: String::new(),
+        config: base.clone(),
+    }];
+    for axis in axes {
+        let mut next = Vec::with_capacity(cells.len() * axis.values.len());
+        for cell in cells.iter() {
+            for value in axis.values.iter() {
+                let mut config = cell.config.clone();
+                crate::set_option(&mut config, &axis.name, value)?;
+                let mut settings = cell.settings.clone();
+                settings.push((axis.name.clone(), value.clone()));
+                next.push(Cell {
+                    settings,
+                    file_name: String::new(),
+                    config,
+                });
+            }
+        }
+        cells = next;
+    }
+
+    for cell in cells.iter_mut() {
+        crate::validate_config(&cell.config)?;
+        let name: Vec<String> = cell
+            .settings
+            .iter()
+            .map(|(name, value)| format!("{}-{}", name, value))
+            .collect();
+        cell.file_name = sanitize(&name.join("_")) + ".svg";
+    }
+    Ok(cells)
+}
+
+fn sanitize(name: &str) -> String {
+    name.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || "-_.".contains(c) {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+/// Convert the input with every cell of the grid, then write the contact sheet
+pub fn run(args: SweepArgs) -> Result<(), CliError> {
+    let img = vtracer::read_image(&args.input_path).map_err(CliError::UnreadableInput)?;
+    let cells = grid(&args.config.for_image(&img)?, &args.axes)?;
+
+    fs::create_dir_all(&args.output_dir).map_err(|err| {
+        CliError::UnwritableOutput(format!("Cannot create output directory: {}", err))
+    })?;
+    vtracer::write_png(&img, &args.output_dir.join("source.png"))
+        .map_err(CliError::UnwritableOutput)?;
+
+    let next = AtomicUsize::new(0);
+    let results: Mutex<Vec<Option<Result<ConversionStats, String>>>> =
+        Mutex::new(cells.iter().map(|_| None).collect());
+    let num_threads = args.jobs.clamp(1, cells.len());
+
+    thread::scope(|scope| {
+        for _ in 0..num_threads {
+            scope.spawn(|| loop {
+                let index = next.fetch_add(1, Ordering::Relaxed);
+                let Some(cell) = cells.get(index) else {
+                    break;
+                };
+                let output_path = args.output_dir.join(&cell.file_name);
+                let result = vtracer::convert_with_stats(img.clone(), cell.config.clone())
+                    .and_then(|(svg, stats)| vtracer::write_svg(svg, &output_path).map(|()| stats));
+                match &result {
+                    Ok(_) if !args.quiet => println!("[converted] {}", output_path.display()),
+                    Ok(_) => (),
+                    Err(msg) => eprintln!("[failed]    {}: {}", output_path.display(), msg),
+                }
+                results.lock().unwrap()[index] = Some(result);
+            });
+        }
+    });
+
+    let results: Vec<Result<ConversionStats, String>> = results
+        .into_inner()
+        .unwrap()
+        .into_iter()
+        .map(|result| result.expect("every cell was converted"))
+        .collect();
+
+    let index_path = args.output_dir.join("index.html");
+    let columns = args.axes.last().map_or(1, |axis| axis.values.len());
+    fs::write(
+        &index_path,
+        contact_sheet(&args.input_path, &cells, &results, columns),
+    )
+    .map_err(|err| {
+        CliError::UnwritableOutput(format!("Cannot write {}: {}", index_path.display(), err))
+    })?;
+    if !args.quiet {
+        println!(
+            "Wrote {} variations to {}",
+            cells.len(),
+            index_path.display()
+        );
+    }
+
+    let failed = results.iter().filter(|result| result.is_err()).count();
+    if failed > 0 {
+        return Err(CliError::ConversionFailed(format!(
+            "{} of {} variations failed",
+            failed,
+            cells.len()
+        )));
+    }
+    Ok(())
+}
+
+/// An HTML page showing the source and every variation in a grid, one row per value of all but
+/// the last axis, captioned with their settings, file size and path count
+fn contact_sheet(
+    input_path: &Path,
+    cells: &[Cell],
+    results: &[Result<ConversionStats, String>],
+    columns: usize,
+) -> String {
+    let title = input_path.file_name().map_or_else(
+        || input_path.display().to_string(),
+        |name| name.to_string_lossy().into_owned(),
+    );
+    let title = escape(&title);
+    let mut html = String::new();
+    let _ = write!(
+        html,
+        r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>Sweep of {title}</title>
+<style>
+body {{ font-family: sans-serif; margin: 16px; background: #f4f4f4; }}
+.sheet {{ display: grid; grid-template-columns: repeat({columns}, minmax(160px, 1fr)); gap: 12px; margin-bottom: 12px; }}
+figure {{ margin: 0; padding: 8px; background: #fff; border: 1px solid #ddd; }}
+figure img {{ display: block; width: 100%; height: auto; }}
+figcaption {{ margin-top: 6px; font-size: 13px; line-height: 1.4; }}
+.failed {{ color: #b00020; }}
+</style>
+</head>
+<body>
+<h1>Sweep of {title}</h1>
+<div class="sheet">
+<figure><img src="source.png" alt="source"><figcaption><b>Source</b></figcaption></figure>
+</div>
+<div class="sheet">
+"#
+    );
+
+    for (cell, result) in cells.iter().zip(results) {
+        let settings: Vec<String> = cell
+            .settings
+            .iter()
+            .map(|(name, value)| format!("{} {}", escape(name), escape(value)))
+            .collect();
+        let settings = settings.join(" &middot; ");
+        let _ = match result {
+            Ok(stats) => writeln!(
+                html,
+                r#"<figure><a href="{file}"><img src="{file}" alt="{settings}" loading="lazy"></a><figcaption><b>{settings}</b><br>{size} &middot; {paths} paths</figcaption></figure>"#,
+                file = escape(&cell.file_name),
+                settings = settings,
+                size = format_size(stats.output_bytes),
+                paths = stats.paths_emitted,
+            ),
+            Err(msg) => writeln!(
+                html,
+                r#"<figure><figcaption><b>{}</b><br><span class="failed">{}</span></figcaption></figure>"#,
+                settings,
+                escape(msg),
+            ),
+        };
+    }
+
+    html.push_str("</div>\n</body>\n</html>\n");
+    html
+}
+
+fn format_size(bytes: usize) -> String {
+    if bytes < 1024 {
+        format!("{} B", bytes)
+    } else if bytes < 1024 * 1024 {
+        format!("{:.1} KiB", bytes as f64 / 1024.0)
+    } else {
+        format!("{:.1} MiB", bytes as f64 / (1024.0 * 1024.0))
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn values(spec: &str) -> Vec<String> {
+        parse_axis(spec, false).unwrap().values
+    }
+
+    #[test]
+    fn lists_and_ranges() {
+        assert_eq!(values("gradient_step=8, 16,32"), ["8", "16", "32"]);
+        assert_eq!(values("--mode=spline,polygon"), ["spline", "polygon"]);
+        assert_eq!(values("color_precision=4..8"), ["4", "5", "6", "7", "8"]);
+        assert_eq!(values("layer_difference=8..64:16"), ["8", "24", "40", "56"]);
+        assert_eq!(values("corner_threshold=-10..10:10"), ["-10", "0", "10"]);
+        assert_eq!(
+            parse_axis("--snap_angle=1..2", false).unwrap().name,
+            "snap_angle"
+        );
+    }
+
+    #[test]
+    fn malformed_axes() {
+        for spec in [
+            "gradient_step",
+            "gradient_step=",
+            "gradient_step=,",
+            "gradient_step=8..",
+            "gradient_step=8..4",
+            "gradient_step=8..64:0",
+            "gradient_step=8..64:x",
+        ] {
+            assert!(parse_axis(spec, false).is_err(), "{}", spec);
+        }
+    }
+
+    #[test]
+    fn grid_combines_every_value() {
+        let axes = [
+            parse_axis("color_precision=4..8", false).unwrap(),
+            parse_axis("layer_difference=8..64:8", false).unwrap(),
+        ];
+        assert_eq!(check_variations(&axes, false).unwrap(), 40);
+        let cells = grid(&Config::default(), &axes).unwrap();
+        assert_eq!(cells.len(), 40);
+        assert_eq!(cells[9].config.color_precision, 5);
+        assert_eq!(cells[9].config.layer_difference, 16);
+        assert_eq!(
+            cells[9].file_name,
+            "color_precision-5_layer_difference-16.svg"
+        );
+
+        // CLI names work as well as field names
+        let axes = [parse_axis("gradient_step=8,16", false).unwrap()];
+        let cells = grid(&Config::default(), &axes).unwrap();
+        assert_eq!(cells[1].config.layer_difference, 16);
+
+        let axes = [parse_axis("no_such_option=1,2", false).unwrap()];
+        assert!(grid(&Config::default(), &axes).is_err());
+    }
+
+    #[test]
+    fn grid_size_is_capped() {
+        let axes = [
+            parse_axis("color_precision=1..8", false).unwrap(),
+            parse_axis("layer_difference=0..32", false).unwrap(),
+        ];
+        let err = check_variations(&axes, false).unwrap_err();
+        assert_eq!(err.exit_code(), 2);
+        assert_eq!(check_variations(&axes, true).unwrap(), 8 * 33);
+
+        assert_eq!(values("layer_difference=0..255").len(), MAX_VARIATIONS);
+        assert!(parse_axis("layer_difference=0..256", false).is_err());
+        assert_eq!(
+            parse_axis("layer_difference=0..256", true)
+                .unwrap()
+                .values
+                .len(),
+            257
+        );
+        assert_eq!(values("layer_difference=0..255:4").len(), 64);
+    }
+}
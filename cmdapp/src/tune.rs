@@ -0,0 +1,210 @@
+use std::collections::HashSet;
+
+use visioncortex::ColorImage;
+
+use super::config::{ColorMode, Config};
+use super::fidelity::{convert_and_compare, Fidelity};
+use super::stats::ConversionStats;
+use super::svg::SvgFile;
+
+const COLOR_PRECISIONS: [i32; 5] = [4, 5, 6, 7, 8];
+const LAYER_DIFFERENCES: [i32; 8] = [4, 8, 16, 24, 32, 48, 64, 96];
+const FILTER_SPECKLES: [i32; 7] = [0, 1, 2, 4, 8, 12, 16];
+/// Upper bound on the number of sweeps over all parameters
+const MAX_ROUNDS: usize = 4;
+
+/// What [`tune`] optimizes for
+#[derive(Debug, Clone, Copy)]
+pub enum TuneGoal {
+    /// Smallest output whose PSNR is at least this many dB
+    MinPsnr(f64),
+    /// Highest PSNR with at most this many paths
+    MaxPaths(usize),
+    /// Highest PSNR with an output of at most this many bytes
+    MaxBytes(usize),
+}
+
+/// The best conversion found by [`tune`]
+pub struct TuneResult {
+    pub config: Config,
+    pub svg: SvgFile,
+    pub stats: ConversionStats,
+    pub fidelity: Fidelity,
+    /// Number of distinct configs converted during the search
+    pub evaluations: usize,
+}
+
+/// The figures a candidate config is judged by
+#[derive(Clone, Copy)]
+struct Score {
+    psnr: f64,
+    paths: usize,
+    bytes: usize,
+}
+
+impl TuneGoal {
+    /// How far `score` is from meeting the goal, 0 if it does
+    fn violation(&self, score: &Score) -> f64 {
+        match *self {
+            Self::MinPsnr(psnr) => (psnr - score.psnr).max(0.0),
+            Self::MaxPaths(paths) => score.paths.saturating_sub(paths) as f64,
+            Self::MaxBytes(bytes) => score.bytes.saturating_sub(bytes) as f64,
+        }
+    }
+
+    /// Whether `a` is better than `b`: meeting the goal comes first, then the objective
+    fn prefers(&self, a: &Score, b: &Score) -> bool {
+        let (violation_a, violation_b) = (self.violation(a), self.violation(b));
+        if violation_a != violation_b {
+            return violation_a < violation_b;
+        }
+        match self {
+            Self::MinPsnr(_) => (a.bytes, -a.psnr) < (b.bytes, -b.psnr),
+            Self::MaxPaths(_) | Self::MaxBytes(_) => (-a.psnr, a.bytes) < (-b.psnr, b.bytes),
+        }
+    }
+
+    fn describe(&self) -> String {
+        match self {
+            Self::MinPsnr(psnr) => format!("a PSNR of at least {} dB", psnr),
+            Self::MaxPaths(paths) => format!("at most {} paths", paths),
+            Self::MaxBytes(bytes) => format!("at most {} bytes", bytes),
+        }
+    }
+}
+
+/// Search `color_precision`, `layer_difference` and `filter_speckle` for the config that best
+/// meets `goal`, starting from `base` (typically [`Config::from_preset`]) and keeping its
+/// other fields. Each parameter is swept in turn while holding the others, until none improves.
+/// Fails if no config that was tried meets the goal.
+pub fn tune(img: &ColorImage, base: Config, goal: TuneGoal) -> Result<TuneResult, String> {
+    let mut tuner = Tuner {
+        img,
+        goal,
+        tried: HashSet::new(),
+        best: None,
+    };
+    let mut current = base;
+    tuner.evaluate(&current)?;
+
+    for _ in 0..MAX_ROUNDS {
+        let mut improved = false;
+        for parameter in parameters(&current.color_mode) {
+            for &value in parameter.values {
+                let mut candidate = current.clone();
+                (parameter.set)(&mut candidate, value);
+                if tuner.evaluate(&candidate)? {
+                    current = candidate;
+                    improved = true;
+                }
+            }
+        }
+        if !improved {
+            break;
+        }
+    }
+
+    let evaluations = tuner.tried.len();
+    let Candidate {
+        config,
+        svg,
+        stats,
+        fidelity,
+        score,
+    } = tuner.best.expect("base config was evaluated");
+    if goal.violation(&score) > 0.0 {
+        return Err(format!(
+            "No config reaches {} (closest: PSNR {:.2} dB, {} paths, {} bytes)",
+            goal.describe(),
+            score.psnr,
+            score.paths,
+            score.bytes
+        ));
+    }
+    Ok(TuneResult {
+        config,
+        svg,
+        stats,
+        fidelity,
+        evaluations,
+    })
+}
+
+struct Parameter {
+    values: &'static [i32],
+    set: fn(&mut Config, i32),
+}
+
+/// The parameters that affect the output in `color_mode`, with the values to try for each
+fn parameters(color_mode: &ColorMode) -> Vec<Parameter> {
+    let filter_speckle = Parameter {
+        values: &FILTER_SPECKLES,
+        set: |config, value| config.filter_speckle = value as usize,
+    };
+    match color_mode {
+        ColorMode::Binary => vec![filter_speckle],
+        ColorMode::Color => vec![
+            Parameter {
+                values: &COLOR_PRECISIONS,
+                set: |config, value| config.color_precision = value,
+            },
+            Parameter {
+                values: &LAYER_DIFFERENCES,
+                set: |config, value| config.layer_difference = value,
+            },
+            filter_speckle,
+        ],
+    }
+}
+
+struct Tuner<'a> {
+    img: &'a ColorImage,
+    goal: TuneGoal,
+    /// The tuned fields of every config converted so far
+    tried: HashSet<(i32, i32, usize)>,
+    best: Option<Candidate>,
+}
+
+struct Candidate {
+    config: Config,
+    svg: SvgFile,
+    stats: ConversionStats,
+    fidelity: Fidelity,
+    score: Score,
+}
+
+impl Tuner<'_> {
+    /// Convert with `config` unless it was tried before; returns whether it is the new best
+    fn evaluate(&mut self, config: &Config) -> Result<bool, String> {
+        let key = (
+            config.color_precision,
+            config.layer_difference,
+            config.filter_speckle,
+        );
+        if !self.tried.insert(key) {
+            return Ok(false);
+        }
+
+        let (svg, stats, fidelity) = convert_and_compare(self.img, config.clone())?;
+        let score = Score {
+            psnr: fidelity.psnr,
+            paths: stats.paths_emitted,
+            bytes: stats.output_bytes,
+        };
+
+        let improved = match &self.best {
+            Some(best) => self.goal.prefers(&score, &best.score),
+            None => true,
+        };
+        if improved {
+            self.best = Some(Candidate {
+                config: config.clone(),
+                svg,
+                stats,
+                fidelity,
+                score,
+            });
+        }
+        Ok(improved)
+    }
+}
@@ -0,0 +1,137 @@
+use std::fs;
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+use crate::error::CliError;
+use crate::ConfigLayers;
+use clap::{App, Arg, ArgGroup, ArgMatches, SubCommand};
+use vtracer::TuneGoal;
+
+pub struct TuneArgs {
+    pub input_path: PathBuf,
+    pub output_path: PathBuf,
+    pub config_path: Option<PathBuf>,
+    pub goal: TuneGoal,
+    pub config: ConfigLayers,
+}
+
+pub fn subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("tune")
+        .about(
+            "Search color_precision, gradient_step and filter_speckle for the config \
+            that best meets a size or error budget, starting from the given options",
+        )
+        .arg(
+            Arg::with_name("input")
+                .long("input")
+                .short("i")
+                .takes_value(true)
+                .help("Path to input raster image")
+                .required(true),
+        )
+        .arg(
+            Arg::with_name("output")
+                .long("output")
+                .short("o")
+                .takes_value(true)
+                .help(
+                    "Path to write the vector graphics of the chosen config to, or `-` for stdout",
+                )
+                .required(true),
+        )
+        .arg(
+            Arg::with_name("min_psnr")
+                .long("min_psnr")
+                .takes_value(true)
+                .help("Make the smallest output whose PSNR is at least this many dB"),
+        )
+        .arg(
+            Arg::with_name("max_paths")
+                .long("max_paths")
+                .takes_value(true)
+                .help("Make the most faithful output with at most this many paths"),
+        )
+        .arg(
+            Arg::with_name("max_bytes")
+                .long("max_bytes")
+                .takes_value(true)
+                .help("Make the most faithful output of at most this many bytes"),
+        )
+        .group(
+            ArgGroup::with_name("goal")
+                .args(&["min_psnr", "max_paths", "max_bytes"])
+                .required(true),
+        )
+        .arg(
+            Arg::with_name("save_config")
+                .long("save_config")
+                .takes_value(true)
+                .help("Path to write the chosen config to, as a TOML file for `--config`"),
+        )
+        .args(&crate::config_args())
+}
+
+pub fn args_from_matches(matches: &ArgMatches) -> Result<TuneArgs, CliError> {
+    let goal = if let Some(psnr) = crate::parse_option(matches, "min_psnr", "numeric")? {
+        TuneGoal::MinPsnr(psnr)
+    } else if let Some(paths) = crate::parse_option(matches, "max_paths", "an integer")? {
+        TuneGoal::MaxPaths(paths)
+    } else if let Some(bytes) = crate::parse_option(matches, "max_bytes", "an integer")? {
+        TuneGoal::MaxBytes(bytes)
+    } else {
+        unreachable!("the goal group is required")
+    };
+
+    Ok(TuneArgs {
+        // Both are required, so clap guarantees they are present
+        input_path: PathBuf::from(matches.value_of("input").unwrap()),
+        output_path: PathBuf::from(matches.value_of("output").unwrap()),
+        config_path: matches.value_of("save_config").map(PathBuf::from),
+        goal,
+        config: ConfigLayers::from_matches(matches)?,
+    })
+}
+
+/// Tune the config for the input, write its output and print the chosen config
+pub fn run(args: TuneArgs) -> Result<(), CliError> {
+    let img = vtracer::read_image(&args.input_path).map_err(CliError::UnreadableInput)?;
+    let config = args.config.for_image(&img)?;
+    let result = vtracer::tune(&img, config, args.goal).map_err(CliError::ConversionFailed)?;
+
+    let config = toml::to_string(&result.config)
+        .map_err(|err| CliError::ConversionFailed(format!("Cannot serialize config: {}", err)))?;
+    let summary = format!(
+        "# Chosen after {} trials: PSNR {:.2} dB, SSIM {:.4}, {} paths, {} bytes\n{}",
+        result.evaluations,
+        result.fidelity.psnr,
+        result.fidelity.ssim,
+        result.stats.paths_emitted,
+        result.stats.output_bytes,
+        config
+    );
+
+    if let Some(config_path) = &args.config_path {
+        fs::write(config_path, &summary).map_err(|err| {
+            CliError::UnwritableOutput(format!(
+                "Cannot write config file {}: {}",
+                config_path.display(),
+                err
+            ))
+        })?;
+    }
+
+    if crate::is_stdio(&args.output_path) {
+        let mut stdout = io::stdout().lock();
+        write!(stdout, "{}", result.svg)
+            .and_then(|()| stdout.flush())
+            .map_err(|err| {
+                CliError::UnwritableOutput(format!("Cannot write to stdout: {}", err))
+            })?;
+        // keep stdout for the SVG
+        eprint!("{}", summary);
+    } else {
+        vtracer::write_svg(result.svg, &args.output_path).map_err(CliError::UnwritableOutput)?;
+        print!("{}", summary);
+    }
+    Ok(())
+}
@@ -13,6 +13,8 @@ def convert_image_to_svg_py(image_path: str,
                             max_iterations: Optional[int] = None,   # default: 10
                             splice_threshold: Optional[int] = None, # default: 45
                             path_precision: Optional[int] = None,   # default: 8
+                            preset: Optional[str] = None,           # "bw", "poster", "photo" or "auto"
+                            pixel_art: Optional[bool] = None,       # default: False
                         ) -> None:
     ...
 
@@ -29,6 +31,8 @@ def convert_raw_image_to_svg(img_bytes: bytes,
                             max_iterations: Optional[int] = None,   # default: 10
                             splice_threshold: Optional[int] = None, # default: 45
                             path_precision: Optional[int] = None,   # default: 8
+                            preset: Optional[str] = None,           # "bw", "poster", "photo" or "auto"
+                            pixel_art: Optional[bool] = None,       # default: False
                         ) -> str:
     ...
 
@@ -45,5 +49,7 @@ def convert_pixels_to_svg(rgba_pixels: list[tuple[int, int, int, int]],
                             max_iterations: Optional[int] = None,   # default: 10
                             splice_threshold: Optional[int] = None, # default: 45
                             path_precision: Optional[int] = None,   # default: 8
+                            preset: Optional[str] = None,           # "bw", "poster", "photo" or "auto"
+                            pixel_art: Optional[bool] = None,       # default: False
                         ) -> str:
     ...
\ No newline at end of file
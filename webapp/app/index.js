@@ -417,28 +417,32 @@ function restart() {
         'mode': mode,
         'clustering_mode': clustering_mode,
         'hierarchical': clustering_hierarchical,
-        'corner_threshold': deg2rad(globalcorner),
+        'corner_threshold': globalcorner,
         'length_threshold': globallength,
         'max_iterations': 10,
-        'splice_threshold': deg2rad(globalsplice),
-        'filter_speckle': globalfilterspeckle*globalfilterspeckle,
-        'color_precision': 8-globalcolorprecision,
+        'splice_threshold': globalsplice,
+        'filter_speckle': globalfilterspeckle,
+        'color_precision': globalcolorprecision,
         'layer_difference': globallayerdifference,
         'path_precision': globalpathprecision,
     });
     if (runner) {
         runner.stop();
     }
-    runner = new ConverterRunner(converter_params);
+    try {
+        runner = new ConverterRunner(converter_params);
+    } catch (err) {
+        // the converter rejects parameters it cannot parse or that are out of range
+        runner = null;
+        progressregion.style.display = 'none';
+        console.error(err);
+        return;
+    }
     progress.value = 0;
     progressregion.style.display = 'block';
     runner.run();
 }
 
-function deg2rad(deg) {
-    return deg/180*3.141592654;
-}
-
 class ConverterRunner {
     constructor (converter_params) {
         this.converter =
@@ -1,10 +1,11 @@
 use wasm_bindgen::prelude::*;
-use visioncortex::{clusters::Clusters, Color, ColorName, PathSimplifyMode};
+use visioncortex::{clusters::Clusters, Color, ColorName};
 
 use crate::{canvas::*};
 use crate::svg::*;
 
 use serde::Deserialize;
+use vtracer::{path_simplify_mode_from_str, ColorMode, Config};
 use super::util;
 
 #[derive(Debug, Deserialize)]
@@ -12,44 +13,61 @@ pub struct BinaryImageConverterParams {
     pub canvas_id: String,
     pub svg_id: String,
     pub mode: String,
-    pub corner_threshold: f64,
+    pub corner_threshold: i32,
     pub length_threshold: f64,
     pub max_iterations: usize,
-    pub splice_threshold: f64,
+    pub splice_threshold: i32,
     pub filter_speckle: usize,
     pub path_precision: u32,
 }
 
+impl BinaryImageConverterParams {
+    pub fn to_config(&self) -> Result<Config, String> {
+        Ok(Config {
+            color_mode: ColorMode::Binary,
+            filter_speckle: self.filter_speckle,
+            mode: path_simplify_mode_from_str(&self.mode)?,
+            corner_threshold: self.corner_threshold,
+            length_threshold: self.length_threshold,
+            max_iterations: self.max_iterations,
+            splice_threshold: self.splice_threshold,
+            path_precision: Some(self.path_precision),
+            ..Default::default()
+        })
+    }
+}
+
 #[wasm_bindgen]
 pub struct BinaryImageConverter {
     canvas: Canvas,
     svg: Svg,
     clusters: Clusters,
     counter: usize,
-    mode: PathSimplifyMode,
-    params: BinaryImageConverterParams,
+    config: Config,
 }
 
 impl BinaryImageConverter {
-    pub fn new(params: BinaryImageConverterParams) -> Self {
+    pub fn new(params: BinaryImageConverterParams) -> Result<Self, String> {
+        let config = params.to_config()?;
+        util::validate(&config)?;
         let canvas = Canvas::new_from_id(&params.canvas_id);
         let svg = Svg::new_from_id(&params.svg_id);
-        Self {
+        Ok(Self {
             canvas,
             svg,
             clusters: Clusters::default(),
             counter: 0,
-            mode: util::path_simplify_mode(&params.mode),
-            params,
-        }
+            config,
+        })
     }
 }
 
 #[wasm_bindgen]
 impl BinaryImageConverter {
-    pub fn new_with_string(params: String) -> Self {
-        let params: BinaryImageConverterParams = serde_json::from_str(params.as_str()).unwrap();
-        Self::new(params)
+    pub fn new_with_string(params: String) -> Result<BinaryImageConverter, JsValue> {
+        let params: BinaryImageConverterParams = serde_json::from_str(params.as_str())
+            .map_err(|err| JsValue::from_str(&format!("Invalid parameters: {}", err)))?;
+        Self::new(params).map_err(|err| JsValue::from_str(&err))
     }
 
     pub fn init(&mut self) {
@@ -69,19 +87,19 @@ impl BinaryImageConverter {
         if self.counter < self.clusters.len() {
             self.canvas.log(&format!("tick {}", self.counter));
             let cluster = self.clusters.get_cluster(self.counter);
-            if cluster.size() >= self.params.filter_speckle {
+            if cluster.size() >= self.config.filter_speckle * self.config.filter_speckle {
                 let paths = cluster.to_compound_path(
-                    self.mode,
-                    self.params.corner_threshold,
-                    self.params.length_threshold,
-                    self.params.max_iterations,
-                    self.params.splice_threshold
+                    self.config.mode,
+                    util::deg2rad(self.config.corner_threshold),
+                    self.config.length_threshold,
+                    self.config.max_iterations,
+                    util::deg2rad(self.config.splice_threshold)
                 );
                 let color = Color::color(&ColorName::Black);
                 self.svg.prepend_path(
                     &paths,
                     &color,
-                    Some(self.params.path_precision),
+                    self.config.path_precision,
                 );
             }
             self.counter += 1;
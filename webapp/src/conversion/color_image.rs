@@ -1,39 +1,57 @@
+use std::str::FromStr;
 use wasm_bindgen::prelude::*;
-use visioncortex::{Color, ColorImage, PathSimplifyMode};
+use visioncortex::{Color, ColorImage};
 use visioncortex::color_clusters::{Clusters, Runner, RunnerConfig, HIERARCHICAL_MAX, IncrementalBuilder, KeyingAction};
 
 use crate::canvas::*;
 use crate::svg::*;
 
 use serde::Deserialize;
+use vtracer::{find_unused_color_in_image, path_simplify_mode_from_str, should_key_image, ColorMode, Config, Hierarchical};
 use super::util;
 
-const KEYING_THRESHOLD: f32 = 0.2;
-
 #[derive(Debug, Deserialize)]
 pub struct ColorImageConverterParams {
     pub canvas_id: String,
     pub svg_id: String,
     pub mode: String,
     pub hierarchical: String,
-    pub corner_threshold: f64,
+    pub corner_threshold: i32,
     pub length_threshold: f64,
     pub max_iterations: usize,
-    pub splice_threshold: f64,
+    pub splice_threshold: i32,
     pub filter_speckle: usize,
     pub color_precision: i32,
     pub layer_difference: i32,
     pub path_precision: u32,
 }
 
+impl ColorImageConverterParams {
+    pub fn to_config(&self) -> Result<Config, String> {
+        Ok(Config {
+            color_mode: ColorMode::Color,
+            hierarchical: Hierarchical::from_str(&self.hierarchical)?,
+            filter_speckle: self.filter_speckle,
+            color_precision: self.color_precision,
+            layer_difference: self.layer_difference,
+            mode: path_simplify_mode_from_str(&self.mode)?,
+            corner_threshold: self.corner_threshold,
+            length_threshold: self.length_threshold,
+            max_iterations: self.max_iterations,
+            splice_threshold: self.splice_threshold,
+            path_precision: Some(self.path_precision),
+            ..Default::default()
+        })
+    }
+}
+
 #[wasm_bindgen]
 pub struct ColorImageConverter {
     canvas: Canvas,
     svg: Svg,
     stage: Stage,
     counter: usize,
-    mode: PathSimplifyMode,
-    params: ColorImageConverterParams,
+    config: Config,
 }
 
 pub enum Stage {
@@ -44,26 +62,28 @@ pub enum Stage {
 }
 
 impl ColorImageConverter {
-    pub fn new(params: ColorImageConverterParams) -> Self {
+    pub fn new(params: ColorImageConverterParams) -> Result<Self, String> {
+        let config = params.to_config()?;
+        util::validate(&config)?;
         let canvas = Canvas::new_from_id(&params.canvas_id);
         let svg = Svg::new_from_id(&params.svg_id);
-        Self {
+        Ok(Self {
             canvas,
             svg,
             stage: Stage::New,
             counter: 0,
-            mode: util::path_simplify_mode(&params.mode),
-            params,
-        }
+            config,
+        })
     }
 }
 
 #[wasm_bindgen]
 impl ColorImageConverter {
 
-    pub fn new_with_string(params: String) -> Self {
-        let params: ColorImageConverterParams = serde_json::from_str(params.as_str()).unwrap();
-        Self::new(params)
+    pub fn new_with_string(params: String) -> Result<ColorImageConverter, JsValue> {
+        let params: ColorImageConverterParams = serde_json::from_str(params.as_str())
+            .map_err(|err| JsValue::from_str(&format!("Invalid parameters: {}", err)))?;
+        Self::new(params).map_err(|err| JsValue::from_str(&err))
     }
 
     pub fn init(&mut self) {
@@ -71,36 +91,20 @@ impl ColorImageConverter {
         let height = self.canvas.height() as u32;
         let mut image = self.canvas.get_image_data_as_color_image(0, 0, width, height);
 
-        let key_color = if Self::should_key_image(&image) {
-            if let Ok(key_color) = Self::find_unused_color_in_image(&image) {
-                for y in 0..height as usize {
-                    for x in 0..width as usize {
-                        if image.get_pixel(x, y).a == 0 {
-                            image.set_pixel(x, y, &key_color);
-                        }
-                    }
-                }
-                key_color
-            } else {
-                Color::default()
-            }
-        } else {
-            // The default color is all zeroes, which is treated by visioncortex as a special value meaning no keying will be applied.
-            Color::default()
-        };
+        let key_color = key_image(&mut image);
 
         let runner = Runner::new(RunnerConfig {
-            diagonal: self.params.layer_difference == 0,
+            diagonal: self.config.layer_difference == 0,
             hierarchical: HIERARCHICAL_MAX,
             batch_size: 25600,
-            good_min_area: self.params.filter_speckle,
+            good_min_area: self.config.filter_speckle * self.config.filter_speckle,
             good_max_area: (width * height) as usize,
-            is_same_color_a: self.params.color_precision,
+            is_same_color_a: 8 - self.config.color_precision,
             is_same_color_b: 1,
-            deepen_diff: self.params.layer_difference,
+            deepen_diff: self.config.layer_difference,
             hollow_neighbours: 1,
             key_color,
-            keying_action: if self.params.hierarchical == "cutout" {
+            keying_action: if matches!(self.config.hierarchical, Hierarchical::Cutout) {
                 KeyingAction::Keep
             } else {
                 KeyingAction::Discard
@@ -117,11 +121,11 @@ impl ColorImageConverter {
             Stage::Clustering(builder) => {
                 self.canvas.log("Clustering tick");
                 if builder.tick() {
-                    match self.params.hierarchical.as_str() {
-                        "stacked" => {
+                    match self.config.hierarchical {
+                        Hierarchical::Stacked => {
                             self.stage = Stage::Vectorize(builder.result());
                         },
-                        "cutout" => {
+                        Hierarchical::Cutout => {
                             let clusters = builder.result();
                             let view = clusters.view();
                             let image = view.to_color_image();
@@ -130,7 +134,7 @@ impl ColorImageConverter {
                                 hierarchical: 64,
                                 batch_size: 25600,
                                 good_min_area: 0,
-                                good_max_area: (image.width * image.height) as usize,
+                                good_max_area: image.width * image.height,
                                 is_same_color_a: 0,
                                 is_same_color_b: 1,
                                 deepen_diff: 0,
@@ -140,7 +144,6 @@ impl ColorImageConverter {
                             }, image);
                             self.stage = Stage::Reclustering(runner.start());
                         },
-                        _ => panic!("unknown hierarchical `{}`", self.params.hierarchical)
                     }
                 }
                 false
@@ -158,16 +161,16 @@ impl ColorImageConverter {
                     self.canvas.log("Vectorize tick");
                     let cluster = view.get_cluster(view.clusters_output[self.counter]);
                     let paths = cluster.to_compound_path(
-                        &view, false, self.mode,
-                        self.params.corner_threshold,
-                        self.params.length_threshold,
-                        self.params.max_iterations,
-                        self.params.splice_threshold
+                        &view, false, self.config.mode,
+                        util::deg2rad(self.config.corner_threshold),
+                        self.config.length_threshold,
+                        self.config.max_iterations,
+                        util::deg2rad(self.config.splice_threshold)
                     );
                     self.svg.prepend_path(
                         &paths,
                         &cluster.residue_color(),
-                        Some(self.params.path_precision),
+                        self.config.path_precision,
                     );
                     self.counter += 1;
                     false
@@ -195,57 +198,62 @@ impl ColorImageConverter {
             }
         }) as i32
     }
+}
 
-    fn color_exists_in_image(img: &ColorImage, color: Color) -> bool {
-        for y in 0..img.height {
-            for x in 0..img.width {
-                let pixel_color = img.get_pixel(x, y);
-                if pixel_color.r == color.r && pixel_color.g == color.g && pixel_color.b == color.b {
-                    return true
+/// Replace the transparent pixels of `image` by a color it does not use, if there are enough of
+/// them, and return that color. Otherwise returns the default color, which means no keying.
+fn key_image(image: &mut ColorImage) -> Color {
+    if should_key_image(image, 1) {
+        if let Ok(key_color) = find_unused_color_in_image(image) {
+            for y in 0..image.height {
+                for x in 0..image.width {
+                    if image.get_pixel(x, y).a == 0 {
+                        image.set_pixel(x, y, &key_color);
+                    }
                 }
             }
+            key_color
+        } else {
+            Color::default()
         }
-        false
+    } else {
+        // The default color is all zeroes, which is treated by visioncortex as a special value meaning no keying will be applied.
+        Color::default()
     }
-    
-    fn find_unused_color_in_image(img: &ColorImage) -> Result<Color, String> {
-        let special_colors = IntoIterator::into_iter([
-            Color::new(255, 0,   0),
-            Color::new(0,   255, 0),
-            Color::new(0,   0,   255),
-            Color::new(255, 255, 0),
-            Color::new(0,   255, 255),
-            Color::new(255, 0,   255),
-            Color::new(128, 128, 128),
-        ]);
-        for color in special_colors {
-            if !Self::color_exists_in_image(img, color) {
-                return Ok(color);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn key_matches_the_cmd_app() {
+        let mut image = ColorImage::new_w_h(16, 16);
+        for y in 0..16 {
+            for x in 0..16 {
+                // the left half red and green, the right half transparent
+                if x < 8 {
+                    let color = if y % 2 == 0 { Color::new(255, 0, 0) } else { Color::new(0, 255, 0) };
+                    image.set_pixel(x, y, &color);
+                }
             }
         }
-        Err(String::from("unable to find unused color in image to use as key"))
+        let expected = find_unused_color_in_image(&image).unwrap();
+        let key_color = key_image(&mut image);
+        assert_eq!(key_color, expected);
+        assert_eq!(key_color, Color::new(0, 0, 255));
+        assert_eq!(image.get_pixel(12, 3), key_color);
+        assert_eq!(image.get_pixel(3, 3), Color::new(0, 255, 0));
     }
-    
-    fn should_key_image(img: &ColorImage) -> bool {
-        if img.width == 0 || img.height == 0 {
-            return false;
-        }
-    
-        // Check for transparency at several scanlines
-        let threshold = ((img.width * 2) as f32 * KEYING_THRESHOLD) as usize;
-        let mut num_transparent_pixels = 0;
-        let y_positions = [0, img.height / 4, img.height / 2, 3 * img.height / 4, img.height - 1];
-        for y in y_positions {
-            for x in 0..img.width {
-                if img.get_pixel(x, y).a == 0 {
-                    num_transparent_pixels += 1;
-                }
-                if num_transparent_pixels >= threshold {
-                    return true;
-                }
+
+    #[test]
+    fn opaque_image_is_not_keyed() {
+        let mut image = ColorImage::new_w_h(8, 8);
+        for y in 0..8 {
+            for x in 0..8 {
+                image.set_pixel(x, y, &Color::new(10, 20, 30));
             }
         }
-    
-        false
+        assert_eq!(key_image(&mut image), Color::default());
     }
-}
\ No newline at end of file
+}
@@ -1,10 +1,13 @@
-use visioncortex::PathSimplifyMode;
+use vtracer::Config;
 
-pub fn path_simplify_mode(s: &str) -> PathSimplifyMode {
-	match s {
-		"polygon" => PathSimplifyMode::Polygon,
-		"spline" => PathSimplifyMode::Spline,
-		"none" => PathSimplifyMode::None,
-		_ => panic!("unknown PathSimplifyMode {}", s),
-	}
+pub fn deg2rad(deg: i32) -> f64 {
+	deg as f64 / 180.0 * std::f64::consts::PI
+}
+
+/// Reject out of range parameters, listing every offending field
+pub fn validate(config: &Config) -> Result<(), String> {
+	config.validate().map_err(|errors| {
+		let errors: Vec<String> = errors.iter().map(|error| error.to_string()).collect();
+		errors.join("; ")
+	})
 }
\ No newline at end of file